@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use assets;
+use error;
+use level;
+
+/// Gifs often give frames no delay, meaning "as fast as you can"; show them
+/// for at least this long, in seconds, so they don't spin every update.
+const MIN_FRAME_TIME: f64 = 0.02;
+
+/// How a clip plays through its frames.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PlayMode {
+  Loop, // start over after the last frame, forever
+  Once, // stop on the last frame
+  PingPong, // forwards then backwards, forever
+  Reverse, // from the last frame back to the first, then stop
+}
+
+/// Something that happened while an Animation played.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+  // A frame with an event attached was shown
+  Frame { clip: String, frame: usize, name: String },
+  // Once and Reverse clips finish when they stop; Loop and PingPong clips
+  // every time they come back around to where they started
+  Finished(String), // clip name
+}
+
+struct Clip<Texture> {
+  asset: Rc<assets::ImageAsset<Texture>>,
+  mode: PlayMode,
+  events: Vec<(usize, String)>, // frame, event name
+}
+
+/// Plays named clips of ImageAsset frames, one at a time.
+///
+/// Actors call update with the time passed and show texture; whatever the
+/// animation noticed along the way comes back as Events, so actors can chain
+/// clips together or play sounds on particular frames.
+pub struct Animation<Texture> {
+  clips: BTreeMap<String, Clip<Texture>>,
+  clip: String, // the clip playing, or stopped on
+  frame: usize,
+  next_frame: f64, // seconds until the next frame
+  forward: bool, // which way the clip is going through its frames
+  playing: bool,
+  speed: f64, // 1 is as fast as the frames say
+  pending: Vec<Event>, // for frames shown outside of update
+}
+
+impl<Texture> Animation<Texture> {
+  /// An animation that starts out playing the clip called name.
+  pub fn new(
+    name: &str,
+    asset: Rc<assets::ImageAsset<Texture>>,
+    mode: PlayMode,
+  ) -> error::Result<Animation<Texture>> {
+    let mut animation = Animation {
+      clips: BTreeMap::new(),
+      clip: String::from(""),
+      frame: 0,
+      next_frame: 0.0,
+      forward: true,
+      playing: false,
+      speed: 1.0,
+      pending: Vec::new(),
+    };
+    animation.add_clip(name, asset, mode)?;
+    animation.play(name)?;
+    Ok(animation)
+  }
+
+  pub fn add_clip(
+    &mut self,
+    name: &str,
+    asset: Rc<assets::ImageAsset<Texture>>,
+    mode: PlayMode,
+  ) -> error::Result<()> {
+    if asset.frames.is_empty() {
+      return Err(error::Error::from(format!("Clip {} has no frames", name)));
+    }
+    self.clips.insert(String::from(name), Clip {
+      asset,
+      mode,
+      events: Vec::new(),
+    });
+    Ok(())
+  }
+
+  /// Report an Event::Frame called event whenever frame of clip is shown.
+  pub fn add_event(&mut self, clip: &str, frame: usize, event: &str) -> error::Result<()> {
+    match self.clips.get_mut(clip) {
+      Some(clip) => {
+        clip.events.push((frame, String::from(event)));
+        Ok(())
+      },
+      None => Err(error::Error::from(format!("No animation clip named {}", clip))),
+    }
+  }
+
+  /// Play the clip called name from the start, unless it's already playing.
+  pub fn play(&mut self, name: &str) -> error::Result<()> {
+    if self.playing && self.clip == name {
+      return Ok(());
+    }
+    let (mode, last) = match self.clips.get(name) {
+      Some(clip) => (clip.mode, clip.asset.frames.len() - 1),
+      None => return Err(error::Error::from(format!("No animation clip named {}", name))),
+    };
+    self.clip = String::from(name);
+    self.playing = true;
+    self.forward = mode != PlayMode::Reverse;
+    let frame = if self.forward { 0 } else { last };
+    self.show(frame);
+    Ok(())
+  }
+
+  /// Stay on the current frame.
+  pub fn stop(&mut self) {
+    self.playing = false;
+  }
+
+  pub fn is_playing(&self) -> bool {
+    self.playing
+  }
+
+  /// Name of the current clip.
+  pub fn clip(&self) -> &str {
+    &self.clip
+  }
+
+  pub fn frame(&self) -> usize {
+    self.frame
+  }
+
+  /// Number of frames in the current clip.
+  pub fn frame_count(&self) -> usize {
+    self.current().asset.frames.len()
+  }
+
+  /// Jump to frame of the current clip, or its last frame if there aren't
+  /// that many. Doesn't report the frame's events, e.g. when restoring a save.
+  pub fn set_frame(&mut self, frame: usize) {
+    let last = self.current().asset.frames.len() - 1;
+    self.frame = if frame > last { last } else { frame };
+    self.next_frame = self.frame_time();
+  }
+
+  /// Seconds until the next frame.
+  pub fn next_frame(&self) -> f64 {
+    self.next_frame
+  }
+
+  /// Wait seconds before the next frame, e.g. when restoring a save.
+  pub fn set_next_frame(&mut self, seconds: f64) {
+    self.next_frame = seconds;
+  }
+
+  /// Scale how fast every clip plays.
+  pub fn set_speed(&mut self, speed: f64) {
+    self.speed = speed.max(0.0);
+  }
+
+  /// Texture of the frame being shown.
+  pub fn texture(&self) -> Rc<Texture> {
+    self.current().asset.frames[self.frame].texture.clone()
+  }
+
+  /// Advance by dt seconds, returning what happened on the way.
+  pub fn update(&mut self, dt: f64) -> Vec<Event> {
+    let mut events: Vec<Event> = self.pending.drain(..).collect();
+    if !self.playing {
+      return events;
+    }
+
+    self.next_frame -= dt * self.speed;
+    while self.playing && self.next_frame <= 0.0 {
+      let next_frame = self.next_frame;
+      self.advance(&mut events);
+      self.next_frame += next_frame;
+    }
+    events
+  }
+
+  fn current(&self) -> &Clip<Texture> {
+    &self.clips[&self.clip]
+  }
+
+  fn frame_time(&self) -> f64 {
+    self.current().asset.frames[self.frame].frame_time.max(MIN_FRAME_TIME)
+  }
+
+  // Move one frame along in the current clip's mode
+  fn advance(&mut self, events: &mut Vec<Event>) {
+    let (mode, last) = {
+      let clip = self.current();
+      (clip.mode, clip.asset.frames.len() - 1)
+    };
+
+    let at_end = if self.forward { self.frame >= last } else { self.frame == 0 };
+    if !at_end {
+      let frame = if self.forward { self.frame + 1 } else { self.frame - 1 };
+      self.show(frame);
+    } else {
+      match mode {
+        PlayMode::Loop => {
+          self.pending.push(Event::Finished(self.clip.clone()));
+          self.show(0);
+        },
+        PlayMode::Once | PlayMode::Reverse => {
+          self.playing = false;
+          self.pending.push(Event::Finished(self.clip.clone()));
+        },
+        PlayMode::PingPong => {
+          self.forward = !self.forward;
+          if self.forward {
+            self.pending.push(Event::Finished(self.clip.clone()));
+          }
+          let frame = if last == 0 {
+            0
+          } else if self.forward {
+            self.frame + 1
+          } else {
+            self.frame - 1
+          };
+          self.show(frame);
+        },
+      }
+    }
+    events.extend(self.pending.drain(..));
+  }
+
+  // Show frame of the current clip for its whole frame time, reporting its
+  // events
+  fn show(&mut self, frame: usize) {
+    self.frame = frame;
+    self.next_frame = self.frame_time();
+    let events: Vec<Event> = self.current().events.iter()
+      .filter(|&&(event_frame, _)| event_frame == frame)
+      .map(|&(_, ref name)| Event::Frame {
+        clip: self.clip.clone(),
+        frame,
+        name: name.clone(),
+      })
+      .collect();
+    self.pending.extend(events);
+  }
+}
+
+/// Add an Event::Frame named after the sound for each of frame_sounds.
+pub fn add_frame_sounds<Texture>(
+  animation: &mut Animation<Texture>,
+  frame_sounds: &[level::FrameSound],
+) -> error::Result<()> {
+  for frame_sound in frame_sounds.iter() {
+    animation.add_event(&frame_sound.clip, frame_sound.frame, &frame_sound.sound)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A clip of count frames, each shown for a quarter of a second
+  fn make_clip(count: usize) -> Rc<assets::ImageAsset<()>> {
+    let mut asset = assets::ImageAsset::new();
+    for _ in 0..count {
+      asset.add_frame(Rc::new(()), 0.25);
+    }
+    Rc::new(asset)
+  }
+
+  // The frame shown after each of steps quarter seconds
+  fn frames(animation: &mut Animation<()>, steps: usize) -> Vec<usize> {
+    (0..steps).map(|_| {
+      animation.update(0.25);
+      animation.frame()
+    }).collect()
+  }
+
+  #[test]
+  fn loop_clips_start_over() {
+    let mut animation = Animation::new("walk", make_clip(3), PlayMode::Loop).unwrap();
+    assert_eq!(frames(&mut animation, 5), vec![1, 2, 0, 1, 2]);
+    assert!(animation.is_playing());
+  }
+
+  #[test]
+  fn once_clips_stop_on_the_last_frame() {
+    let mut animation = Animation::new("clue", make_clip(3), PlayMode::Once).unwrap();
+    assert_eq!(frames(&mut animation, 2), vec![1, 2]);
+    assert_eq!(animation.update(0.25), vec![Event::Finished(String::from("clue"))]);
+    assert!(!animation.is_playing());
+    assert_eq!(frames(&mut animation, 2), vec![2, 2]);
+  }
+
+  #[test]
+  fn ping_pong_clips_turn_around_at_either_end() {
+    let mut animation = Animation::new("fly", make_clip(3), PlayMode::PingPong).unwrap();
+    assert_eq!(frames(&mut animation, 6), vec![1, 2, 1, 0, 1, 2]);
+    assert!(animation.is_playing());
+  }
+
+  #[test]
+  fn reverse_clips_play_backwards_and_stop() {
+    let mut animation = Animation::new("open", make_clip(3), PlayMode::Reverse).unwrap();
+    assert_eq!(animation.frame(), 2);
+    assert_eq!(frames(&mut animation, 3), vec![1, 0, 0]);
+    assert!(!animation.is_playing());
+  }
+
+  #[test]
+  fn speed_scales_the_frame_time() {
+    let mut animation = Animation::new("walk", make_clip(3), PlayMode::Loop).unwrap();
+    animation.set_speed(2.0);
+    assert_eq!(frames(&mut animation, 2), vec![2, 1]);
+  }
+
+  #[test]
+  fn frame_events_are_reported_when_their_frame_shows() {
+    let mut animation = Animation::new("walk", make_clip(3), PlayMode::Loop).unwrap();
+    animation.add_event("walk", 2, "footstep").unwrap();
+    assert!(animation.add_event("run", 0, "footstep").is_err());
+
+    let footstep = Event::Frame {
+      clip: String::from("walk"),
+      frame: 2,
+      name: String::from("footstep"),
+    };
+    assert!(animation.update(0.25).is_empty());
+    assert_eq!(animation.update(0.25), vec![footstep.clone()]);
+    assert_eq!(animation.update(0.25), vec![Event::Finished(String::from("walk"))]);
+    // A long update reports everything it skipped past
+    assert_eq!(animation.update(0.5), vec![footstep]);
+  }
+}
@@ -7,115 +7,377 @@ use std::rc::Rc;
 use error;
 use handler;
 
-pub struct Application<Window, EventHandler>
+/// Runs the window's event loop, handing events to application modes. Modes
+/// can be of any type that implements EventHandler, e.g. a game and its menus.
+pub struct Application<Window>
 where
   Window: piston_window::Window,
-  EventHandler: handler::EventHandler,
 {
   window: Rc<RefCell<piston_window::PistonWindow<Window>>>,
-  application_modes: BTreeMap<String, Box<EventHandler>>,
-  active_application_mode: Option<String>,
+  modes: ModeStack,
 }
 
-impl<Window, EventHandler> Application<Window, EventHandler>
+impl<Window> Application<Window>
 where
   Window: piston_window::Window,
-  EventHandler: handler::EventHandler,
 {
   /// Create a Application with default values.
   pub fn new(window: Rc<RefCell<piston_window::PistonWindow<Window>>>) -> Self {
     Application {
-      window: window,
-      application_modes: BTreeMap::new(),
-      active_application_mode: None,
+      window,
+      modes: ModeStack::default(),
     }
   }
 
   pub fn add_application_mode(
     &mut self,
     name: &str,
-    application_mode: Box<EventHandler>,
+    application_mode: Box<handler::EventHandler>,
   ) -> error::Result<()> {
-    match self.application_modes.insert(String::from(name), application_mode) {
-      Some(_) => Err(error::Error::from(
-          format!("Application mode {} already exists", name),
-          )),
-      None => Ok(()),
-    }
-  }
-
-  pub fn get_application_mode(&self, name: &str) -> Option<&Box<EventHandler>> {
-    self.application_modes.get(name)
+    self.modes.add(name, application_mode)
   }
 
   pub fn get_application_mode_mut(
     &mut self,
     name: &str,
-  ) -> Option<&mut Box<EventHandler>> {
-    self.application_modes.get_mut(name)
+  ) -> Option<&mut handler::EventHandler> {
+    self.modes.get_mut(name)
   }
 
   pub fn get_active_application_mode_mut(
     &mut self,
-  ) -> Option<&mut Box<EventHandler>> {
-    match self.active_application_mode.to_owned() {
-      Some(active_name) => self.get_application_mode_mut(&active_name),
-      None => None,
-    }
+  ) -> Option<&mut handler::EventHandler> {
+    self.modes.active_mut()
   }
 
+  /// Make name the only active mode.
   pub fn set_active_application_mode(
     &mut self,
     name: &str,
   ) -> error::Result<()> {
-    match self.active_application_mode {
-      Some(ref active_name) => {
-        if name == active_name {
-          return Err(error::Error::from(
-              format!("Application mode {} is already active", name),
-              ));
-        }
-      },
-      None => {},
-    }
-
-    match self.get_application_mode(name) {
-      Some(_) => {
-        self.active_application_mode = Some(String::from(name));
-        Ok(())
-      },
-      None => Err(error::Error::from(
-          format!("No application mode named {}", name),
-          )),
-    }
+    self.modes.switch(name)
   }
 
   pub fn next(&mut self) -> Option<piston_window::Event> {
     self.window.borrow_mut().next()
   }
 
-  /// Process events from window until termination.
-  /// Returns early if active_application_mode is None at any time.
+  /// Process events from window until termination or a mode asks to quit.
+  /// Returns early if there is no active application mode at any time.
   pub fn spin(&mut self) -> error::Result<()> {
-    if self.active_application_mode.is_none() {
+    use piston_window::{CloseEvent, RenderEvent}; // close_args, render_args
+
+    if self.modes.stack.is_empty() {
       return Err(error::Error::from(
           "Cannot spin with no active application mode",
           ));
     }
 
     while let Some(event) = self.next() {
-      match self.get_active_application_mode_mut() {
+      // Let modes under the active one draw first, so overlays go on top.
+      if event.render_args().is_some() {
+        let inactive = self.modes.stack[..self.modes.stack.len() - 1].to_vec();
+        for name in inactive.iter() {
+          if let Some(application_mode) = self.get_application_mode_mut(name) {
+            application_mode.on_event(&event)?;
+          }
+        }
+      }
+      // Every mode gets a chance to clean up when the window closes.
+      if event.close_args().is_some() {
+        let active_name = self.modes.stack.last().cloned();
+        for (name, application_mode) in self.modes.modes.iter_mut() {
+          if Some(name) != active_name.as_ref() {
+            application_mode.on_event(&event)?;
+          }
+        }
+      }
+
+      let transition = match self.get_active_application_mode_mut() {
         Some(application_mode) => {
           application_mode.on_event(&event)?;
+          application_mode.transition()
         },
         None => {
           return Err(error::Error::from(
               "Cannot handle event without active application mode",
               ));
         },
+      };
+
+      if let Some(transition) = transition {
+        if !self.modes.transition(transition)? {
+          break;
+        }
       }
     }
 
     Ok(())
   }
 }
+
+/// Application modes by name, and the stack of those that are active.
+pub struct ModeStack {
+  modes: BTreeMap<String, Box<handler::EventHandler>>,
+  // Active modes, bottom first; the last one is the active mode and gets all
+  // input. Modes under it are only rendered, so they show behind overlays.
+  stack: Vec<String>,
+}
+
+impl Default for ModeStack {
+  fn default() -> Self {
+    ModeStack {
+      modes: BTreeMap::new(),
+      stack: Vec::new(),
+    }
+  }
+}
+
+impl ModeStack {
+  pub fn add(
+    &mut self,
+    name: &str,
+    application_mode: Box<handler::EventHandler>,
+  ) -> error::Result<()> {
+    match self.modes.insert(String::from(name), application_mode) {
+      Some(_) => Err(error::Error::from(
+          format!("Application mode {} already exists", name),
+          )),
+      None => Ok(()),
+    }
+  }
+
+  pub fn get(&self, name: &str) -> Option<&handler::EventHandler> {
+    self.modes.get(name).map(|mode| &**mode)
+  }
+
+  pub fn get_mut(&mut self, name: &str) -> Option<&mut handler::EventHandler> {
+    match self.modes.get_mut(name) {
+      Some(mode) => Some(&mut **mode),
+      None => None,
+    }
+  }
+
+  pub fn active_mut(&mut self) -> Option<&mut handler::EventHandler> {
+    match self.stack.last().cloned() {
+      Some(active_name) => self.get_mut(&active_name),
+      None => None,
+    }
+  }
+
+  /// Make name the only active mode. Switching to the mode that already is
+  /// the only active mode leaves it be.
+  pub fn switch(&mut self, name: &str) -> error::Result<()> {
+    if self.stack.len() == 1 && self.stack[0] == name {
+      return Ok(());
+    }
+
+    self.check(name)?;
+    self.deactivate()?;
+    let left: Vec<String> = self.stack.drain(..)
+      .filter(|active_name| active_name != name)
+      .collect();
+    for left_name in left.iter() {
+      self.leave(left_name)?;
+    }
+    self.stack.push(String::from(name));
+    self.activate()
+  }
+
+  /// Make name the active mode, keeping the current modes under it.
+  pub fn push(&mut self, name: &str) -> error::Result<()> {
+    if self.stack.iter().any(|active_name| active_name == name) {
+      return Err(error::Error::from(
+          format!("Application mode {} is already active", name),
+          ));
+    }
+
+    self.check(name)?;
+    self.deactivate()?;
+    self.stack.push(String::from(name));
+    self.activate()
+  }
+
+  /// Go back to the mode under the active mode.
+  pub fn pop(&mut self) -> error::Result<()> {
+    if self.stack.len() < 2 {
+      return Err(error::Error::from(
+          "Cannot pop the last application mode",
+          ));
+    }
+
+    self.deactivate()?;
+    if let Some(name) = self.stack.pop() {
+      self.leave(&name)?;
+    }
+    self.activate()
+  }
+
+  /// Take every mode off the stack, top first.
+  pub fn clear(&mut self) -> error::Result<()> {
+    self.deactivate()?;
+    while let Some(name) = self.stack.pop() {
+      self.leave(&name)?;
+    }
+    Ok(())
+  }
+
+  /// Make the transition a mode asked for. Returns false once the
+  /// application should quit.
+  pub fn transition(&mut self, transition: handler::Transition) -> error::Result<bool> {
+    match transition {
+      handler::Transition::Switch(name) => self.switch(&name)?,
+      handler::Transition::Push(name) => self.push(&name)?,
+      handler::Transition::Pop => self.pop()?,
+      handler::Transition::Quit => {
+        // Quitting leaves every mode, so they can save what they need to
+        self.clear()?;
+        return Ok(false);
+      },
+    }
+    Ok(true)
+  }
+
+  fn check(&self, name: &str) -> error::Result<()> {
+    match self.get(name) {
+      Some(_) => Ok(()),
+      None => Err(error::Error::from(
+          format!("No application mode named {}", name),
+          )),
+    }
+  }
+
+  fn activate(&mut self) -> error::Result<()> {
+    match self.active_mut() {
+      Some(application_mode) => application_mode.on_activate(),
+      None => Ok(()),
+    }
+  }
+
+  fn deactivate(&mut self) -> error::Result<()> {
+    match self.active_mut() {
+      Some(application_mode) => application_mode.on_deactivate(),
+      None => Ok(()),
+    }
+  }
+
+  fn leave(&mut self, name: &str) -> error::Result<()> {
+    match self.get_mut(name) {
+      Some(application_mode) => application_mode.on_leave(),
+      None => Ok(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A mode that logs the calls it gets as "name call"
+  struct Mode {
+    name: String,
+    log: Rc<RefCell<Vec<String>>>,
+  }
+
+  impl Mode {
+    fn log(&self, call: &str) {
+      self.log.borrow_mut().push(format!("{} {}", self.name, call));
+    }
+  }
+
+  impl handler::InputHandler for Mode {}
+
+  impl handler::UpdateHandler for Mode {}
+
+  impl handler::WindowHandler for Mode {}
+
+  impl handler::EventHandler for Mode {
+    fn on_activate(&mut self) -> error::Result<()> {
+      self.log("activate");
+      Ok(())
+    }
+
+    fn on_deactivate(&mut self) -> error::Result<()> {
+      self.log("deactivate");
+      Ok(())
+    }
+
+    fn on_leave(&mut self) -> error::Result<()> {
+      self.log("leave");
+      Ok(())
+    }
+  }
+
+  fn make_modes(log: &Rc<RefCell<Vec<String>>>) -> ModeStack {
+    let mut modes = ModeStack::default();
+    for name in ["title", "game", "pause"].iter() {
+      let mode = Mode { name: name.to_string(), log: log.clone() };
+      modes.add(name, Box::new(mode)).unwrap();
+    }
+    modes
+  }
+
+  fn take(log: &Rc<RefCell<Vec<String>>>) -> Vec<String> {
+    std::mem::replace(&mut *log.borrow_mut(), Vec::new())
+  }
+
+  #[test]
+  fn push_and_pop_keep_the_modes_underneath() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut modes = make_modes(&log);
+    modes.switch("game").unwrap();
+    assert_eq!(take(&log), vec!["game activate"]);
+
+    modes.push("pause").unwrap();
+    assert_eq!(modes.stack, vec!["game", "pause"]);
+    assert_eq!(take(&log), vec!["game deactivate", "pause activate"]);
+    assert!(modes.push("game").is_err());
+
+    modes.pop().unwrap();
+    assert_eq!(modes.stack, vec!["game"]);
+    assert_eq!(take(&log), vec!["pause deactivate", "pause leave", "game activate"]);
+    assert!(modes.pop().is_err());
+  }
+
+  #[test]
+  fn switching_leaves_every_other_mode() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut modes = make_modes(&log);
+    modes.switch("game").unwrap();
+    modes.push("pause").unwrap();
+    take(&log);
+
+    modes.switch("title").unwrap();
+    assert_eq!(modes.stack, vec!["title"]);
+    assert_eq!(take(&log), vec![
+      "pause deactivate", "game leave", "pause leave", "title activate",
+    ]);
+    assert!(modes.switch("credits").is_err());
+    assert_eq!(modes.stack, vec!["title"]);
+  }
+
+  #[test]
+  fn switching_to_the_active_mode_does_nothing() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut modes = make_modes(&log);
+    modes.switch("title").unwrap();
+    take(&log);
+
+    assert!(modes.transition(handler::Transition::Switch(String::from("title"))).unwrap());
+    assert_eq!(modes.stack, vec!["title"]);
+    assert!(take(&log).is_empty());
+  }
+
+  #[test]
+  fn quitting_leaves_every_mode() {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut modes = make_modes(&log);
+    modes.switch("game").unwrap();
+    modes.push("pause").unwrap();
+    take(&log);
+
+    assert!(!modes.transition(handler::Transition::Quit).unwrap());
+    assert!(modes.stack.is_empty());
+    assert_eq!(take(&log), vec!["pause deactivate", "pause leave", "game leave"]);
+  }
+}
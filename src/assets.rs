@@ -1,27 +1,27 @@
 extern crate piston_window;
 extern crate find_folder;
 extern crate gif;
+extern crate graphics;
 extern crate image;
-extern crate tiled;
 
 use std::rc::Rc;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-type Texture = piston_window::G2dTexture;
+use error;
 
-pub struct Frame {
+pub struct Frame<Texture> {
     pub texture: Rc<Texture>,
     pub frame_time: f64, // frame delay, units of 10ms
 }
 
-pub struct ImageAsset {
-    pub frames: Vec<Frame>,
+pub struct ImageAsset<Texture> {
+    pub frames: Vec<Frame<Texture>>,
 }
 
-impl ImageAsset {
-    pub fn new() -> ImageAsset {
+impl<Texture> ImageAsset<Texture> {
+    pub fn new() -> ImageAsset<Texture> {
         ImageAsset {
             frames: Vec::new(),
         }
@@ -35,94 +35,252 @@ impl ImageAsset {
     }
 }
 
-pub type AssetMap = HashMap<String, Rc<ImageAsset>>;
+pub type AssetMap<Texture> = HashMap<String, Rc<ImageAsset<Texture>>>;
 
-fn load_assets_from_dir<Window>(
-  mut window: &mut piston_window::PistonWindow<Window>,
+/// A texture that only knows its size. Assets loaded with it can drive the
+/// sprite scene without a window or a graphics device.
+#[derive(Clone, Debug)]
+pub struct NullTexture {
+    width: u32,
+    height: u32,
+}
+
+impl graphics::ImageSize for NullTexture {
+    fn get_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+}
+
+fn load_assets_from_dir<Texture, F>(
   dir: &Path,
   prefix: &str,
-  mut assets: &mut AssetMap)
-where Window: piston_window::Window
+  mut assets: &mut AssetMap<Texture>,
+  make_texture: &mut F) -> error::Result<()>
+where F: FnMut(&image::RgbaImage) -> error::Result<Texture>
 {
-  for entry in dir.read_dir().expect("read dir call failed") {
-    if let Ok(entry) = entry {
-      if entry.file_type().unwrap().is_dir() {
-
-        let name = if prefix.len() > 0 {
-            prefix.to_owned() + "/" + entry.file_name().to_str().unwrap()
-        } else {
-            entry.file_name().to_str().unwrap().to_owned()
-        };
-
-        load_assets_from_dir(&mut window, &entry.path(), &name, &mut assets);
-      } else if entry.file_type().unwrap().is_file() {
-        let path = entry.path();
-        let name = if prefix.len() > 0 {
-            prefix.to_owned() + "/" + path.file_stem().unwrap().to_str().unwrap()
-        } else {
-            path.file_stem().unwrap().to_str().unwrap().to_owned()
-        };
-        if let Some(extension) = entry.path().extension() {
-          match extension.to_str().unwrap() {
-              "png" => {
-                  println!("Loading {}", name);
-                  let texture = Rc::new(piston_window::Texture::from_path(
-                                        &mut window.factory,
-                                        path,
-                                        piston_window::Flip::None,
-                                        &piston_window::TextureSettings::new().mag(piston_window::Filter::Nearest),
-                                        ).unwrap());
-                  let mut asset = ImageAsset::new();
-                  asset.add_frame(texture, 0.0);
-                  assets.insert(name, Rc::new(asset));
-              }
-              "gif" => {
-                  use self::gif::Decoder;
-                  use self::gif::SetParameter;
-                  println!("Loading {}", name);
-                  let mut asset = ImageAsset::new();
-
-                  let mut decoder = Decoder::new(File::open(&path).expect(&format!("Could not open {:?}", &path)));
-                  decoder.set(gif::ColorOutput::RGBA);
-                  let mut decoder = decoder.read_info().expect(&format!("Could not decode gif {:?}", &path));
-
-                  let size = (decoder.width() as u32, decoder.height() as u32);
-                  let frame_size = (size.0 * size.1 * 4) as usize;
-                  while let Some(frame) = decoder.read_next_frame().expect(&format!("Could not read next frame from {:?}", &path)) {
-                      use self::image::GenericImage;
-                      let cur_frame = vec![0u8; frame_size];
-                      let src = image::ImageBuffer::<image::Rgba<u8>, Vec<u8>>::from_raw(frame.width as u32, frame.height as u32, frame.buffer.clone().into_owned()).expect("Could not create source image (source too small?)");
-                      let mut dst = image::ImageBuffer::<image::Rgba<u8>, Vec<u8>>::from_raw(size.0, size.1, cur_frame).expect("Could not create destination image buffer");
-                      dst.copy_from(&src, frame.left as u32, frame.top as u32);
-
-                      let texture = Rc::new(piston_window::Texture::from_image(
-                                            &mut window.factory,
-                                            &dst,
-                                            &piston_window::TextureSettings::new().mag(piston_window::Filter::Nearest),
-                                            ).expect("Could not create Texture"));
-                      // convert frame time from 10ms units to floating-point seconds
-                      asset.add_frame(texture, (frame.delay as f64) / 100.0);
-                  }
-                  assets.insert(name, Rc::new(asset));
-              }
-              _ => (),
+  for entry in dir.read_dir()? {
+    let entry = entry?;
+    let file_type = entry.file_type()?;
+    if file_type.is_dir() {
+
+      let name = if !prefix.is_empty() {
+          prefix.to_owned() + "/" + &entry.file_name().to_string_lossy()
+      } else {
+          entry.file_name().to_string_lossy().into_owned()
+      };
+
+      load_assets_from_dir(&entry.path(), &name, &mut assets, make_texture)?;
+    } else if file_type.is_file() {
+      let path = entry.path();
+      let stem = match path.file_stem() {
+        Some(stem) => stem.to_string_lossy().into_owned(),
+        None => continue,
+      };
+      let name = if !prefix.is_empty() {
+          prefix.to_owned() + "/" + &stem
+      } else {
+          stem
+      };
+      if let Some(asset) = load_image(&path, make_texture)? {
+        println!("Loaded {}", name);
+        assets.insert(name, Rc::new(asset));
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Decode the png or gif at path into an ImageAsset. Files of any other type
+/// aren't images, so they give None.
+fn load_image<Texture, F>(path: &Path, make_texture: &mut F)
+  -> error::Result<Option<ImageAsset<Texture>>>
+where F: FnMut(&image::RgbaImage) -> error::Result<Texture>
+{
+  let extension = path.extension().and_then(|extension| extension.to_str());
+  match extension {
+      Some("png") => {
+          let image = image::open(&path)
+              .map_err(|e| error::Error::from(format!("Could not open {:?}: {}", &path, e)))?
+              .to_rgba();
+          let texture = Rc::new(make_texture(&image)?);
+          let mut asset = ImageAsset::new();
+          asset.add_frame(texture, 0.0);
+          Ok(Some(asset))
+      }
+      Some("gif") => {
+          use self::gif::Decoder;
+          use self::gif::SetParameter;
+          let mut asset = ImageAsset::new();
+
+          let mut decoder = Decoder::new(File::open(&path)?);
+          decoder.set(gif::ColorOutput::RGBA);
+          let mut decoder = decoder.read_info()
+              .map_err(|e| error::Error::from(format!("Could not decode gif {:?}: {}", &path, e)))?;
+
+          let size = (u32::from(decoder.width()), u32::from(decoder.height()));
+          let frame_size = (size.0 * size.1 * 4) as usize;
+          while let Some(frame) = decoder.read_next_frame()
+              .map_err(|e| error::Error::from(format!("Could not read next frame from {:?}: {}", &path, e)))? {
+              use self::image::GenericImage;
+              let cur_frame = vec![0u8; frame_size];
+              let src = image::ImageBuffer::<image::Rgba<u8>, Vec<u8>>::from_raw(u32::from(frame.width), u32::from(frame.height), frame.buffer.clone().into_owned())
+                  .ok_or_else(|| error::Error::from(format!("Frame of {:?} is larger than its data", &path)))?;
+              let mut dst = image::ImageBuffer::<image::Rgba<u8>, Vec<u8>>::from_raw(size.0, size.1, cur_frame)
+                  .ok_or_else(|| error::Error::from("Could not create destination image buffer"))?;
+              dst.copy_from(&src, u32::from(frame.left), u32::from(frame.top));
+
+              let texture = Rc::new(make_texture(&dst)?);
+              // convert frame time from 10ms units to floating-point seconds
+              asset.add_frame(texture, f64::from(frame.delay) / 100.0);
           }
-        }
+          if asset.frames.is_empty() {
+              return Err(error::Error::from(format!("{:?} has no frames", &path)));
+          }
+          Ok(Some(asset))
+      }
+      _ => Ok(None),
+  }
+}
+
+/// The assets folder.
+pub fn asset_dir() -> error::Result<PathBuf> {
+  find_folder::Search::ParentsThenKids(3,3).for_folder("assets")
+    .map_err(|e| error::Error::from(format!("Could not find assets folder: {:?}", e)))
+}
+
+/// The name of every image under the assets folder, without loading them.
+pub fn asset_names() -> error::Result<BTreeSet<String>> {
+  let mut names = BTreeSet::new();
+  list_asset_names(&asset_dir()?, "", &mut names)?;
+  Ok(names)
+}
+
+fn list_asset_names(dir: &Path, prefix: &str, names: &mut BTreeSet<String>)
+  -> error::Result<()>
+{
+  for entry in dir.read_dir()? {
+    let path = entry?.path();
+    // Like load_assets_from_dir, directories keep their whole name and files
+    // lose their extension
+    let is_dir = path.is_dir();
+    let base = if is_dir { path.file_name() } else { path.file_stem() };
+    let base = match base {
+      Some(base) => base.to_string_lossy().into_owned(),
+      None => continue,
+    };
+    let name = if !prefix.is_empty() {
+      prefix.to_owned() + "/" + &base
+    } else {
+      base
+    };
+
+    if is_dir {
+      list_asset_names(&path, &name, names)?;
+    } else {
+      match path.extension().and_then(|extension| extension.to_str()) {
+        Some("png") | Some("gif") => {
+          names.insert(name);
+        },
+        _ => {},
       }
     }
   }
+  Ok(())
 }
 
-pub fn load_assets<Window>(mut window: &mut piston_window::PistonWindow<Window>) -> AssetMap
-where Window: piston_window::Window
+/// The asset called name, e.g. "objects/car/car".
+pub fn get_asset<Texture>(assets: &AssetMap<Texture>, name: &str)
+  -> error::Result<Rc<ImageAsset<Texture>>>
+{
+  assets.get(name)
+    .cloned()
+    .ok_or_else(|| error::Error::from(format!("Could not find asset {}", name)))
+}
+
+/// Load the image at path into assets again, replacing the old version.
+/// Returns the name of the asset, or None if path isn't an image in the
+/// assets folder.
+pub fn reload_asset_with<Texture, F>(
+  assets: &mut AssetMap<Texture>,
+  path: &Path,
+  mut make_texture: F,
+) -> error::Result<Option<String>>
+where F: FnMut(&image::RgbaImage) -> error::Result<Texture>
+{
+  let asset_dir = asset_dir()?;
+  let relative = match path.strip_prefix(&asset_dir) {
+    Ok(relative) => relative.with_extension(""),
+    Err(_) => return Ok(None),
+  };
+  let name = relative.components()
+    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+    .collect::<Vec<String>>()
+    .join("/");
+
+  match load_image(path, &mut make_texture)? {
+    Some(asset) => {
+      println!("Reloaded {}", name);
+      assets.insert(name.clone(), Rc::new(asset));
+      Ok(Some(name))
+    },
+    None => Ok(None),
+  }
+}
+
+/// Load every image under the assets folder, turning each decoded frame into
+/// a texture with make_texture.
+pub fn load_assets_with<Texture, F>(mut make_texture: F) -> error::Result<AssetMap<Texture>>
+where F: FnMut(&image::RgbaImage) -> error::Result<Texture>
 {
   let mut assets = HashMap::new();
   // Load assets. This probably isn't the place, but we'll deal with that
   // later.
 
-  let asset_dir = find_folder::Search::ParentsThenKids(3,3).for_folder("assets").unwrap();
+  let asset_dir = asset_dir()?;
+
+  load_assets_from_dir(&asset_dir, "", &mut assets, &mut make_texture)?;
+
+  Ok(assets)
+}
+
+pub fn load_assets<Window>(window: &mut piston_window::PistonWindow<Window>)
+  -> error::Result<AssetMap<piston_window::G2dTexture>>
+where Window: piston_window::Window
+{
+  let mut factory = window.factory.clone();
+  load_assets_with(|image| {
+    piston_window::Texture::from_image(
+      &mut factory,
+      image,
+      &piston_window::TextureSettings::new().mag(piston_window::Filter::Nearest),
+      ).map_err(|e| error::Error::from(format!("Could not create texture: {}", e)))
+  })
+}
 
-  load_assets_from_dir(&mut window, &asset_dir, "", &mut assets);
+/// Reload the image at path with textures for window; see reload_asset_with.
+pub fn reload_asset<Window>(
+  window: &mut piston_window::PistonWindow<Window>,
+  assets: &mut AssetMap<piston_window::G2dTexture>,
+  path: &Path,
+) -> error::Result<Option<String>>
+where Window: piston_window::Window
+{
+  let mut factory = window.factory.clone();
+  reload_asset_with(assets, path, |image| {
+    piston_window::Texture::from_image(
+      &mut factory,
+      image,
+      &piston_window::TextureSettings::new().mag(piston_window::Filter::Nearest),
+      ).map_err(|e| error::Error::from(format!("Could not create texture: {}", e)))
+  })
+}
 
-  return assets;
+/// Load every image under the assets folder without a graphics device.
+pub fn load_null_assets() -> error::Result<AssetMap<NullTexture>> {
+  load_assets_with(|image| {
+    Ok(NullTexture {
+      width: image.width(),
+      height: image.height(),
+    })
+  })
 }
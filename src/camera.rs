@@ -1,13 +1,54 @@
+extern crate graphics;
 extern crate nalgebra;
 extern crate piston_window;
 
+use self::graphics::math::Matrix2d;
+
 pub type WorldPoint2 = nalgebra::Point2<f64>;
 pub type WorldVector2 = nalgebra::Vector2<f64>;
 
+/// Roughly how long the camera takes to catch up with what it follows, in
+/// seconds.
+const SMOOTH_TIME: f64 = 0.3;
+/// How far what the camera follows can move from the middle of the screen
+/// before the camera moves too, in screen pixels.
+const DEAD_ZONE: [f64; 2] = [150.0, 100.0];
+/// How fast the screen shakes back and forth.
+const SHAKE_FREQUENCY: f64 = 30.0;
+
+/// A scripted shot: look at target for a while instead of following.
+struct Shot {
+  target: WorldPoint2,
+  remaining: f64, // seconds
+}
+
+/// A change of zoom in progress.
+struct ZoomTransition {
+  from: f64,
+  to: f64,
+  elapsed: f64,
+  duration: f64,
+}
+
+/// A 2D camera that follows a point around a bounded world.
+///
+/// The camera eases towards what it follows with a critically damped spring,
+/// so it never overshoots, and only starts moving once the followed point
+/// leaves a dead zone in the middle of the screen. Scripted shots take over
+/// from following for a while, and zoom changes and shakes play out over
+/// time. Everything is driven by update, so a replay films the same way.
 pub struct Camera2 {
   pub zoom: f64,
   pub position: WorldPoint2,
   pub velocity: WorldVector2,
+  goal: WorldPoint2, // where the camera is easing towards
+  settled: bool, // false until the camera has been placed
+  shots: Vec<Shot>, // first is playing
+  zoom_transition: Option<ZoomTransition>,
+  shake_strength: f64, // screen pixels
+  shake_remaining: f64,
+  shake_duration: f64,
+  shake_offset: WorldVector2, // screen pixels
 }
 
 impl Camera2 {
@@ -16,6 +57,296 @@ impl Camera2 {
       zoom: 1.0,
       position: WorldPoint2::new(0.0, 0.0),
       velocity: WorldVector2::new(0.0, 0.0),
+      goal: WorldPoint2::new(0.0, 0.0),
+      settled: false,
+      shots: Vec::new(),
+      zoom_transition: None,
+      shake_strength: 0.0,
+      shake_remaining: 0.0,
+      shake_duration: 0.0,
+      shake_offset: WorldVector2::new(0.0, 0.0),
+    }
+  }
+
+  /// Put the camera somewhere right away, without easing there.
+  pub fn jump_to(&mut self, position: WorldPoint2, zoom: f64) {
+    self.position = position;
+    self.goal = position;
+    self.velocity = WorldVector2::new(0.0, 0.0);
+    self.zoom = zoom;
+    self.zoom_transition = None;
+    self.settled = true;
+  }
+
+  /// Change zoom to zoom over seconds.
+  pub fn zoom_to(&mut self, zoom: f64, seconds: f64) {
+    if seconds <= 0.0 {
+      self.zoom = zoom;
+      self.zoom_transition = None;
+      return;
+    }
+    self.zoom_transition = Some(ZoomTransition {
+      from: self.zoom,
+      to: zoom,
+      elapsed: 0.0,
+      duration: seconds,
+    });
+  }
+
+  /// Shake the screen by up to strength pixels, dying down over seconds. A
+  /// weaker shake doesn't cut a stronger one short.
+  pub fn shake(&mut self, strength: f64, seconds: f64) {
+    let current = self.shake_strength * self.shake_fraction();
+    if strength >= current {
+      self.shake_strength = strength;
+      self.shake_remaining = seconds;
+      self.shake_duration = seconds;
+    }
+  }
+
+  /// Pan over to look at target for seconds, then go back to following.
+  /// Shots queue up behind each other.
+  pub fn look_at(&mut self, target: WorldPoint2, seconds: f64) {
+    self.shots.push(Shot { target, remaining: seconds });
+  }
+
+  /// Move the camera towards subject, or towards the current shot, keeping
+  /// the view of view_size inside bounds (min, max).
+  pub fn update(
+    &mut self,
+    subject: WorldPoint2,
+    dt: f64,
+    view_size: [f64; 2],
+    bounds: (WorldPoint2, WorldPoint2),
+  ) {
+    if let Some(mut transition) = self.zoom_transition.take() {
+      transition.elapsed += dt;
+      let t = (transition.elapsed / transition.duration).min(1.0);
+      // Ease in and out
+      let t = t * t * (3.0 - 2.0 * t);
+      self.zoom = transition.from + (transition.to - transition.from) * t;
+      if transition.elapsed < transition.duration {
+        self.zoom_transition = Some(transition);
+      }
+    }
+
+    let view = [view_size[0] / self.zoom, view_size[1] / self.zoom];
+    let (ref min, ref max) = bounds;
+    if !self.settled {
+      // Start on the subject, as far as the bounds allow
+      let start = WorldPoint2::new(
+        clamp_view(subject.x, min.x, max.x, view[0]),
+        clamp_view(subject.y, min.y, max.y, view[1]),
+      );
+      let zoom = self.zoom;
+      self.jump_to(start, zoom);
+    }
+
+    let shot_done = match self.shots.first_mut() {
+      Some(shot) => {
+        self.goal = shot.target;
+        shot.remaining -= dt;
+        shot.remaining <= 0.0
+      },
+      None => {
+        // Only follow once the subject leaves the dead zone around the goal
+        let dead_zone = [DEAD_ZONE[0] / self.zoom, DEAD_ZONE[1] / self.zoom];
+        self.goal.x = clamp(self.goal.x, subject.x - dead_zone[0], subject.x + dead_zone[0]);
+        self.goal.y = clamp(self.goal.y, subject.y - dead_zone[1], subject.y + dead_zone[1]);
+        false
+      },
+    };
+    if shot_done {
+      self.shots.remove(0);
+    }
+
+    // The camera can't show past the bounds, so don't aim past them either
+    let goal = WorldPoint2::new(
+      clamp_view(self.goal.x, min.x, max.x, view[0]),
+      clamp_view(self.goal.y, min.y, max.y, view[1]),
+    );
+
+    self.position.x = smooth_damp(self.position.x, goal.x, &mut self.velocity.x, dt);
+    self.position.y = smooth_damp(self.position.y, goal.y, &mut self.velocity.y, dt);
+    self.position.x = clamp_view(self.position.x, min.x, max.x, view[0]);
+    self.position.y = clamp_view(self.position.y, min.y, max.y, view[1]);
+
+    if self.shake_remaining > 0.0 {
+      self.shake_remaining = (self.shake_remaining - dt).max(0.0);
+      // Smooth deterministic wobble rather than noise, so replays match
+      let amplitude = self.shake_strength * self.shake_fraction();
+      let phase = self.shake_remaining * SHAKE_FREQUENCY;
+      self.shake_offset = WorldVector2::new(
+        amplitude * (phase * 2.1).sin(),
+        amplitude * (phase * 2.9 + 1.3).sin(),
+      );
+    } else {
+      self.shake_offset = WorldVector2::new(0.0, 0.0);
+    }
+  }
+
+  /// Transform from world to screen coordinates, so the camera position is
+  /// in the middle of a view of view_size and the world is scaled by zoom
+  /// around it.
+  pub fn transform(&self, base: Matrix2d, view_size: [f64; 2]) -> Matrix2d {
+    use self::graphics::Transformed; // trans, zoom
+
+    base
+      .trans(view_size[0] * 0.5 + self.shake_offset.x, view_size[1] * 0.5 + self.shake_offset.y)
+      .zoom(self.zoom)
+      .trans(-self.position.x, -self.position.y)
+  }
+
+  /// The world point shown at a screen position; the inverse of transform,
+  /// ignoring shake.
+  pub fn screen_to_world(&self, screen: [f64; 2], view_size: [f64; 2]) -> WorldPoint2 {
+    WorldPoint2::new(
+      (screen[0] - view_size[0] * 0.5) / self.zoom + self.position.x,
+      (screen[1] - view_size[1] * 0.5) / self.zoom + self.position.y,
+    )
+  }
+
+  // How much of the shake is left, from 1 down to 0
+  fn shake_fraction(&self) -> f64 {
+    if self.shake_duration > 0.0 {
+      self.shake_remaining / self.shake_duration
+    } else {
+      0.0
+    }
+  }
+}
+
+// Ease current towards target like a critically damped spring that settles in
+// about SMOOTH_TIME, updating velocity; see Game Programming Gems 4, 1.10
+fn smooth_damp(current: f64, target: f64, velocity: &mut f64, dt: f64) -> f64 {
+  let omega = 2.0 / SMOOTH_TIME;
+  let x = omega * dt;
+  let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+  let change = current - target;
+  let temp = (*velocity + omega * change) * dt;
+  *velocity = (*velocity - omega * temp) * decay;
+  target + (change + temp) * decay
+}
+
+// Clamp the center of a view so it stays within [min, max]; a view bigger
+// than the bounds is centered on them.
+fn clamp_view(x: f64, min: f64, max: f64, view: f64) -> f64 {
+  if max - min < view {
+    (min + max) * 0.5
+  } else {
+    clamp(x, min + view * 0.5, max - view * 0.5)
+  }
+}
+
+fn clamp(x: f64, min: f64, max: f64) -> f64 {
+  x.max(min).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DT: f64 = 1.0 / 60.0;
+  const VIEW: [f64; 2] = [800.0, 600.0];
+
+  fn bounds() -> (WorldPoint2, WorldPoint2) {
+    (WorldPoint2::new(0.0, 0.0), WorldPoint2::new(4000.0, 2000.0))
+  }
+
+  // A camera that has settled on (1000, 1000)
+  fn settled() -> Camera2 {
+    let mut camera = Camera2::new();
+    camera.update(WorldPoint2::new(1000.0, 1000.0), DT, VIEW, bounds());
+    camera
+  }
+
+  fn run(camera: &mut Camera2, subject: WorldPoint2, seconds: f64) {
+    for _ in 0..(seconds / DT).round() as usize {
+      camera.update(subject, DT, VIEW, bounds());
+    }
+  }
+
+  #[test]
+  fn follows_the_subject_without_overshooting() {
+    let mut camera = settled();
+    assert_eq!(camera.position, WorldPoint2::new(1000.0, 1000.0));
+
+    // The camera stops with the subject at the edge of the dead zone
+    let subject = WorldPoint2::new(2000.0, 1000.0);
+    let mut last = camera.position.x;
+    for _ in 0..180 {
+      camera.update(subject, DT, VIEW, bounds());
+      assert!(camera.position.x >= last && camera.position.x <= 1850.0,
+              "camera at {} after {}", camera.position.x, last);
+      last = camera.position.x;
+    }
+    assert!((camera.position.x - 1850.0).abs() < 1.0, "camera at {}", camera.position.x);
+  }
+
+  #[test]
+  fn stays_put_while_the_subject_is_in_the_dead_zone() {
+    let mut camera = settled();
+    run(&mut camera, WorldPoint2::new(1100.0, 1050.0), 1.0);
+    assert_eq!(camera.position, WorldPoint2::new(1000.0, 1000.0));
+
+    // Zoomed in, the same move on screen is a smaller move in the world
+    camera.zoom = 2.0;
+    run(&mut camera, WorldPoint2::new(1100.0, 1050.0), 3.0);
+    assert!((camera.position.x - 1025.0).abs() < 1.0, "camera at {}", camera.position.x);
+    assert!((camera.position.y - 1000.0).abs() < 1.0, "camera at {}", camera.position.y);
+  }
+
+  #[test]
+  fn keeps_the_view_inside_the_bounds_at_any_zoom() {
+    let corner = WorldPoint2::new(0.0, 0.0);
+    let mut camera = Camera2::new();
+    run(&mut camera, corner, 1.0);
+    assert_eq!(camera.position, WorldPoint2::new(400.0, 300.0));
+
+    camera.zoom_to(2.0, 0.0);
+    run(&mut camera, corner, 3.0);
+    assert!((camera.position - WorldPoint2::new(200.0, 150.0)).norm() < 1.0,
+            "camera at {}", camera.position);
+
+    // A view bigger than the world is centered on it
+    camera.zoom_to(0.1, 0.0);
+    run(&mut camera, corner, 1.0);
+    assert_eq!(camera.position, WorldPoint2::new(2000.0, 1000.0));
+  }
+
+  #[test]
+  fn zooms_over_time() {
+    let mut camera = settled();
+    camera.zoom_to(2.0, 1.0);
+    run(&mut camera, WorldPoint2::new(1000.0, 1000.0), 0.5);
+    assert!((camera.zoom - 1.5).abs() < 1e-6, "zoom {}", camera.zoom);
+    run(&mut camera, WorldPoint2::new(1000.0, 1000.0), 1.0);
+    assert!((camera.zoom - 2.0).abs() < 1e-6, "zoom {}", camera.zoom);
+  }
+
+  #[test]
+  fn shakes_die_down() {
+    let subject = WorldPoint2::new(1000.0, 1000.0);
+    let mut camera = settled();
+    camera.shake(20.0, 1.0);
+    // A weaker shake doesn't cut the stronger one short
+    camera.shake(5.0, 0.1);
+
+    let mut biggest = 0.0f64;
+    for _ in 0..30 {
+      camera.update(subject, DT, VIEW, bounds());
+      biggest = biggest.max(camera.shake_offset.norm());
+    }
+    assert!(biggest > 5.0 && biggest <= 20.0 * 2.0f64.sqrt(), "shook by {}", biggest);
+
+    // Half way through, the shake is at most half as strong
+    for _ in 0..15 {
+      camera.update(subject, DT, VIEW, bounds());
+      assert!(camera.shake_offset.norm() <= 10.0 * 2.0f64.sqrt());
     }
+
+    run(&mut camera, subject, 0.5);
+    assert_eq!(camera.shake_offset, WorldVector2::new(0.0, 0.0));
+    assert_eq!(camera.position, subject);
   }
 }
@@ -0,0 +1,78 @@
+extern crate serde_json;
+extern crate std;
+
+use error;
+
+/// One entry in a campaign. Levels are played until the hero wins;
+/// interstitials are text screens shown between levels.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Step {
+  Level(String), // path to the level file
+  Interstitial {
+    title: String,
+    #[serde(default)]
+    lines: Vec<String>,
+    duration: u64, // seconds each line is shown for
+  },
+}
+
+/// The ordered list of levels and interstitials that make up a game.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Campaign {
+  pub steps: Vec<Step>,
+}
+
+impl Campaign {
+  pub fn from_path_str(path_str: &str) -> error::Result<Campaign> {
+    Self::from_path(&std::path::Path::new(path_str))
+  }
+
+  pub fn from_path(path: &std::path::Path) -> error::Result<Campaign> {
+    let file = std::fs::File::open(path)?;
+    let campaign = serde_json::from_reader(file)?;
+    Ok(campaign)
+  }
+
+  /// The step that follows step. Once past the last step, the campaign stays
+  /// there.
+  pub fn next_step(&self, step: usize) -> usize {
+    if step >= self.steps.len() {
+      step
+    } else {
+      step + 1
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn loads_the_campaign() {
+    let campaign = Campaign::from_path_str("assets/campaign.json").unwrap();
+    match campaign.steps.first() {
+      Some(&Step::Level(_)) => {},
+      step => panic!("campaign starts with {:?}", step),
+    }
+  }
+
+  #[test]
+  fn advances_through_every_step_and_stops_past_the_last() {
+    let campaign = Campaign {
+      steps: vec![
+        Step::Level(String::from("one.json")),
+        Step::Interstitial { title: String::from("Between"), lines: Vec::new(), duration: 1 },
+        Step::Level(String::from("two.json")),
+      ],
+    };
+    let mut step = 0;
+    let mut visited = vec![step];
+    while campaign.next_step(step) != step {
+      step = campaign.next_step(step);
+      visited.push(step);
+    }
+    assert_eq!(visited, vec![0, 1, 2, 3]);
+    assert_eq!(campaign.next_step(3), 3);
+  }
+}
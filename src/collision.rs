@@ -0,0 +1,372 @@
+extern crate graphics;
+extern crate nalgebra;
+extern crate ncollide;
+
+use std::collections::HashMap;
+
+use self::nalgebra::{Isometry2, Point2, Vector2};
+use self::ncollide::bounding_volume::{self, BoundingVolume, AABB2};
+use self::ncollide::query::{self, Proximity};
+use self::ncollide::shape::{Ball, ConvexHull, Cuboid, ShapeHandle2};
+
+use entity;
+use level;
+
+/// An actor's collision shape, placed in the world.
+#[derive(Clone)]
+pub struct Collider {
+  position: Isometry2<f64>,
+  shape: ShapeHandle2<f64>,
+  aabb: AABB2<f64>,
+}
+
+impl Collider {
+  /// Build the collider of an actor. Shapes are given in unscaled image
+  /// pixels relative to the center of the actor's sprite, and are mirrored
+  /// when the actor faces left. Actors without a shape get a box as wide as
+  /// their width and as tall as their sprite.
+  pub fn new(
+    shape: Option<&level::Shape>,
+    bb: graphics::types::Rectangle,
+    width: f64,
+    scale: f64,
+    direction: bool,
+  ) -> Collider {
+    let center = Vector2::new(bb[0] + bb[2] / 2.0, bb[1] + bb[3] / 2.0);
+    let flip = if direction { 1.0 } else { -1.0 };
+
+    let shape = match shape {
+      Some(&level::Shape::Aabb { half_width, half_height }) => {
+        ShapeHandle2::new(Cuboid::new(Vector2::new(half_width * scale, half_height * scale)))
+      },
+      Some(&level::Shape::Circle { radius }) => ShapeHandle2::new(Ball::new(radius * scale)),
+      Some(&level::Shape::Polygon(ref points)) => {
+        let points = points.iter()
+          .map(|point| Point2::new(point.x * scale * flip, point.y * scale))
+          .collect();
+        ShapeHandle2::new(ConvexHull::new(points))
+      },
+      None => ShapeHandle2::new(Cuboid::new(Vector2::new(width / 2.0, bb[3] / 2.0))),
+    };
+
+    let position = Isometry2::new(center, 0.0);
+    let aabb = bounding_volume::aabb(shape.as_ref(), &position);
+
+    Collider {
+      position,
+      shape,
+      aabb,
+    }
+  }
+
+  pub fn aabb(&self) -> &AABB2<f64> {
+    &self.aabb
+  }
+
+  pub fn intersects(&self, other: &Collider) -> bool {
+    if !self.aabb.intersects(&other.aabb) {
+      return false;
+    }
+    let proximity = query::proximity(
+      &self.position, self.shape.as_ref(),
+      &other.position, other.shape.as_ref(),
+      0.0);
+    match proximity {
+      Proximity::Intersecting => true,
+      _ => false,
+    }
+  }
+}
+
+/// Actors wider than this are kept out of the sweep. A full-level background
+/// would otherwise stretch how far left every query has to look to the whole
+/// level.
+const MAX_SWEEP_WIDTH: f64 = 2048.0;
+
+struct Entry {
+  collider: Collider,
+  // What the collider was built from, to tell when it needs rebuilding
+  bb: graphics::types::Rectangle,
+  width: f64,
+  direction: bool,
+}
+
+/// Sort and sweep broad phase over the actors of a level. Colliders are sorted
+/// by the left edge of their bounding box, so a query only looks at actors
+/// whose boxes could reach it, and only those get exact shape tests. It's kept
+/// from update to update, rebuilding only the colliders of actors that moved.
+pub struct BroadPhase {
+  entries: HashMap<String, Entry>,
+  sweep: Vec<(f64, String)>, // left edge and name, sorted by left edge
+  wide: Vec<String>, // actors too wide to sweep; every query tests them
+  max_width: f64, // widest bounding box in the sweep, to bound how far left a query looks
+}
+
+impl BroadPhase {
+  /// Create an empty BroadPhase. Call update to add the entities of a level.
+  pub fn new() -> BroadPhase {
+    BroadPhase {
+      entries: HashMap::new(),
+      sweep: Vec::new(),
+      wide: Vec::new(),
+      max_width: 0.0,
+    }
+  }
+
+  /// Catch up with entities: rebuild the colliders of actors that moved,
+  /// turned or changed size since the last update, and re-sort the sweep if
+  /// any did.
+  #[allow(clippy::float_cmp)] // any change at all, however small, counts
+  pub fn update(&mut self, entities: &entity::EntityMap) {
+    let mut moved = false;
+    let mut added = false;
+    for (name, entity) in entities.iter() {
+      let entity = entity.borrow();
+      let (bb, width, direction) = (entity.bb(), entity.width(), entity.direction());
+      let unchanged = match self.entries.get(name) {
+        Some(entry) => entry.bb == bb && entry.width == width && entry.direction == direction,
+        None => {
+          added = true;
+          false
+        },
+      };
+      if !unchanged {
+        self.entries.insert(name.to_owned(), Entry {
+          collider: entity.collider(),
+          bb,
+          width,
+          direction,
+        });
+        moved = true;
+      }
+    }
+    if self.entries.len() != entities.len() {
+      self.entries.retain(|name, _| entities.contains_key(name));
+      added = true;
+    }
+    if !moved && !added {
+      return;
+    }
+
+    let names: Vec<String> = if added {
+      self.entries.keys().cloned().collect()
+    } else {
+      // Actors only move a little between updates, so keeping the old order
+      // leaves the sweep nearly sorted, which the sort below is fast on.
+      let mut names: Vec<String> = self.sweep.drain(..).map(|(_, name)| name).collect();
+      names.extend(self.wide.drain(..));
+      names
+    };
+
+    self.sweep.clear();
+    self.wide.clear();
+    self.max_width = 0.0;
+    for name in names {
+      let (mins_x, width) = {
+        let aabb = self.entries[&name].collider.aabb();
+        (aabb.mins().x, aabb.maxs().x - aabb.mins().x)
+      };
+      if width > MAX_SWEEP_WIDTH {
+        self.wide.push(name);
+      } else {
+        self.max_width = self.max_width.max(width);
+        self.sweep.push((mins_x, name));
+      }
+    }
+    self.sweep.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+  }
+
+  /// Names of the entities overlapping the entity called name, in name order.
+  pub fn overlapping(&self, name: &str) -> Vec<String> {
+    let collider = match self.entries.get(name) {
+      Some(entry) => &entry.collider,
+      None => return Vec::new(),
+    };
+    let mut names = self.query(collider);
+    names.retain(|other| other != name);
+    names
+  }
+
+  /// Names of the entities overlapping collider, in name order.
+  pub fn query(&self, collider: &Collider) -> Vec<String> {
+    let mins_x = collider.aabb().mins().x;
+    let maxs_x = collider.aabb().maxs().x;
+
+    // Nothing starting further left than the widest box can reach us
+    let start = self.lower_bound(mins_x - self.max_width);
+
+    let mut names: Vec<String> = self.sweep[start..].iter()
+      .take_while(|&&(x, _)| x <= maxs_x)
+      .map(|&(_, ref name)| name)
+      .chain(self.wide.iter())
+      .filter(|name| self.entries[*name].collider.intersects(collider))
+      .cloned()
+      .collect();
+    names.sort();
+    names
+  }
+
+  // Index of the first entry in the sweep starting at or right of x
+  fn lower_bound(&self, x: f64) -> usize {
+    let mut low = 0;
+    let mut high = self.sweep.len();
+    while low < high {
+      let mid = (low + high) / 2;
+      if self.sweep[mid].0 < x {
+        low = mid + 1;
+      } else {
+        high = mid;
+      }
+    }
+    low
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  extern crate piston_window;
+  extern crate uuid;
+
+  use super::*;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use error;
+
+  fn point(x: f64, y: f64) -> level::Point {
+    level::Point { x, y }
+  }
+
+  // A collider with its center at x, y
+  fn collider(shape: level::Shape, x: f64, y: f64, direction: bool) -> Collider {
+    Collider::new(Some(&shape), [x - 1.0, y - 1.0, 2.0, 2.0], 2.0, 1.0, direction)
+  }
+
+  fn circle(radius: f64, x: f64, y: f64) -> Collider {
+    collider(level::Shape::Circle { radius }, x, y, true)
+  }
+
+  fn square(half_width: f64, x: f64, y: f64) -> Collider {
+    let shape = level::Shape::Aabb { half_width, half_height: half_width };
+    collider(shape, x, y, true)
+  }
+
+  // Points down, 20 wide and 20 tall
+  fn triangle(x: f64, y: f64) -> Collider {
+    let points = vec![point(0.0, -10.0), point(10.0, 10.0), point(-10.0, 10.0)];
+    collider(level::Shape::Polygon(points), x, y, true)
+  }
+
+  #[test]
+  fn circle_and_aabb() {
+    assert!(circle(10.0, 0.0, 0.0).intersects(&square(5.0, 14.0, 0.0)));
+    assert!(!circle(10.0, 0.0, 0.0).intersects(&square(5.0, 16.0, 0.0)));
+    // The boxes of both overlap, but the corner is out of reach
+    assert!(!circle(10.0, 0.0, 0.0).intersects(&square(5.0, 13.0, 13.0)));
+    assert!(circle(10.0, 0.0, 0.0).intersects(&square(5.0, 12.0, 12.0)));
+  }
+
+  #[test]
+  fn circle_and_polygon() {
+    assert!(triangle(0.0, 0.0).intersects(&circle(5.0, 0.0, -14.0)));
+    assert!(!triangle(0.0, 0.0).intersects(&circle(5.0, 0.0, -16.0)));
+    // Beside the point, inside the triangle's box
+    assert!(!triangle(0.0, 0.0).intersects(&circle(2.0, 8.0, -8.0)));
+  }
+
+  #[test]
+  fn aabb_and_polygon() {
+    assert!(triangle(0.0, 0.0).intersects(&square(5.0, 14.0, 8.0)));
+    assert!(!triangle(0.0, 0.0).intersects(&square(5.0, 16.0, 8.0)));
+    assert!(!triangle(0.0, 0.0).intersects(&square(3.0, 8.0, -6.0)));
+  }
+
+  #[test]
+  fn polygons_are_mirrored_when_facing_left() {
+    // Points right when facing right
+    let points = vec![point(10.0, 0.0), point(0.0, 5.0), point(0.0, -5.0)];
+    let facing = |direction| collider(level::Shape::Polygon(points.clone()), 0.0, 0.0, direction);
+    assert!(facing(true).intersects(&circle(3.0, 12.0, 0.0)));
+    assert!(!facing(true).intersects(&circle(3.0, -12.0, 0.0)));
+    assert!(facing(false).intersects(&circle(3.0, -12.0, 0.0)));
+    assert!(!facing(false).intersects(&circle(3.0, 12.0, 0.0)));
+  }
+
+  // An actor that is just a box
+  struct Block {
+    name: String,
+    bb: graphics::types::Rectangle,
+    text: String,
+  }
+
+  impl entity::Actor for Block {
+    fn name(&self) -> String { self.name.to_owned() }
+    fn position(&self) -> entity::WorldPoint2 { entity::WorldPoint2::new(self.bb[0], self.bb[1]) }
+    fn velocity(&self) -> entity::WorldVector2 { entity::WorldVector2::new(0.0, 0.0) }
+    fn scale(&self) -> f64 { 1.0 }
+    fn visible(&self) -> bool { true }
+    fn active(&self) -> bool { true }
+    fn sprite_id(&self) -> uuid::Uuid { uuid::Uuid::nil() }
+    fn width(&self) -> f64 { self.bb[2] }
+    fn text(&self) -> &String { &self.text }
+    fn frame(&self) -> usize { 0 }
+    fn bb(&self) -> graphics::types::Rectangle { self.bb }
+    fn set_text(&mut self, _new_text: String, _time: f64) -> error::Result<()> { Ok(()) }
+    fn set_position(&mut self, position: entity::WorldPoint2) -> error::Result<()> {
+      self.bb[0] = position.x;
+      self.bb[1] = position.y;
+      Ok(())
+    }
+    fn set_velocity(&mut self, _velocity: entity::WorldVector2) -> error::Result<()> { Ok(()) }
+    fn set_scale(&mut self, _scale: f64) -> error::Result<()> { Ok(()) }
+    fn set_visible(&mut self, _visible: bool) -> error::Result<()> { Ok(()) }
+    fn set_active(&mut self, _active: bool) -> error::Result<()> { Ok(()) }
+    fn set_frame(&mut self, _frame: usize) -> error::Result<()> { Ok(()) }
+    fn on_update(&mut self, _update_args: &piston_window::UpdateArgs) -> error::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn blocks(bbs: &[(&str, graphics::types::Rectangle)]) -> entity::EntityMap {
+    let mut entities = entity::EntityMap::new();
+    for &(name, bb) in bbs {
+      let block = Block { name: name.to_owned(), bb, text: String::new() };
+      entities.insert(name.to_owned(), Rc::new(RefCell::new(block)));
+    }
+    entities
+  }
+
+  #[test]
+  fn boxes_must_overlap_on_both_axes() {
+    let entities = blocks(&[
+      ("a", [0.0, 0.0, 10.0, 10.0]),
+      ("above", [0.0, -20.0, 10.0, 10.0]), // overlaps a on x only
+      ("beside", [12.0, 0.0, 10.0, 10.0]), // overlaps a on y only
+      ("corner", [5.0, 5.0, 10.0, 10.0]),
+    ]);
+    let mut broad_phase = BroadPhase::new();
+    broad_phase.update(&entities);
+    assert_eq!(broad_phase.overlapping("a"), vec!["corner"]);
+    assert_eq!(broad_phase.overlapping("beside"), vec!["corner"]);
+    assert!(broad_phase.overlapping("above").is_empty());
+  }
+
+  #[test]
+  fn wide_actors_are_found_outside_the_sweep() {
+    let entities = blocks(&[
+      ("background", [0.0, 0.0, 5000.0, 100.0]),
+      ("left", [10.0, 10.0, 10.0, 10.0]),
+      ("right", [4900.0, 10.0, 10.0, 10.0]),
+    ]);
+    let mut broad_phase = BroadPhase::new();
+    broad_phase.update(&entities);
+    assert_eq!(broad_phase.wide, vec!["background"]);
+    assert_eq!(broad_phase.overlapping("right"), vec!["background"]);
+    assert_eq!(broad_phase.overlapping("background"), vec!["left", "right"]);
+
+    // Moving an actor is picked up by the next update
+    entities["left"].borrow_mut().set_position(entity::WorldPoint2::new(4895.0, 10.0)).unwrap();
+    broad_phase.update(&entities);
+    assert_eq!(broad_phase.overlapping("right"), vec!["background", "left"]);
+  }
+}
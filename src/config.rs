@@ -32,7 +32,7 @@ fn default_window_decorated() -> bool {
   false
 }
 fn default_window_controllers() -> bool {
-  false
+  true
 }
 fn default_event_max_fps() -> u64 {
   60
@@ -52,6 +52,45 @@ fn default_event_bench_mode() -> bool {
 fn default_event_lazy() -> bool {
   false
 }
+fn default_input_bindings() -> Vec<Binding> {
+  use piston_window::Key;
+
+  let binding = |action, input| Binding { action, input };
+  vec![
+    binding(Action::MoveLeft, Input::Key(Key::Left)),
+    binding(Action::MoveLeft, Input::ControllerButton(13)),
+    binding(Action::MoveLeft, Input::ControllerAxis { axis: 0, positive: false }),
+    binding(Action::MoveRight, Input::Key(Key::Right)),
+    binding(Action::MoveRight, Input::ControllerButton(14)),
+    binding(Action::MoveRight, Input::ControllerAxis { axis: 0, positive: true }),
+    binding(Action::MoveUp, Input::Key(Key::Up)),
+    binding(Action::MoveUp, Input::ControllerButton(11)),
+    binding(Action::MoveUp, Input::ControllerAxis { axis: 1, positive: false }),
+    binding(Action::MoveDown, Input::Key(Key::Down)),
+    binding(Action::MoveDown, Input::ControllerButton(12)),
+    binding(Action::MoveDown, Input::ControllerAxis { axis: 1, positive: true }),
+    binding(Action::Materialize, Input::Key(Key::LShift)),
+    binding(Action::Materialize, Input::ControllerButton(2)),
+    binding(Action::Interact, Input::Key(Key::Space)),
+    binding(Action::Interact, Input::ControllerButton(0)),
+    binding(Action::Blow, Input::Key(Key::B)),
+    binding(Action::Blow, Input::ControllerButton(1)),
+    binding(Action::DebugSound, Input::Key(Key::X)),
+    binding(Action::QuickSave, Input::Key(Key::F5)),
+    binding(Action::QuickLoad, Input::Key(Key::F9)),
+    binding(Action::Pause, Input::Key(Key::Escape)),
+    binding(Action::Pause, Input::ControllerButton(6)),
+  ]
+}
+fn default_input_axis_dead_zone() -> f64 {
+  0.5
+}
+pub fn default_input_hero_speed() -> f64 {
+  500.0
+}
+fn default_audio_volume() -> f32 {
+  1.0
+}
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 struct WindowConfigSize {
@@ -100,12 +139,128 @@ struct EventConfig {
   lazy: bool,
 }
 
+/// What the player can do, independent of the keys and buttons doing it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Action {
+  MoveLeft,
+  MoveRight,
+  MoveUp,
+  MoveDown,
+  Materialize,
+  Interact,
+  Blow,
+  DebugSound,
+  QuickSave,
+  QuickLoad,
+  Pause, // also backs out of menus
+}
+
+impl Action {
+  /// Whether the hero keeps moving for as long as the action is held.
+  pub fn is_movement(self) -> bool {
+    match self {
+      Action::MoveLeft | Action::MoveRight | Action::MoveUp | Action::MoveDown => true,
+      _ => false,
+    }
+  }
+}
+
+/// A key, gamepad button or direction of a gamepad axis.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Input {
+  Key(piston_window::Key),
+  ControllerButton(u8), // on any controller
+  ControllerAxis { axis: u8, positive: bool }, // pushed past the dead zone
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Binding {
+  pub action: Action,
+  pub input: Input,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InputConfig {
+  #[serde(default = "default_input_bindings")]
+  pub bindings: Vec<Binding>,
+  #[serde(default = "default_input_axis_dead_zone")]
+  pub axis_dead_zone: f64,
+  #[serde(default = "default_input_hero_speed")]
+  pub hero_speed: f64,
+}
+
+impl Default for InputConfig {
+  fn default() -> InputConfig {
+    InputConfig {
+      bindings: default_input_bindings(),
+      axis_dead_zone: default_input_axis_dead_zone(),
+      hero_speed: default_input_hero_speed(),
+    }
+  }
+}
+
+impl InputConfig {
+  /// The action bound to a key or gamepad button, if any.
+  pub fn button_action(&self, button: &piston_window::Button) -> Option<Action> {
+    let input = match *button {
+      piston_window::Button::Keyboard(key) => Input::Key(key),
+      piston_window::Button::Controller(button) => Input::ControllerButton(button.button),
+      _ => return None,
+    };
+    self.action(&input)
+  }
+
+  /// The action bound to the direction a gamepad axis is pushed in, if it's
+  /// pushed past the dead zone.
+  pub fn axis_action(&self, axis: &piston_window::ControllerAxisArgs) -> Option<Action> {
+    if axis.position.abs() < self.axis_dead_zone {
+      return None;
+    }
+    self.action(&Input::ControllerAxis {
+      axis: axis.axis,
+      positive: axis.position > 0.0,
+    })
+  }
+
+  fn action(&self, input: &Input) -> Option<Action> {
+    self.bindings.iter()
+      .find(|binding| binding.input == *input)
+      .map(|binding| binding.action)
+  }
+}
+
+/// Volumes from 0 to 1. Music and sound effects are also scaled by the master
+/// volume.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AudioConfig {
+  #[serde(default = "default_audio_volume")]
+  pub master_volume: f32,
+  #[serde(default = "default_audio_volume")]
+  pub music_volume: f32,
+  #[serde(default = "default_audio_volume")]
+  pub sfx_volume: f32,
+}
+
+impl Default for AudioConfig {
+  fn default() -> AudioConfig {
+    AudioConfig {
+      master_volume: default_audio_volume(),
+      music_volume: default_audio_volume(),
+      sfx_volume: default_audio_volume(),
+    }
+  }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Config {
   #[serde(default)]
   window: WindowConfig,
   #[serde(default)]
   event: EventConfig,
+  #[serde(default)]
+  input: InputConfig,
+  #[serde(default)]
+  audio: AudioConfig,
 }
 
 impl Config {
@@ -123,11 +278,19 @@ impl Config {
     let config = serde_json::from_str(json_str)?;
     Ok(config)
   }
+
+  pub fn input(&self) -> &InputConfig {
+    &self.input
+  }
+
+  pub fn audio(&self) -> &AudioConfig {
+    &self.audio
+  }
 }
 
 impl<'config> From<&'config Config> for piston_window::WindowSettings {
   fn from(config: &'config Config) -> piston_window::WindowSettings {
-    let window_settings = piston_window::WindowSettings::new(
+    piston_window::WindowSettings::new(
       config.window.title.as_str(),
       piston_window::Size {
         width: config.window.size.width,
@@ -141,9 +304,7 @@ impl<'config> From<&'config Config> for piston_window::WindowSettings {
       .srgb(config.window.srgb)
       .resizable(config.window.resizable)
       .decorated(config.window.decorated)
-      .controllers(config.window.controllers);
-
-    window_settings
+      .controllers(config.window.controllers)
   }
 }
 
@@ -181,3 +342,49 @@ impl<'config> std::convert::TryFrom<&'config Config>
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn axis(axis: u8, position: f64) -> piston_window::ControllerAxisArgs {
+    piston_window::ControllerAxisArgs { id: 0, axis, position }
+  }
+
+  #[test]
+  fn keys_and_buttons_map_to_actions() {
+    let input = InputConfig::default();
+    let key = piston_window::Button::Keyboard(piston_window::Key::Space);
+    assert_eq!(input.button_action(&key), Some(Action::Interact));
+    let button = piston_window::Button::Controller(
+      piston_window::ControllerButton { id: 3, button: 13 });
+    assert_eq!(input.button_action(&button), Some(Action::MoveLeft));
+    let unbound = piston_window::Button::Keyboard(piston_window::Key::Q);
+    assert_eq!(input.button_action(&unbound), None);
+  }
+
+  #[test]
+  fn axes_map_to_actions_past_the_dead_zone() {
+    let input = InputConfig::default();
+    assert_eq!(input.axis_action(&axis(0, 0.4)), None);
+    assert_eq!(input.axis_action(&axis(0, -0.4)), None);
+    assert_eq!(input.axis_action(&axis(0, 0.6)), Some(Action::MoveRight));
+    assert_eq!(input.axis_action(&axis(0, -0.6)), Some(Action::MoveLeft));
+    assert_eq!(input.axis_action(&axis(1, 1.0)), Some(Action::MoveDown));
+    assert_eq!(input.axis_action(&axis(5, 1.0)), None);
+  }
+
+  #[test]
+  fn bindings_come_from_the_config_file() {
+    let config = Config::from_json_str(r#"{"input": {
+      "bindings": [{"action": "Interact", "input": {"Key": "E"}}],
+      "axis_dead_zone": 0.9
+    }}"#).unwrap();
+    let input = config.input();
+    let e = piston_window::Button::Keyboard(piston_window::Key::E);
+    assert_eq!(input.button_action(&e), Some(Action::Interact));
+    let space = piston_window::Button::Keyboard(piston_window::Key::Space);
+    assert_eq!(input.button_action(&space), None);
+    assert_eq!(input.axis_action(&axis(0, 0.6)), None);
+  }
+}
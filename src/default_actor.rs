@@ -6,43 +6,89 @@ extern crate graphics;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use animation;
 use assets;
 use entity;
 use error;
 use level;
 use sound;
 
-pub struct DefaultActor {
+pub struct DefaultActor<Texture>
+where
+  Texture: graphics::ImageSize,
+{
   name: String,
   position: entity::WorldPoint2,
   velocity: entity::WorldVector2,
   scale: f64,
   width: f64,
+  shape: Option<level::Shape>,
   text: String,
   text_time: f64,
   visible: bool,
   active: bool,
   sprite_id: uuid::Uuid,
-  scene: Rc<RefCell<sprite::Scene<piston_window::G2dTexture>>>,
-  asset: Rc<assets::ImageAsset>,
+  scene: Rc<RefCell<sprite::Scene<Texture>>>,
+  // A clip for each state, or without states, "open" plays the image
+  // forwards and "close" plays it backwards
+  animation: animation::Animation<Texture>,
   actor_type: level::ActorType,
   sound: String,
-  animating: bool,
-  frame: usize,
-  next_frame: f64,
+  sounds: Vec<String>, // waiting to be taken by take_sounds
   reversible: bool,
-  state: bool,
+  carryable: bool,
+  state: bool, // opened; only used without states
+  states: Vec<level::ActorState>,
+  current: usize, // index of the current state
+  state_time: f64, // seconds since the current state was entered
 }
 
-impl DefaultActor {
+impl<Texture> DefaultActor<Texture>
+where
+  Texture: graphics::ImageSize,
+{
   pub fn new(
     actor: &level::Actor,
-    assets: &assets::AssetMap,
-    scene: Rc<RefCell<sprite::Scene<piston_window::G2dTexture>>>,
-  ) -> DefaultActor {
-    let asset = assets.get(&actor.image)
-        .expect("Could not find asset").clone();
-    let texture = asset.frames.get(0).unwrap().texture.clone();
+    assets: &assets::AssetMap<Texture>,
+    scene: Rc<RefCell<sprite::Scene<Texture>>>,
+  ) -> error::Result<DefaultActor<Texture>> {
+    use error::ResultExt; // chain_err
+
+    check_states(actor).chain_err(|| format!("Failed to create {}", actor.name))?;
+
+    let asset = assets::get_asset(assets, &actor.image)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
+    let state_asset = |state: &level::ActorState| match state.image {
+      Some(ref image) => assets::get_asset(assets, image)
+        .chain_err(|| format!("Failed to create {}", actor.name)),
+      None => Ok(asset.clone()),
+    };
+    let mut animation = match actor.states.first() {
+      Some(first) => {
+        let mut animation = animation::Animation::new(
+          &first.name, state_asset(first)?, first.mode)?;
+        for state in actor.states.iter().skip(1) {
+          animation.add_clip(&state.name, state_asset(state)?, state.mode)?;
+        }
+        animation
+      },
+      None => {
+        let mut animation = animation::Animation::new(
+          "open", asset.clone(), animation::PlayMode::Once)?;
+        animation.add_clip("close", asset.clone(), animation::PlayMode::Reverse)?;
+        // Props wait on their first frame until something opens them
+        animation.stop();
+        animation
+      },
+    };
+    animation::add_frame_sounds(&mut animation, &actor.frame_sounds)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
+    let texture = animation.texture();
+
+    let active = match actor.states.first().and_then(|state| state.active) {
+      Some(active) => active,
+      None => actor.active,
+    };
 
     let mut sprite = sprite::Sprite::from_texture(texture);
 
@@ -51,31 +97,121 @@ impl DefaultActor {
 
     let id: uuid::Uuid = scene.borrow_mut().add_child(sprite);
 
-    DefaultActor {
+    Ok(DefaultActor {
       name: actor.name.to_owned(),
       position: entity::WorldPoint2::new(actor.position.x, actor.position.y),
       velocity: entity::WorldVector2::new(0.0, 0.0),
       scale: actor.scale,
-      width: (actor.width as f64) * actor.scale,
+      width: f64::from(actor.width) * actor.scale,
+      shape: actor.shape.clone(),
       text: String::from(""),
       text_time: 0.0,
       visible: actor.visible,
-      active: actor.active,
+      active,
       sprite_id: id,
-      scene: scene,
-      asset: asset.clone(),
+      scene,
+      animation,
       actor_type: actor.actor_type.to_owned(),
       sound: actor.sound.to_owned(),
-      animating: false,
-      frame: 0,
-      next_frame: 0.0,
+      sounds: Vec::new(),
       reversible: actor.reversible,
+      carryable: actor.carryable,
       state: false,
+      states: actor.states.clone(),
+      current: 0,
+      state_time: 0.0,
+    })
+  }
+
+  // Go to the state called name, starting its animation over
+  fn enter(&mut self, name: &str) -> error::Result<()> {
+    let index = state_index(&self.states, name)?;
+    self.current = index;
+    self.state_time = 0.0;
+    self.animation.stop();
+    self.animation.play(name)?;
+
+    let state = &self.states[index];
+    if !state.sound.is_empty() {
+      self.sounds.push(state.sound.clone());
+    }
+    if let Some(active) = state.active {
+      self.active = active;
+    }
+    Ok(())
+  }
+
+  // Take the current state's first transition on event, if it has one
+  fn transition(&mut self, event: level::StateEvent) -> error::Result<()> {
+    if self.states.is_empty() {
+      return Ok(());
+    }
+    let to = self.states[self.current].transitions.iter()
+      .find(|transition| transition.on == event)
+      .map(|transition| transition.to.clone());
+    match to {
+      Some(to) => self.enter(&to),
+      None => Ok(()),
+    }
+  }
+
+  // Take the current state's first timer transition that's run out, if any
+  fn check_timers(&mut self, dt: f64) -> error::Result<()> {
+    if self.states.is_empty() {
+      return Ok(());
+    }
+    self.state_time += dt;
+    let state_time = self.state_time;
+    let to = self.states[self.current].transitions.iter()
+      .find(|transition| match transition.on {
+        level::StateEvent::Timer(seconds) => state_time >= seconds,
+        _ => false,
+      })
+      .map(|transition| transition.to.clone());
+    match to {
+      Some(to) => self.enter(&to),
+      None => Ok(()),
     }
   }
 }
 
-impl entity::Actor for DefaultActor {
+/// Make sure an actor's states have unique names, only go to states that
+/// exist, and that its frame sounds are on clips it has.
+pub fn check_states(actor: &level::Actor) -> error::Result<()> {
+  for (i, state) in actor.states.iter().enumerate() {
+    if actor.states[..i].iter().any(|other| other.name == state.name) {
+      return Err(error::Error::from(format!("More than one state named {}", state.name)));
+    }
+    for transition in state.transitions.iter() {
+      state_index(&actor.states, &transition.to)
+        .map_err(|_| error::Error::from(
+          format!("State {} goes to unknown state {}", state.name, transition.to)))?;
+    }
+  }
+  for frame_sound in actor.frame_sounds.iter() {
+    let known = if actor.states.is_empty() {
+      frame_sound.clip == "open" || frame_sound.clip == "close"
+    } else {
+      state_index(&actor.states, &frame_sound.clip).is_ok()
+    };
+    if !known {
+      return Err(error::Error::from(
+        format!("Sound {} is on unknown clip {}", frame_sound.sound, frame_sound.clip)));
+    }
+  }
+  Ok(())
+}
+
+fn state_index(states: &[level::ActorState], name: &str) -> error::Result<usize> {
+  states.iter()
+    .position(|state| state.name == name)
+    .ok_or_else(|| error::Error::from(format!("No state named {}", name)))
+}
+
+impl<Texture> entity::Actor for DefaultActor<Texture>
+where
+  Texture: graphics::ImageSize,
+{
   fn name(&self) -> String {
     self.name.clone()
   }
@@ -108,6 +244,14 @@ impl entity::Actor for DefaultActor {
     self.width
   }
 
+  fn carryable(&self) -> bool {
+    self.carryable
+  }
+
+  fn shape(&self) -> Option<&level::Shape> {
+    self.shape.as_ref()
+  }
+
   fn text(&self) -> &String {
     &self.text
   }
@@ -147,6 +291,13 @@ impl entity::Actor for DefaultActor {
     Ok(())
   }
 
+  fn set_rotation(&mut self, degrees: f64) -> error::Result<()> {
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_rotation(degrees);
+    }
+    Ok(())
+  }
+
   fn set_visible(&mut self, visible: bool) -> error::Result<()> {
     self.visible = visible;
     if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
@@ -160,49 +311,79 @@ impl entity::Actor for DefaultActor {
     Ok(())
   }
 
+  fn frame(&self) -> usize {
+    self.animation.frame()
+  }
+
+  fn machine_state(&self) -> Option<String> {
+    self.states.get(self.current).map(|state| state.name.clone())
+  }
+
+  fn set_machine_state(&mut self, name: &str) -> error::Result<()> {
+    self.current = state_index(&self.states, name)?;
+    self.state_time = 0.0;
+    self.animation.stop();
+    self.animation.play(name)
+  }
+
+  fn disturbed(&self) -> bool {
+    if !self.states.is_empty() {
+      return self.current != 0;
+    }
+    self.state && !self.animation.is_playing()
+  }
+
+  fn revert(&mut self) -> error::Result<()> {
+    if !self.states.is_empty() {
+      let first = self.states[0].name.clone();
+      return self.enter(&first);
+    }
+    // Closing works even on props the hero can't close again
+    if self.disturbed() {
+      self.animation.play("close")?;
+      if !self.sound.is_empty() {
+        self.sounds.push(self.sound.clone());
+      }
+    }
+    Ok(())
+  }
+
+  fn set_frame(&mut self, frame: usize) -> error::Result<()> {
+    self.animation.set_frame(frame);
+    if self.states.is_empty() {
+      self.animation.stop();
+      // A prop resting on its last frame has already been opened.
+      let frame = self.animation.frame();
+      self.state = frame > 0 && frame + 1 == self.animation.frame_count();
+    }
+
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_texture(self.animation.texture());
+    }
+    Ok(())
+  }
+
   fn on_update(&mut self, update_args: &piston_window::UpdateArgs) -> error::Result<()> {
     let new_position = self.position + self.velocity * update_args.dt;
     self.set_position(new_position)?;
 
-    // update time to next frame
-    if self.animating {
-      self.next_frame -= update_args.dt;
-
-      if self.next_frame <= 0.0 {
-        // if it's time for the next frame, get the asset
-        let asset = &self.asset;
-
-        // get the index of the next frame
-        if ! self.state {
-          self.frame += 1;
-        } else {
-          if self.frame > 0 {
-            self.frame -= 1;
-          }
-        }
-
-        // If this is the last frame, stop animation
-        if self.frame + 1 >= asset.frames.len() || self.frame <= 0 {
-          self.animating = false;
+    for event in self.animation.update(update_args.dt) {
+      match event {
+        animation::Event::Finished(_) if !self.states.is_empty() => {
+          self.transition(level::StateEvent::AnimationFinished)?;
+        },
+        // Done opening or closing
+        animation::Event::Finished(_) => {
           self.state = !self.state;
           self.active = !self.active;
-        }
-
-        // Clamp frame number to within bounds
-        if self.frame >= asset.frames.len() {
-          self.frame = asset.frames.len() - 1;
-        }
-
-        //// Get the next frame
-        let frame = asset.frames.get(self.frame).unwrap();
-
-        // Set the frame time and the update the sprite
-        self.next_frame += frame.frame_time;
-        if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
-            sprite.set_texture(frame.texture.clone());
-        }
+        },
+        animation::Event::Frame { name, .. } => self.sounds.push(name),
       }
     }
+    self.check_timers(update_args.dt)?;
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_texture(self.animation.texture());
+    }
 
     // Reset text after timeout
     if self.text_time > 0.0 {
@@ -214,15 +395,126 @@ impl entity::Actor for DefaultActor {
     Ok(())
   }
 
+  fn take_sounds(&mut self) -> Vec<String> {
+    self.sounds.drain(..).collect()
+  }
+
   fn interact_hero(&mut self, sounds: &mut sound::SoundEffects) {
-    if ! self.animating {
-      if ! self.state || self.reversible {
-        self.animating = true;
-        sounds.play(&self.sound);
+    if !self.states.is_empty() {
+      if let Err(e) = self.transition(level::StateEvent::HeroInteract) {
+        println!("{} can't change state: {}", self.name, e);
+      }
+      return;
+    }
+    if ! self.animation.is_playing() && (! self.state || self.reversible) {
+      let clip = if self.state { "close" } else { "open" };
+      match self.animation.play(clip) {
+        Ok(()) => {
+          sounds.play_at(&self.sound, self.position);
+        },
+        Err(e) => println!("{} can't {}: {}", self.name, clip, e),
       }
     }
   }
 
   fn interact_detective(&mut self) {
+    if let Err(e) = self.transition(level::StateEvent::DetectiveOverlap) {
+      println!("{} can't change state: {}", self.name, e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  extern crate serde_json;
+
+  use super::*;
+  use entity::Actor;
+
+  fn make_actor(states: &str) -> DefaultActor<assets::NullTexture> {
+    let level = level::Level::from_path_str("assets/levels/sample.json").unwrap();
+    let mut actor = level.actors.iter().find(|actor| actor.name == "car").unwrap().clone();
+    actor.states = serde_json::from_str(states).unwrap();
+    let assets = assets::load_null_assets().unwrap();
+    let scene = Rc::new(RefCell::new(sprite::Scene::new()));
+    DefaultActor::new(&actor, &assets, scene).unwrap()
+  }
+
+  fn update(actor: &mut DefaultActor<assets::NullTexture>, dt: f64) {
+    actor.on_update(&piston_window::UpdateArgs { dt }).unwrap();
+  }
+
+  const DOOR: &str = r#"[
+    {"name": "closed", "transitions": [{"on": "HeroInteract", "to": "opening"}]},
+    {"name": "opening", "sound": "car_horn", "active": false,
+     "transitions": [{"on": "AnimationFinished", "to": "open"}]},
+    {"name": "open", "mode": "Loop", "transitions": [
+      {"on": "DetectiveOverlap", "to": "closed"},
+      {"on": {"Timer": 2.0}, "to": "closed"}
+    ]}
+  ]"#;
+
+  #[test]
+  fn states_change_on_their_transitions_events() {
+    let mut actor = make_actor(DOOR);
+    let mut sounds = sound::SoundEffects::muted();
+    assert_eq!(actor.machine_state(), Some(String::from("closed")));
+    assert!(actor.active());
+
+    // Closed doesn't care about the detective
+    actor.interact_detective();
+    assert_eq!(actor.machine_state(), Some(String::from("closed")));
+
+    actor.interact_hero(&mut sounds);
+    assert_eq!(actor.machine_state(), Some(String::from("opening")));
+    assert!(!actor.active());
+    assert_eq!(actor.take_sounds(), vec![String::from("car_horn")]);
+
+    for _ in 0..100 {
+      update(&mut actor, 0.1);
+      if actor.machine_state() != Some(String::from("opening")) {
+        break;
+      }
+    }
+    assert_eq!(actor.machine_state(), Some(String::from("open")));
+
+    actor.interact_detective();
+    assert_eq!(actor.machine_state(), Some(String::from("closed")));
+  }
+
+  #[test]
+  fn timers_count_from_entering_the_state() {
+    let mut actor = make_actor(DOOR);
+    actor.set_machine_state("open").unwrap();
+    update(&mut actor, 1.5);
+    assert_eq!(actor.machine_state(), Some(String::from("open")));
+    update(&mut actor, 1.0);
+    assert_eq!(actor.machine_state(), Some(String::from("closed")));
+  }
+
+  #[test]
+  fn broken_state_machines_are_rejected() {
+    let level = level::Level::from_path_str("assets/levels/sample.json").unwrap();
+    let mut actor = level.actors[0].clone();
+    actor.states = serde_json::from_str(DOOR).unwrap();
+    assert!(check_states(&actor).is_ok());
+
+    actor.states[2].transitions[0].to = String::from("ajar");
+    assert!(check_states(&actor).is_err());
+    actor.states[2].transitions[0].to = String::from("closed");
+
+    actor.frame_sounds.push(level::FrameSound {
+      clip: String::from("open"),
+      frame: 0,
+      sound: String::from("car_horn"),
+    });
+    assert!(check_states(&actor).is_ok());
+    actor.frame_sounds[0].clip = String::from("slam");
+    assert!(check_states(&actor).is_err());
+
+    actor.frame_sounds.clear();
+    let closed = actor.states[0].clone();
+    actor.states.push(closed);
+    assert!(check_states(&actor).is_err());
   }
 }
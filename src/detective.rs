@@ -1,3 +1,4 @@
+extern crate ai_behavior;
 extern crate piston_window;
 extern crate sprite;
 extern crate uuid;
@@ -6,41 +7,97 @@ extern crate graphics;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use animation;
 use assets;
 use entity;
 use error;
 use level;
+use save;
 use sound;
 
-type Texture = piston_window::G2dTexture;
-type SceneRcRef = Rc<RefCell<sprite::Scene<Texture>>>;
-pub type DetectiveRcRef = Rc<RefCell<Detective>>;
+type SceneRcRef<Texture> = Rc<RefCell<sprite::Scene<Texture>>>;
+pub type DetectiveRcRef<Texture> = Rc<RefCell<Detective<Texture>>>;
 
-#[derive(Copy, Clone)]
-enum DetectiveState {
+/// How far ahead of him the detective notices items being put down, in
+/// pixels.
+const ITEM_NOTICE_DISTANCE: f64 = 600.0;
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum DetectiveState {
     Idle,
     Walk,
     Clue,
 }
 
-pub struct Detective {
+/// The detective's behavior when the level doesn't give one: wait for the
+/// hero (or the ghost) to get him moving, then walk back and forth inspecting
+/// clues until he finds the macguffin.
+pub fn default_behavior() -> level::Behavior {
+  use level::Behavior::{Action, Select, Sequence, WhenAny, While};
+  use level::DetectiveAction::*;
+
+  Sequence(vec![
+    WhenAny(vec![
+      Action(WaitForHero),
+      Sequence(vec![Action(WaitForGhost), Action(Flee)]),
+    ]),
+    While(Box::new(Action(WaitForMacguffin)), vec![
+      Action(Wander),
+      Select(vec![
+        Sequence(vec![Action(SeesGhost), Action(Flee)]),
+        Sequence(vec![Action(HitObstacle), Action(TurnAround)]),
+        Sequence(vec![Action(FoundClue), Action(Inspect)]),
+      ]),
+    ]),
+    Action(Idle),
+  ])
+}
+
+fn to_ai_behavior(behavior: &level::Behavior) -> ai_behavior::Behavior<level::DetectiveAction> {
+  let all = |behaviors: &Vec<level::Behavior>| behaviors.iter().map(to_ai_behavior).collect();
+  match *behavior {
+    level::Behavior::Action(ref action) => ai_behavior::Behavior::Action(action.clone()),
+    level::Behavior::Wait(seconds) => ai_behavior::Behavior::Wait(seconds),
+    level::Behavior::WaitForever => ai_behavior::Behavior::WaitForever,
+    level::Behavior::Fail(ref behavior) => {
+      ai_behavior::Behavior::Fail(Box::new(to_ai_behavior(behavior)))
+    },
+    level::Behavior::AlwaysSucceed(ref behavior) => {
+      ai_behavior::Behavior::AlwaysSucceed(Box::new(to_ai_behavior(behavior)))
+    },
+    level::Behavior::Select(ref behaviors) => ai_behavior::Behavior::Select(all(behaviors)),
+    level::Behavior::Sequence(ref behaviors) => ai_behavior::Behavior::Sequence(all(behaviors)),
+    level::Behavior::While(ref condition, ref behaviors) => {
+      ai_behavior::Behavior::While(Box::new(to_ai_behavior(condition)), all(behaviors))
+    },
+    level::Behavior::WhenAll(ref behaviors) => ai_behavior::Behavior::WhenAll(all(behaviors)),
+    level::Behavior::WhenAny(ref behaviors) => ai_behavior::Behavior::WhenAny(all(behaviors)),
+  }
+}
+
+// Walking speed the walk frames are drawn for
+const WALK_ANIMATION_SPEED: f64 = 100.0;
+
+pub struct Detective<Texture>
+where
+  Texture: graphics::ImageSize,
+{
   name: String,
   position: entity::WorldPoint2,
   scale: f64,
   speed: f64,
   width: f64,
+  shape: Option<level::Shape>,
   text: String,
   text_time: f64,
   visible: bool,
   active: bool,
   sprite_id: uuid::Uuid,
-  scene: SceneRcRef,
-  idle: Rc<assets::ImageAsset>, // asset for idle animation
-  walk: Rc<assets::ImageAsset>, // asset for walk animation
-  clue: Rc<assets::ImageAsset>, // asset for clue animation
+  scene: SceneRcRef<Texture>,
+  // "idle" and "walk" loop, "clue" plays once
+  animation: animation::Animation<Texture>,
+  sounds: Vec<String>, // waiting to be taken by take_sounds
   clue_sound: String,
-  frame: usize,
-  next_frame: f64,
   // The detective's state machine is tied to his animations; code may set
   // next_state, but he won't progress to that state until his current animation
   // is done
@@ -51,32 +108,51 @@ pub struct Detective {
   direction: bool,
   found_macguffin: bool,
   done: bool,
+  walk_bounds: (f64, f64), // x positions the detective turns around at
+  // Behavior tree deciding what the detective does next; taken out of the
+  // Option while it runs
+  behavior: Option<ai_behavior::State<level::DetectiveAction, ()>>,
+  // What the detective noticed since his behavior tree last ran
+  hero_interacted: bool,
+  ghost_seen: bool,
+  obstacle_hit: bool,
+  clue_found: bool,
+  item_dropped: bool,
+  // Whether the last clue found was the macguffin
+  clue_is_macguffin: bool,
+  awake: bool, // the hero has interacted with the detective at least once
+  inspecting: bool,
+  clue_done: bool, // the clue animation finished since inspecting started
 }
 
 
-impl Detective {
+impl<Texture> Detective<Texture>
+where
+  Texture: graphics::ImageSize,
+{
   pub fn new(
     actor: &level::Detective,
-    assets: &assets::AssetMap,
-    scene: SceneRcRef,
-  ) -> Detective {
+    assets: &assets::AssetMap<Texture>,
+    scene: SceneRcRef<Texture>,
+  ) -> error::Result<Detective<Texture>> {
+    use error::ResultExt; // chain_err
+
     // Get the idle asset and add it to our internal state to asset map
-    let idle = assets.get(&actor.idle)
-        .expect("Could not find asset")
-        .clone();
-    let walk = assets.get(&actor.walk)
-        .expect("Could not find asset")
-        .clone();
-    let clue = assets.get(&actor.clue)
-        .expect("Could not find asset")
-        .clone();
-
-    // Set the current state and remaining frame time
-    let frame : usize = 0;
-    let frame0 = idle.frames.get(0).unwrap();
-    let next_frame = frame0.frame_time;
-
-    let hero_texture = frame0.texture.clone();
+    let idle = assets::get_asset(assets, &actor.idle)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
+    let walk = assets::get_asset(assets, &actor.walk)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
+    let clue = assets::get_asset(assets, &actor.clue)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
+
+    let mut animation = animation::Animation::new(
+      "idle", idle, animation::PlayMode::Loop)?;
+    animation.add_clip("walk", walk, animation::PlayMode::Loop)?;
+    animation.add_clip("clue", clue, animation::PlayMode::Once)?;
+    animation::add_frame_sounds(&mut animation, &actor.frame_sounds)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
+
+    let hero_texture = animation.texture();
 
     let mut hero_sprite = sprite::Sprite::from_texture(hero_texture);
 
@@ -85,24 +161,24 @@ impl Detective {
 
     let hero_id: uuid::Uuid = scene.borrow_mut().add_child(hero_sprite);
 
-    Detective {
+    let behavior = actor.behavior.clone().unwrap_or_else(default_behavior);
+
+    Ok(Detective {
       name: actor.name.to_owned(),
       position: entity::WorldPoint2::new(actor.position.x, actor.position.y),
       scale: actor.scale,
-      width: (actor.width as f64) * actor.scale,
+      width: f64::from(actor.width) * actor.scale,
+      shape: actor.shape.clone(),
       text: String::from(""),
       text_time: 0.0,
       speed: actor.speed,
       visible: true,
       active: true,
       sprite_id: hero_id,
-      scene: scene,
-      idle: idle.clone(),
-      walk,
-      clue,
+      scene,
+      animation,
+      sounds: Vec::new(),
       clue_sound: actor.clue_sound.to_owned(),
-      frame,
-      next_frame,
       state: DetectiveState::Idle,
       next_state: DetectiveState::Idle,
       last_obstacle: String::from(""),
@@ -110,14 +186,26 @@ impl Detective {
       direction: true,
       found_macguffin: false,
       done: false,
-    }
+      walk_bounds: (::std::f64::MIN, ::std::f64::MAX),
+      behavior: Some(ai_behavior::State::new(to_ai_behavior(&behavior))),
+      hero_interacted: false,
+      ghost_seen: false,
+      obstacle_hit: false,
+      clue_found: false,
+      item_dropped: false,
+      clue_is_macguffin: false,
+      awake: false,
+      inspecting: false,
+      clue_done: false,
+    })
   }
 
-  pub fn interact_entity(&mut self, actor: &entity::Actor, sounds: &mut sound::SoundEffects) {
-    // How can the detective interact with things?
-    //  - barrier: detective turns around and walks the other way
-    //  - clue: detective stops, inspects
-    //    - if this is the macguffin, trigger level completion
+  pub fn interact_entity(&mut self, actor: &entity::Actor, _sounds: &mut sound::SoundEffects) {
+    // The detective only notices things here; what he does about them is up
+    // to his behavior tree
+    //  - barrier: the default tree turns around and walks the other way
+    //  - clue: the default tree stops and inspects
+    //    - if this is the macguffin, inspecting it triggers level completion
     //    - if this isn't the macguffin, detective continues moving
     match actor.actor_type() {
       // Do nothing for static actors
@@ -127,36 +215,168 @@ impl Detective {
           println!("Detective hit obstacle {}!", actor.name());
           self.last_obstacle = actor.name();
 
-          // if this obstacle is "active", walk the other way
+          // only "active" obstacles are in the way
           if actor.active() {
-            let dir = !self.direction;
-            self.set_direction(dir);
-            self.last_clue = String::from("");
+            self.obstacle_hit = true;
           }
         }
       },
       level::ActorType::Clue(macguffin) => {
         if self.last_clue != actor.name() && actor.active() {
           self.last_clue = actor.name();
-          self.next_state = DetectiveState::Clue;
-          sounds.play(&self.clue_sound);
+          self.clue_found = true;
+          self.clue_is_macguffin = macguffin;
+        }
+      }
+    }
+  }
+
+  /// Note that the detective has seen the materialized ghost.
+  pub fn see_ghost(&mut self) {
+    self.ghost_seen = true;
+  }
+
+  /// Note that an item was put down. If it's in front of the detective he
+  /// notices, and inspects it when he gets there even if he already has.
+  pub fn notice_drop(&mut self, item: &entity::Actor) -> error::Result<()> {
+    use entity::Actor;
+    let ahead = if self.direction {
+      item.position().x - self.position.x
+    } else {
+      self.position.x - item.position().x
+    };
+    if ahead < 0.0 || ahead > ITEM_NOTICE_DISTANCE {
+      return Ok(());
+    }
 
-          if macguffin {
+    println!("Detective noticed {} being dropped!", item.name());
+    self.item_dropped = true;
+    if self.last_clue == item.name() {
+      self.last_clue = String::from("");
+    }
+    self.set_text(String::from("What was that?"), 2.0)
+  }
+
+  /// Run the detective's behavior tree against whatever he noticed during
+  /// this update.
+  #[allow(clippy::trivially_copy_pass_by_ref)]
+  pub fn think(
+    &mut self,
+    update_args: &piston_window::UpdateArgs,
+    sounds: &mut sound::SoundEffects,
+  ) -> error::Result<()> {
+    let event: piston_window::Event = update_args.clone().into();
+    // The tree can't return errors, so the first one stops the action it came
+    // from and is returned once the tree is done
+    let mut result = Ok(());
+    if let Some(mut behavior) = self.behavior.take() {
+      behavior.event(&event, &mut |args: ai_behavior::ActionArgs<piston_window::Event, level::DetectiveAction, ()>| {
+        match self.run_action(args.action, args.dt, sounds) {
+          Ok(status) => status,
+          Err(e) => {
+            if result.is_ok() {
+              result = Err(e);
+            }
+            (ai_behavior::Status::Failure, args.dt)
+          },
+        }
+      });
+      self.behavior = Some(behavior);
+    }
+
+    self.hero_interacted = false;
+    self.ghost_seen = false;
+    self.obstacle_hit = false;
+    self.clue_found = false;
+    self.item_dropped = false;
+    result
+  }
+
+  fn run_action(
+    &mut self,
+    action: &level::DetectiveAction,
+    dt: f64,
+    sounds: &mut sound::SoundEffects,
+  ) -> error::Result<(ai_behavior::Status, f64)> {
+    use self::ai_behavior::Status::{Failure, Running, Success};
+    use level::DetectiveAction::*;
+
+    // Conditions finish immediately, waits keep running until they're true.
+    // Actions that deal with something the detective noticed forget it, so a
+    // Wander loop waits for the next thing instead of going round forever.
+    let check = |condition: bool| if condition { (Success, dt) } else { (Failure, dt) };
+    let wait = |condition: bool| if condition { (Success, dt) } else { (Running, 0.0) };
+
+    let status = match *action {
+      Idle => {
+        self.next_state = DetectiveState::Idle;
+        (Running, 0.0)
+      },
+      Walk => {
+        self.next_state = DetectiveState::Walk;
+        (Running, 0.0)
+      },
+      Wander => {
+        self.next_state = DetectiveState::Walk;
+        wait(self.ghost_seen || self.obstacle_hit || self.clue_found)
+      },
+      TurnAround => {
+        self.obstacle_hit = false;
+        let dir = !self.direction;
+        self.set_direction(dir);
+        self.last_clue = String::from("");
+        (Success, dt)
+      },
+      Inspect => {
+        if !self.inspecting {
+          self.clue_found = false;
+          self.inspecting = true;
+          self.clue_done = false;
+          self.next_state = DetectiveState::Clue;
+          sounds.play_at(&self.clue_sound, self.position);
+        }
+        if self.clue_done {
+          self.inspecting = false;
+          if self.clue_is_macguffin {
             println!("Detective found the macguffin!");
             self.found_macguffin = true;
           }
+          (Success, dt)
+        } else {
+          (Running, 0.0)
         }
-      }
-    }
+      },
+      Flee => {
+        self.ghost_seen = false;
+        self.run_away()?;
+        (Success, dt)
+      },
+      WaitForHero => wait(self.awake),
+      WaitForGhost => wait(self.ghost_seen),
+      WaitForMacguffin => wait(self.found_macguffin),
+      HeroInteracted => check(self.hero_interacted),
+      SeesGhost => check(self.ghost_seen),
+      HitObstacle => check(self.obstacle_hit),
+      FoundClue => check(self.clue_found),
+      ItemDropped => check(self.item_dropped),
+    };
+    Ok(status)
   }
 
-  pub fn run_away(&mut self) {
+  pub fn run_away(&mut self) -> error::Result<()> {
     use entity::Actor;
+    self.inspecting = false;
     self.last_obstacle = String::from("");
     let dir = !self.direction;
     self.set_direction(dir);
     self.next_state = DetectiveState::Walk;
-    self.set_text(String::from("Aaaaah!!!"), 2.0).expect("Failed setting text");
+    self.set_text(String::from("Aaaaah!!!"), 2.0)
+  }
+
+  /// Turn the detective around whenever he walks left of min_x or right of
+  /// max_x.
+  pub fn set_walk_bounds(&mut self, min_x: f64, max_x: f64) {
+    self.walk_bounds = (min_x, max_x);
   }
 
   pub fn set_direction(&mut self, dir: bool) {
@@ -169,10 +389,64 @@ impl Detective {
   pub fn done(&self) -> bool {
     self.done
   }
+
+  pub fn save(&self) -> save::Detective {
+    save::Detective {
+      state: self.state,
+      next_state: self.next_state,
+      last_obstacle: self.last_obstacle.clone(),
+      last_clue: self.last_clue.clone(),
+      found_macguffin: self.found_macguffin,
+      direction: self.direction,
+      done: self.done,
+      next_frame: self.animation.next_frame(),
+      awake: self.awake,
+      behavior: self.behavior.clone(),
+      clue_is_macguffin: self.clue_is_macguffin,
+      inspecting: self.inspecting,
+      clue_done: self.clue_done,
+    }
+  }
+
+  /// Wait seconds before the next animation frame, e.g. when restoring a save.
+  pub fn set_next_frame(&mut self, seconds: f64) {
+    self.animation.set_next_frame(seconds);
+  }
+
+  pub fn restore(&mut self, saved: &save::Detective) -> error::Result<()> {
+    self.state = saved.state;
+    self.next_state = saved.next_state;
+    self.last_obstacle = saved.last_obstacle.clone();
+    self.last_clue = saved.last_clue.clone();
+    self.found_macguffin = saved.found_macguffin;
+    self.done = saved.done;
+    self.awake = saved.awake;
+    // Saves from before behavior trees were saved start the tree over
+    if saved.behavior.is_some() {
+      self.behavior = saved.behavior.clone();
+    }
+    self.clue_is_macguffin = saved.clue_is_macguffin;
+    self.inspecting = saved.inspecting;
+    self.clue_done = saved.clue_done;
+    self.set_direction(saved.direction);
+    self.animation.play(clip(self.state))
+  }
+}
+
+// The animation clip for state
+fn clip(state: DetectiveState) -> &'static str {
+  match state {
+    DetectiveState::Idle => "idle",
+    DetectiveState::Walk => "walk",
+    DetectiveState::Clue => "clue",
+  }
 }
 
 
-impl entity::Actor for Detective {
+impl<Texture> entity::Actor for Detective<Texture>
+where
+  Texture: graphics::ImageSize,
+{
   fn name(&self) -> String {
     self.name.clone()
   }
@@ -205,6 +479,10 @@ impl entity::Actor for Detective {
     self.width
   }
 
+  fn shape(&self) -> Option<&level::Shape> {
+    self.shape.as_ref()
+  }
+
   fn direction(&self) -> bool {
     self.direction
   }
@@ -246,6 +524,18 @@ impl entity::Actor for Detective {
     Ok(())
   }
 
+  fn frame(&self) -> usize {
+    self.animation.frame()
+  }
+
+  fn set_frame(&mut self, frame: usize) -> error::Result<()> {
+    self.animation.set_frame(frame);
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_texture(self.animation.texture());
+    }
+    Ok(())
+  }
+
   fn text(&self) -> &String {
     &self.text
   }
@@ -257,46 +547,37 @@ impl entity::Actor for Detective {
 
   fn on_update(&mut self, update_args: &piston_window::UpdateArgs) -> error::Result<()> {
     // motion update if detective is in walking state
-    match self.state {
-      DetectiveState::Walk => {
-        let velocity = if self.direction {
-          entity::WorldVector2::new(self.speed, 0.0)
-        } else {
-          entity::WorldVector2::new(-self.speed, 0.0)
-        };
-        let new_position = self.position + velocity * update_args.dt;
-        self.set_position(new_position)?;
-      },
-      _ => (),
+    if let DetectiveState::Walk = self.state {
+      // Step in time with the ground going by
+      self.animation.set_speed(self.speed / WALK_ANIMATION_SPEED);
+      let velocity = if self.direction {
+        entity::WorldVector2::new(self.speed, 0.0)
+      } else {
+        entity::WorldVector2::new(-self.speed, 0.0)
+      };
+      let new_position = self.position + velocity * update_args.dt;
+      self.set_position(new_position)?;
+    } else {
+      self.animation.set_speed(1.0);
     }
 
-    // HACK(austin): keep the detective from wandering off screen
-    // TODO(austin): don't use hardcoded world bounds here
-    if self.position.x > 4800.0 {
+    // Keep the detective from wandering off screen
+    if self.position.x > self.walk_bounds.1 {
       self.set_direction(false);
       self.last_obstacle = String::from("");
       self.last_clue = String::from("");
-    } else if self.position.x < 150.0 {
+    } else if self.position.x < self.walk_bounds.0 {
       self.set_direction(true);
       self.last_obstacle = String::from("");
       self.last_clue = String::from("");
     }
 
-    // update time to next frame
-    self.next_frame -= update_args.dt;
-
-    if self.next_frame <= 0.0 {
-      // if it's time for the next frame, get the asset
-      let asset = match self.state {
-          DetectiveState::Idle => &self.idle,
-          DetectiveState::Walk => &self.walk,
-          DetectiveState::Clue => &self.clue,
-      };
-
-      // get the index of the next frame
-      self.frame += 1;
-      if self.frame >= asset.frames.len() {
-          self.frame = 0;
+    for event in self.animation.update(update_args.dt) {
+      match event {
+        animation::Event::Finished(_) => {
+          if let DetectiveState::Clue = self.state {
+            self.clue_done = true;
+          }
           // Transition to next state
           self.state = self.next_state;
 
@@ -308,32 +589,17 @@ impl entity::Actor for Detective {
                 DetectiveState::Idle
               }
               DetectiveState::Walk => DetectiveState::Walk,
-              DetectiveState::Clue => {
-                if self.found_macguffin {
-                  DetectiveState::Idle
-                } else {
-                  DetectiveState::Walk
-                }
-              }
+              // the behavior tree decides where to go after a clue
+              DetectiveState::Clue => DetectiveState::Idle,
           };
-      }
-
-      // if it's time for the next frame, get the asset
-      let asset = match self.state {
-          DetectiveState::Idle => &self.idle,
-          DetectiveState::Walk => &self.walk,
-          DetectiveState::Clue => &self.clue,
-      };
-
-      // Get the next frame
-      let frame = asset.frames.get(self.frame).unwrap();
-
-      // Set the frame time and the update the sprite
-      self.next_frame += frame.frame_time;
-      if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
-          sprite.set_texture(frame.texture.clone());
+          self.animation.play(clip(self.state))?;
+        },
+        animation::Event::Frame { name, .. } => self.sounds.push(name),
       }
     }
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_texture(self.animation.texture());
+    }
 
     // Reset text after timeout
     if self.text_time > 0.0 {
@@ -345,9 +611,14 @@ impl entity::Actor for Detective {
     Ok(())
   }
 
+  fn take_sounds(&mut self) -> Vec<String> {
+    self.sounds.drain(..).collect()
+  }
+
   fn interact_hero(&mut self, _sounds: &mut sound::SoundEffects) {
     println!("Hero interacted with Detective!");
-    self.next_state = DetectiveState::Walk;
+    self.hero_interacted = true;
+    self.awake = true;
   }
 
   fn interact_detective(&mut self) {
@@ -0,0 +1,596 @@
+extern crate graphics;
+extern crate piston_window;
+extern crate serde_json;
+extern crate std;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use assets;
+use camera;
+use entity;
+use error;
+use font;
+use handler;
+use level;
+use rust_rider;
+
+/// How far the arrow keys move the camera each update, in screen pixels per
+/// second.
+const PAN_SPEED: f64 = 800.0;
+/// How much one notch of the scroll wheel zooms.
+const ZOOM_STEP: f64 = 1.1;
+/// How much one press changes an actor's scale, and its width.
+const SCALE_STEP: f64 = 0.1;
+const WIDTH_STEP: u32 = 10;
+
+const HELP_TEXT: &str = "Drag to move, right drag to pan, scroll to zoom. \
+T type, +/- scale, [/] width, R reversible, A active, V visible, C carryable, \
+PgUp/PgDn pick image, N new, Del delete, Ctrl+S save, Esc quit";
+
+/// Lays out a level with the mouse and saves it back to its file.
+///
+/// The level is shown with the same State the game uses, but never updated,
+/// so everything stays where it's put. Whenever an actor's definition changes
+/// the State is rebuilt from the edited level.
+pub struct Editor<Window>
+where
+  Window: piston_window::Window,
+{
+  path: String,
+  level: level::Level,
+  state: Option<rust_rider::State<piston_window::G2dTexture>>,
+  camera: camera::Camera2,
+  assets: assets::AssetMap<piston_window::G2dTexture>,
+  asset_names: Vec<String>, // sorted, for picking what to spawn
+  spawn_index: usize,
+  selection: Option<String>, // actor name
+  // The grabbed actor and where it was grabbed, relative to its position
+  drag: Option<(String, entity::WorldVector2)>,
+  panning: bool,
+  pan: [f64; 2], // direction the arrow keys are panning in
+  cursor: [f64; 2], // screen coordinates
+  control: bool,
+  message: String, // result of the last save, or the last error
+  back: Option<handler::Transition>,
+  transition: Option<handler::Transition>,
+  window: Rc<RefCell<piston_window::PistonWindow<Window>>>,
+  glyphs: font::GlyphsRcRef,
+}
+
+impl<Window> Editor<Window>
+where
+  Window: piston_window::Window,
+{
+  /// Create an Editor for the level at path.
+  pub fn new(
+    window: Rc<RefCell<piston_window::PistonWindow<Window>>>,
+    glyphs: font::GlyphsRcRef,
+    assets: assets::AssetMap<piston_window::G2dTexture>,
+    path: &str,
+  ) -> error::Result<Editor<Window>> {
+    let level = level::Level::from_path_str(path)?;
+    let mut asset_names: Vec<String> = assets.keys().cloned().collect();
+    asset_names.sort();
+
+    let mut camera = camera::Camera2::new();
+    camera.position = camera::WorldPoint2::new(level.hero.position.x, level.hero.position.y);
+
+    let mut editor = Editor {
+      path: String::from(path),
+      level,
+      state: None,
+      camera,
+      assets,
+      asset_names,
+      spawn_index: 0,
+      selection: None,
+      drag: None,
+      panning: false,
+      pan: [0.0, 0.0],
+      cursor: [0.0, 0.0],
+      control: false,
+      message: String::from(""),
+      back: None,
+      transition: None,
+      window,
+      glyphs,
+    };
+    editor.rebuild();
+    Ok(editor)
+  }
+
+  /// Where the pause key goes.
+  pub fn set_back(&mut self, back: handler::Transition) {
+    self.back = Some(back);
+  }
+
+  /// Save the level as JSON. Tiled maps are saved next to the map, since
+  /// they can't be written back.
+  pub fn save(&self) -> error::Result<String> {
+    let path = save_path(&self.path);
+    let file = std::fs::File::create(&path)?;
+    serde_json::to_writer_pretty(file, &self.level)?;
+    Ok(path.to_string_lossy().into_owned())
+  }
+
+  // Build the State again from the edited level
+  fn rebuild(&mut self) {
+    match rust_rider::State::load(self.level.clone(), &self.assets) {
+      Ok(state) => self.state = Some(state),
+      Err(e) => {
+        self.state = None;
+        self.message = format!("{}", e);
+      },
+    }
+  }
+
+  fn view_size(&self) -> [f64; 2] {
+    use piston_window::Window; // size
+
+    let size = self.window.borrow().size();
+    [f64::from(size.width), f64::from(size.height)]
+  }
+
+  fn cursor_world(&self) -> camera::WorldPoint2 {
+    self.camera.screen_to_world(self.cursor, self.view_size())
+  }
+
+  fn pick(&self, point: camera::WorldPoint2) -> Option<String> {
+    match self.state {
+      Some(ref state) => pick(&self.level, state, point),
+      None => None,
+    }
+  }
+
+  // Where the level says the actor called name is
+  fn position_mut(&mut self, name: &str) -> Option<&mut level::Point> {
+    if self.level.hero.name == name {
+      return Some(&mut self.level.hero.position);
+    }
+    if self.level.detective.name == name {
+      return Some(&mut self.level.detective.position);
+    }
+    if let Some(ref mut killer) = self.level.killer {
+      if killer.name == name {
+        return Some(&mut killer.position);
+      }
+    }
+    self.level.actors.iter_mut()
+      .find(|actor| actor.name == name)
+      .map(|actor| &mut actor.position)
+  }
+
+  fn selected_actor_mut(&mut self) -> Option<&mut level::Actor> {
+    let name = match self.selection {
+      Some(ref name) => name.to_owned(),
+      None => return None,
+    };
+    self.level.actors.iter_mut().find(|actor| actor.name == name)
+  }
+
+  // Change the selected actor's definition and show the change. The hero,
+  // detective and killer aren't Actors, so they can only be moved.
+  fn edit<F>(&mut self, change: F)
+  where F: FnOnce(&mut level::Actor)
+  {
+    let changed = match self.selected_actor_mut() {
+      Some(actor) => {
+        change(actor);
+        true
+      },
+      None => false,
+    };
+    if changed {
+      self.rebuild();
+    } else if let Some(ref name) = self.selection {
+      self.message = format!("{} can only be moved", name);
+    }
+  }
+
+  fn on_mouse_press(&mut self, button: piston_window::MouseButton) {
+    match button {
+      piston_window::MouseButton::Left => {
+        let point = self.cursor_world();
+        self.selection = self.pick(point);
+        self.drag = match (self.selection.clone(), self.state.as_ref()) {
+          (Some(name), Some(state)) => state.entities().get(&name).map(|entity| {
+            let position = entity.borrow().position();
+            (name, point - position)
+          }),
+          _ => None,
+        };
+      },
+      piston_window::MouseButton::Right => self.panning = true,
+      _ => {},
+    }
+  }
+
+  fn on_mouse_release(&mut self, button: piston_window::MouseButton) {
+    match button {
+      piston_window::MouseButton::Left => self.drag = None,
+      piston_window::MouseButton::Right => self.panning = false,
+      _ => {},
+    }
+  }
+
+  fn on_key_press(&mut self, key: piston_window::Key) {
+    use piston_window::Key;
+
+    match key {
+      Key::LCtrl | Key::RCtrl => self.control = true,
+      Key::Left => self.pan[0] = -1.0,
+      Key::Right => self.pan[0] = 1.0,
+      Key::Up => self.pan[1] = -1.0,
+      Key::Down => self.pan[1] = 1.0,
+      Key::S if self.control => {
+        self.message = match self.save() {
+          // The campaign refers to levels by path, so it won't see the JSON
+          // saved for a Tiled map until it's pointed at it
+          Ok(ref path) if *path != self.path => {
+            format!("Saved {}; the campaign still loads {}", path, self.path)
+          },
+          Ok(path) => format!("Saved {}", path),
+          Err(e) => format!("Failed to save: {}", e),
+        };
+      },
+      Key::T => self.edit(|actor| {
+        actor.actor_type = match actor.actor_type {
+          level::ActorType::Static => level::ActorType::Obstacle,
+          level::ActorType::Obstacle => level::ActorType::Clue(false),
+          level::ActorType::Clue(false) => level::ActorType::Clue(true),
+          level::ActorType::Clue(true) => level::ActorType::Static,
+        };
+      }),
+      Key::Equals | Key::NumPadPlus => self.edit(|actor| actor.scale += SCALE_STEP),
+      Key::Minus | Key::NumPadMinus => self.edit(|actor| {
+        actor.scale = (actor.scale - SCALE_STEP).max(SCALE_STEP);
+      }),
+      Key::RightBracket => self.edit(|actor| actor.width += WIDTH_STEP),
+      Key::LeftBracket => self.edit(|actor| {
+        actor.width = actor.width.saturating_sub(WIDTH_STEP).max(1);
+      }),
+      Key::R => self.edit(|actor| actor.reversible = !actor.reversible),
+      Key::A => self.edit(|actor| actor.active = !actor.active),
+      Key::V => self.edit(|actor| actor.visible = !actor.visible),
+      Key::C => self.edit(|actor| actor.carryable = !actor.carryable),
+      Key::PageUp if !self.asset_names.is_empty() => {
+        self.spawn_index = (self.spawn_index + self.asset_names.len() - 1) % self.asset_names.len();
+      },
+      Key::PageDown if !self.asset_names.is_empty() => {
+        self.spawn_index = (self.spawn_index + 1) % self.asset_names.len();
+      },
+      Key::N => self.spawn(),
+      Key::Delete | Key::Backspace => {
+        if let Some(name) = self.selection.take() {
+          self.drag = None;
+          let count = self.level.actors.len();
+          self.level.actors.retain(|actor| actor.name != name);
+          if self.level.actors.len() != count {
+            self.rebuild();
+          } else {
+            self.message = format!("{} can't be deleted", name);
+          }
+        }
+      },
+      Key::Escape => self.transition = self.back.clone(),
+      _ => {},
+    }
+  }
+
+  fn on_key_release(&mut self, key: piston_window::Key) {
+    use piston_window::Key;
+
+    match key {
+      Key::LCtrl | Key::RCtrl => self.control = false,
+      Key::Left | Key::Right => self.pan[0] = 0.0,
+      Key::Up | Key::Down => self.pan[1] = 0.0,
+      _ => {},
+    }
+  }
+
+  // Add an actor with the picked image under the cursor
+  fn spawn(&mut self) {
+    use self::graphics::ImageSize; // get_size
+
+    let image = match self.asset_names.get(self.spawn_index) {
+      Some(image) => image.to_owned(),
+      None => return,
+    };
+    let width = match self.assets.get(&image).and_then(|asset| asset.frames.first()) {
+      Some(frame) => frame.texture.get_size().0,
+      None => return,
+    };
+    let name = spawn_name(&self.level, &image);
+
+    let point = self.cursor_world();
+    self.level.actors.push(level::Actor {
+      name: name.to_owned(),
+      actor_type: level::ActorType::Static,
+      image,
+      sound: String::from(""),
+      position: level::Point { x: point.x, y: point.y },
+      scale: 1.0,
+      width,
+      visible: true,
+      active: false,
+      reversible: false,
+      carryable: false,
+      shape: None,
+      body: None,
+      frame_sounds: Vec::new(),
+      states: Vec::new(),
+    });
+    self.selection = Some(name);
+    self.rebuild();
+  }
+
+  fn describe_selection(&self) -> String {
+    let name = match self.selection {
+      Some(ref name) => name,
+      None => {
+        let image = self.asset_names.get(self.spawn_index).map_or("", |name| name.as_str());
+        return format!("New actor image: {}", image);
+      },
+    };
+    match self.level.actors.iter().find(|actor| actor.name == *name) {
+      Some(actor) => format!(
+        "{}: {} {:?} scale {:.1} width {} at ({:.0}, {:.0}){}{}{}{}",
+        actor.name, actor.image, actor.actor_type, actor.scale, actor.width,
+        actor.position.x, actor.position.y,
+        if actor.reversible { " reversible" } else { "" },
+        if actor.active { " active" } else { "" },
+        if actor.visible { "" } else { " hidden" },
+        if actor.carryable { " carryable" } else { "" }),
+      None => name.to_owned(),
+    }
+  }
+}
+
+// The topmost actor of level under a world point. The hero, detective and
+// killer are drawn over the other actors, and later actors over earlier ones.
+fn pick<Texture>(
+  level: &level::Level,
+  state: &rust_rider::State<Texture>,
+  point: camera::WorldPoint2,
+) -> Option<String>
+where
+  Texture: graphics::ImageSize + 'static,
+{
+  let mut names = vec![level.hero.name.to_owned(), level.detective.name.to_owned()];
+  names.extend(level.killer.iter().map(|killer| killer.name.to_owned()));
+  names.extend(level.actors.iter().rev().map(|actor| actor.name.to_owned()));
+
+  names.into_iter().find(|name| {
+    match state.entities().get(name) {
+      Some(entity) => {
+        let bb = entity.borrow().bb();
+        point.x >= bb[0] && point.x <= bb[0] + bb[2] &&
+          point.y >= bb[1] && point.y <= bb[1] + bb[3]
+      },
+      None => false,
+    }
+  })
+}
+
+// A name for a new actor showing image: the image's name, numbered to keep
+// names in level unique
+fn spawn_name(level: &level::Level, image: &str) -> String {
+  let base = image.rsplit('/').next().unwrap_or(image);
+  (1..)
+    .map(|number| format!("{}_{}", base, number))
+    .find(|name| {
+      level.actors.iter().all(|actor| actor.name != *name) &&
+        level.hero.name != *name &&
+        level.detective.name != *name &&
+        level.killer.iter().all(|killer| killer.name != *name)
+    })
+    .unwrap_or_else(|| base.to_owned())
+}
+
+// Where the level loaded from path is saved. Tiled maps can't be written
+// back, so they're saved as JSON next to the map.
+fn save_path(path: &str) -> std::path::PathBuf {
+  std::path::Path::new(path).with_extension("json")
+}
+
+/// How Editor responds to input-events.
+impl<Window> handler::InputHandler for Editor<Window>
+where Window: piston_window::Window,
+{
+  fn on_press(
+    &mut self,
+    _event: &piston_window::Event,
+    button: &piston_window::Button,
+  ) -> error::Result<()> {
+    match *button {
+      piston_window::Button::Mouse(button) => self.on_mouse_press(button),
+      piston_window::Button::Keyboard(key) => self.on_key_press(key),
+      _ => {},
+    }
+    Ok(())
+  }
+
+  fn on_release(
+    &mut self,
+    _event: &piston_window::Event,
+    button: &piston_window::Button,
+  ) -> error::Result<()> {
+    match *button {
+      piston_window::Button::Mouse(button) => self.on_mouse_release(button),
+      piston_window::Button::Keyboard(key) => self.on_key_release(key),
+      _ => {},
+    }
+    Ok(())
+  }
+
+  fn on_mouse_cursor(
+    &mut self,
+    _event: &piston_window::Event,
+    position: &[f64; 2],
+  ) -> error::Result<()> {
+    if self.panning {
+      self.camera.position.x -= (position[0] - self.cursor[0]) / self.camera.zoom;
+      self.camera.position.y -= (position[1] - self.cursor[1]) / self.camera.zoom;
+    }
+    self.cursor = *position;
+
+    let drag = self.drag.clone();
+    if let Some((name, grab)) = drag {
+      let position = self.cursor_world() - grab;
+      if let Some(point) = self.position_mut(&name) {
+        point.x = position.x;
+        point.y = position.y;
+      }
+      if let Some(entity) = self.state.as_ref().and_then(|state| state.entities().get(&name)) {
+        entity.borrow_mut().set_position(position)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn on_mouse_scroll(
+    &mut self,
+    _event: &piston_window::Event,
+    scroll: &[f64; 2],
+  ) -> error::Result<()> {
+    // Keep the point under the cursor where it is
+    let view_size = self.view_size();
+    let before = self.camera.screen_to_world(self.cursor, view_size);
+    self.camera.zoom *= ZOOM_STEP.powf(scroll[1]);
+    let after = self.camera.screen_to_world(self.cursor, view_size);
+    self.camera.position += before - after;
+    Ok(())
+  }
+}
+
+/// How Editor responds to update-events.
+impl<Window> handler::UpdateHandler for Editor<Window>
+where Window: piston_window::Window,
+{
+  fn on_update(
+    &mut self,
+    _event: &piston_window::Event,
+    update_args: &piston_window::UpdateArgs,
+  ) -> error::Result<()> {
+    let distance = PAN_SPEED * update_args.dt / self.camera.zoom;
+    self.camera.position.x += self.pan[0] * distance;
+    self.camera.position.y += self.pan[1] * distance;
+    Ok(())
+  }
+}
+
+/// How Editor responds to window-events.
+impl<Window> handler::WindowHandler for Editor<Window>
+where Window: piston_window::OpenGLWindow,
+{
+  fn on_render(
+    &mut self,
+    event: &piston_window::Event,
+    _render_args: &piston_window::RenderArgs,
+  ) -> error::Result<()> {
+    use self::graphics::Transformed; // piston_window::Context.trans
+
+    let view_size = self.view_size();
+    let selection = self.describe_selection();
+
+    self.window.borrow_mut().draw_2d(event, |context, graphics| {
+      piston_window::clear([0.5, 0.5, 0.5, 1.0], graphics);
+      let transform = self.camera.transform(context.transform, view_size);
+      let line = 2.0 / self.camera.zoom;
+
+      let (ref min, ref max) = self.level.world_bounds;
+      piston_window::Rectangle::new_border([0.0, 0.0, 1.0, 1.0], line).draw(
+        [min.x, min.y, max.x - min.x, max.y - min.y],
+        &context.draw_state,
+        transform,
+        graphics,
+      );
+
+      if let Some(ref state) = self.state {
+        let scene = state.scene();
+        scene.borrow_mut().draw(transform, graphics);
+
+        if let Some(entity) = self.selection.as_ref().and_then(|name| state.entities().get(name)) {
+          piston_window::Rectangle::new_border([1.0, 0.8, 0.2, 1.0], line).draw(
+            entity.borrow().bb(),
+            &context.draw_state,
+            transform,
+            graphics,
+          );
+        }
+      }
+
+      let lines = [&self.path, &selection, &self.message, &String::from(HELP_TEXT)];
+      for (index, text) in lines.iter().enumerate() {
+        let transform = context.transform.trans(20.0, 40.0 + index as f64 * 30.0);
+        piston_window::text::Text::new_color([1.0, 1.0, 1.0, 1.0], 2).draw(
+            text,
+            &mut *self.glyphs.borrow_mut(),
+            &context.draw_state,
+            transform,
+            graphics
+        ).expect("Failed drawing editor text");
+      }
+    });
+
+    Ok(())
+  }
+}
+
+/// Inherit default implementation of EventHandler::on_event.
+impl<Window> handler::EventHandler for Editor<Window>
+where Window: piston_window::OpenGLWindow,
+{
+  fn on_activate(&mut self) -> error::Result<()> {
+    self.transition = None;
+    Ok(())
+  }
+
+  fn transition(&mut self) -> Option<handler::Transition> {
+    self.transition.take()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SAMPLE_LEVEL: &str = "assets/levels/sample.json";
+
+  #[test]
+  fn picks_the_topmost_actor() {
+    let mut level = level::Level::from_path_str(SAMPLE_LEVEL).unwrap();
+    // Put two actors on top of each other, away from everything else
+    for actor in level.actors[..2].iter_mut() {
+      actor.position = level::Point { x: -10000.0, y: -10000.0 };
+    }
+    let assets = assets::load_null_assets().unwrap();
+    let state = rust_rider::State::load(level.clone(), &assets).unwrap();
+
+    let hero = &level.hero.position;
+    assert_eq!(pick(&level, &state, camera::WorldPoint2::new(hero.x, hero.y)),
+               Some(level.hero.name.to_owned()));
+    assert_eq!(pick(&level, &state, camera::WorldPoint2::new(-10000.0, -10000.0)),
+               Some(level.actors[1].name.to_owned()));
+    assert_eq!(pick(&level, &state, camera::WorldPoint2::new(-20000.0, -20000.0)), None);
+  }
+
+  #[test]
+  fn spawned_actors_get_unused_names() {
+    let mut level = level::Level::from_path_str(SAMPLE_LEVEL).unwrap();
+    assert_eq!(spawn_name(&level, "objects/car/car"), "car_1");
+
+    let mut car = level.actors[0].clone();
+    car.name = String::from("car_1");
+    level.actors.push(car);
+    level.detective.name = String::from("car_2");
+    assert_eq!(spawn_name(&level, "objects/car/car"), "car_3");
+  }
+
+  #[test]
+  fn tiled_maps_are_saved_as_json_next_to_the_map() {
+    assert_eq!(save_path(SAMPLE_LEVEL), std::path::Path::new(SAMPLE_LEVEL));
+    assert_eq!(save_path("assets/levels/sample.tmx"), std::path::Path::new(SAMPLE_LEVEL));
+  }
+}
@@ -6,16 +6,21 @@ extern crate graphics;
 
 use std::cell::RefCell;
 use std::rc::Rc;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+use collision;
 use error;
 use sound;
 use level;
 
-pub type EntityMap = HashMap<String, Rc<RefCell<Actor>>>;
+// Ordered so entities are always updated in the same order, which keeps
+// replays deterministic.
+pub type EntityMap = BTreeMap<String, Rc<RefCell<Actor>>>;
 pub type WorldPoint2 = nalgebra::Point2<f64>;
 pub type WorldVector2 = nalgebra::Vector2<f64>;
 
+// on_update takes its args by reference, like handler::UpdateHandler
+#[allow(clippy::trivially_copy_pass_by_ref)]
 pub trait Actor {
   fn name(&self) -> String;
   fn position(&self) -> WorldPoint2;
@@ -26,9 +31,19 @@ pub trait Actor {
   fn sprite_id(&self) -> uuid::Uuid;
   fn width(&self) -> f64;
   fn text(&self) -> &String;
+  // index of the animation frame currently shown
+  fn frame(&self) -> usize;
   fn actor_type(&self) -> level::ActorType {
     level::ActorType::Static
   }
+  // whether the materialized ghost can pick this up; see item::Inventory
+  fn carryable(&self) -> bool {
+    false
+  }
+  // collision shape declared in the level, if any
+  fn shape(&self) -> Option<&level::Shape> {
+    None
+  }
 
   // which direction is an Actor facing?
   // true: right
@@ -45,9 +60,38 @@ pub trait Actor {
   fn set_scale(&mut self, scale: f64) -> error::Result<()>;
   fn set_visible(&mut self, visible: bool) -> error::Result<()>;
   fn set_active(&mut self, active: bool) -> error::Result<()>;
+  fn set_frame(&mut self, frame: usize) -> error::Result<()>;
+  // name of the state the actor's state machine is in, for actors with states
+  fn machine_state(&self) -> Option<String> {
+    None
+  }
+  // go to a state without playing its sound or changing active, e.g. when
+  // restoring a save
+  fn set_machine_state(&mut self, _name: &str) -> error::Result<()> {
+    Ok(())
+  }
+  // whether the actor has been changed from how the level started, e.g.
+  // opened; the killer puts disturbed actors back
+  fn disturbed(&self) -> bool {
+    false
+  }
+  // put the actor back how the level started, e.g. close it
+  fn revert(&mut self) -> error::Result<()> {
+    Ok(())
+  }
+  // rotation in degrees, clockwise; only actors moved by physics rotate
+  fn set_rotation(&mut self, _degrees: f64) -> error::Result<()> {
+    Ok(())
+  }
 
   fn on_update(&mut self, update_args: &piston_window::UpdateArgs) -> error::Result<()>;
 
+  // sounds the actor wants played at its position since this was last called,
+  // e.g. from frame_sounds
+  fn take_sounds(&mut self) -> Vec<String> {
+    Vec::new()
+  }
+
   fn interact_hero(&mut self, _sounds: &mut sound::SoundEffects) {
     // What happens when this object interacts with the hero (i.e. ghost)
   }
@@ -56,27 +100,13 @@ pub trait Actor {
     // What happens when this object interacts with the detective
   }
 
-  fn overlap(&self, other: &Actor) -> bool {
-    let r1 = self.bb();
-    let r1_hw = self.width() / 2.0;
-    let r2 = other.bb();
-    let r2_hw = other.width() / 2.0;
-
-    let r1_x = r1[0] + (r1[2] / 2.0);
-    let r1_xmin = r1_x - r1_hw;
-    let r1_xmax = r1_x + r1_hw;
-
-    let r2_x = r2[0] + (r2[2] / 2.0);
-    let r2_xmin = r2_x - r2_hw;
-    let r2_xmax = r2_x + r2_hw;
+  fn collider(&self) -> collision::Collider {
+    collision::Collider::new(
+      self.shape(), self.bb(), self.width(), self.scale(), self.direction())
+  }
 
-    if r1_xmax < r2_xmin {
-      false
-    } else if r2_xmax < r1_xmin {
-      false
-    } else {
-      true
-    }
+  fn overlap(&self, other: &Actor) -> bool {
+    self.collider().intersects(&other.collider())
   }
 
   fn set_velocity_x(&mut self, x: f64) {
@@ -1,3 +1,5 @@
+#![allow(deprecated)] // error_chain implements the deprecated Error::cause
+
 extern crate error_chain;
 extern crate serde_json;
 extern crate std;
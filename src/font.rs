@@ -2,12 +2,9 @@ extern crate piston_window;
 extern crate find_folder;
 
 use std::cmp;
-use std::time::{Duration, SystemTime};
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use piston_window::Glyphs;
-
 pub type GlyphsRcRef = Rc<RefCell<piston_window::Glyphs>>;
 
 pub fn load_font<Window>(font_name: String,
@@ -17,7 +14,7 @@ where Window: piston_window::Window
     // Load font
     let font_dir = find_folder::Search::ParentsThenKids(3, 3)
         .for_folder("assets/fonts").unwrap();
-    let ref font = font_dir.join(font_name);
+    let font = &font_dir.join(font_name);
     let glyphs = piston_window::Glyphs::new(
         font,
         window.factory.clone(),
@@ -26,43 +23,50 @@ where Window: piston_window::Window
     Rc::new(RefCell::new(glyphs))
 }
 
+/// A sequence of strings shown one after another. Time only advances through
+/// update(), so the text shown is the same every time a run is replayed.
 pub struct FontTransition {
   strings: Vec<String>,
-  start_time: SystemTime,
-  duration: Duration,
+  elapsed: f64, // seconds since the first string was shown
+  duration: f64, // seconds each string is shown for
 }
 
 impl FontTransition {
   pub fn new(strings: Vec<String>, duration: u64)
   -> FontTransition {
-    let now = SystemTime::now();
     FontTransition {
-      strings: strings,
-      start_time: now,
-      duration: Duration::new(duration, 0),
+      strings,
+      elapsed: 0.0,
+      duration: duration as f64,
     }
   }
 
+  /// Queue another string after the one currently shown. Strings that have
+  /// already been shown are dropped so the transition doesn't start over.
   pub fn next(&mut self, next_text: String, duration: u64) {
-    let now = SystemTime::now();
+    let idx = self.current_index();
+    self.strings.drain(..idx);
     self.strings.push(next_text);
-    self.start_time = now;
-    self.duration = Duration::new(duration, 0);
+    self.elapsed = 0.0;
+    self.duration = duration as f64;
+  }
+
+  pub fn update(&mut self, dt: f64) {
+    self.elapsed += dt;
   }
 
   pub fn current_text(&self) -> String {
-    let now = SystemTime::now();
-    // If it's time to change the text
-    if self.start_time < now {
-      let dt = now.duration_since(self.start_time)
-          .expect("You don't understand the time library")
-          .as_secs();
-      let idx: u64 = dt / self.duration.as_secs();
-      let idx = cmp::min(idx, self.strings.len() as u64 - 1);
-      self.strings[idx as usize].clone()
+    self.strings.get(self.current_index()).cloned().unwrap_or_default()
+  }
+
+  fn current_index(&self) -> usize {
+    let last = self.strings.len().saturating_sub(1);
+    // If it's time to change the text; a duration of 0 holds the last string
+    if self.duration > 0.0 {
+      let idx = (self.elapsed / self.duration) as usize;
+      cmp::min(idx, last)
     } else {
-      self.strings[0].clone()
+      last
     }
   }
-
 }
@@ -2,75 +2,88 @@ extern crate piston_window;
 
 use error;
 
+/// A request from an application mode to change which modes are active.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transition {
+  Switch(String), // make the named mode the only active mode
+  Push(String), // put the named mode over the active mode, e.g. a pause menu
+  Pop, // go back to the mode under the active mode
+  Quit,
+}
+
+// Handlers take the concrete piston_window::Event rather than being generic
+// over GenericEvent, so they can be used as trait objects and modes of
+// different types can share an Application.
+
 /// An interface describing all the different input-events that can be handled.
 pub trait InputHandler {
-  fn on_button<Event: piston_window::GenericEvent>(
+  fn on_button(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _button_args: &piston_window::ButtonArgs,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_controller_axis<Event: piston_window::GenericEvent>(
+  fn on_controller_axis(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _controller_axis_args: &piston_window::ControllerAxisArgs,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_mouse_cursor<Event: piston_window::GenericEvent>(
+  fn on_mouse_cursor(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _position: &[f64; 2],
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_mouse_relative<Event: piston_window::GenericEvent>(
+  fn on_mouse_relative(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _relative: &[f64; 2],
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_mouse_scroll<Event: piston_window::GenericEvent>(
+  fn on_mouse_scroll(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _scroll: &[f64; 2],
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_press<Event: piston_window::GenericEvent>(
+  fn on_press(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _button: &piston_window::Button,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_release<Event: piston_window::GenericEvent>(
+  fn on_release(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _button: &piston_window::Button,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_text<Event: piston_window::GenericEvent>(
+  fn on_text(
     &mut self,
-    _event: &Event,
-    _text: &String,
+    _event: &piston_window::Event,
+    _text: &str,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_touch<Event: piston_window::GenericEvent>(
+  fn on_touch(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _touch_args: &piston_window::TouchArgs,
   ) -> error::Result<()> {
     Ok(())
@@ -78,18 +91,20 @@ pub trait InputHandler {
 }
 
 /// An interface describing all the different update-events that can be handled.
+// Event args are passed by reference like piston passes them to its callbacks
+#[allow(clippy::trivially_copy_pass_by_ref)]
 pub trait UpdateHandler {
-  fn on_idle<Event: piston_window::GenericEvent>(
+  fn on_idle(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _idle_args: &piston_window::IdleArgs,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_update<Event: piston_window::GenericEvent>(
+  fn on_update(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _update_args: &piston_window::UpdateArgs,
   ) -> error::Result<()> {
     Ok(())
@@ -97,50 +112,51 @@ pub trait UpdateHandler {
 }
 
 /// An interface describing all the different window-events that can be handled.
+#[allow(clippy::trivially_copy_pass_by_ref)]
 pub trait WindowHandler {
-  fn on_after_render<Event: piston_window::GenericEvent>(
+  fn on_after_render(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _after_render_args: &piston_window::AfterRenderArgs,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_close<Event: piston_window::GenericEvent>(
+  fn on_close(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _close_args: &piston_window::CloseArgs,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_cursor<Event: piston_window::GenericEvent>(
+  fn on_cursor(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _cursor: bool,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_focus<Event: piston_window::GenericEvent>(
+  fn on_focus(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _focus: bool,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_render<Event: piston_window::GenericEvent>(
+  fn on_render(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _render_args: &piston_window::RenderArgs,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_resize<Event: piston_window::GenericEvent>(
+  fn on_resize(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
     _size: &[u32; 2],
   ) -> error::Result<()> {
     Ok(())
@@ -149,84 +165,115 @@ pub trait WindowHandler {
 
 /// An interface that dispatches events to more specific handlers.
 pub trait EventHandler: InputHandler + UpdateHandler + WindowHandler {
-  fn before_event<Event: piston_window::GenericEvent>(
+  /// Called whenever this mode becomes the active mode.
+  fn on_activate(&mut self) -> error::Result<()> {
+    Ok(())
+  }
+
+  /// Called whenever this mode stops being the active mode, because another
+  /// mode was pushed over it or it was taken off the mode stack.
+  fn on_deactivate(&mut self) -> error::Result<()> {
+    Ok(())
+  }
+
+  /// Called when this mode is taken off the mode stack, whether it was the
+  /// active mode or under it.
+  fn on_leave(&mut self) -> error::Result<()> {
+    Ok(())
+  }
+
+  /// Take the transition this mode wants to make, if any. Checked after every
+  /// event the mode handles.
+  fn transition(&mut self) -> Option<Transition> {
+    None
+  }
+
+  fn before_event(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn after_event<Event: piston_window::GenericEvent>(
+  fn after_event(
     &mut self,
-    _event: &Event,
+    _event: &piston_window::Event,
   ) -> error::Result<()> {
     Ok(())
   }
 
-  fn on_event<Event: piston_window::GenericEvent>(
+  fn on_event(
     &mut self,
-    event: &Event,
+    event: &piston_window::Event,
   ) -> error::Result<()> {
-    self.before_event::<Event>(&event)?;
+    // button_args, update_args, render_args, ...
+    use piston_window::{
+      AfterRenderEvent, ButtonEvent, CloseEvent, ControllerAxisEvent,
+      CursorEvent, FocusEvent, IdleEvent, MouseCursorEvent,
+      MouseRelativeEvent, MouseScrollEvent, PressEvent, ReleaseEvent,
+      RenderEvent, ResizeEvent, TextEvent, TouchEvent, UpdateEvent,
+    };
+
+    self.before_event(event)?;
 
     // Dispatch input events to InputHandler functions.
     if let Some(button) = event.button_args() {
-      self.on_button::<Event>(&event, &button)?;
+      self.on_button(event, &button)?;
     }
     if let Some(controller_axis) = event.controller_axis_args() {
-      self.on_controller_axis::<Event>(&event, &controller_axis)?;
+      self.on_controller_axis(event, &controller_axis)?;
     }
     if let Some(mouse_cursor) = event.mouse_cursor_args() {
-      self.on_mouse_cursor::<Event>(&event, &mouse_cursor)?;
+      self.on_mouse_cursor(event, &mouse_cursor)?;
     }
     if let Some(mouse_relative) = event.mouse_relative_args() {
-      self.on_mouse_relative::<Event>(&event, &mouse_relative)?;
+      self.on_mouse_relative(event, &mouse_relative)?;
     }
     if let Some(mouse_scroll) = event.mouse_scroll_args() {
-      self.on_mouse_scroll::<Event>(&event, &mouse_scroll)?;
+      self.on_mouse_scroll(event, &mouse_scroll)?;
     }
     if let Some(press) = event.press_args() {
-      self.on_press::<Event>(&event, &press)?;
+      self.on_press(event, &press)?;
     }
     if let Some(release) = event.release_args() {
-      self.on_release::<Event>(&event, &release)?;
+      self.on_release(event, &release)?;
     }
     if let Some(text) = event.text_args() {
-      self.on_text::<Event>(&event, &text)?;
+      self.on_text(event, &text)?;
     }
     if let Some(touch) = event.touch_args() {
-      self.on_touch::<Event>(&event, &touch)?;
+      self.on_touch(event, &touch)?;
     }
 
     // Dispatch update events to UpdateHandler functions.
     if let Some(idle) = event.idle_args() {
-      self.on_idle::<Event>(&event, &idle)?;
+      self.on_idle(event, &idle)?;
     }
     if let Some(update) = event.update_args() {
-      self.on_update::<Event>(&event, &update)?;
+      self.on_update(event, &update)?;
     }
 
     // Dispatch window events to WindowHandler functions.
     if let Some(after_render) = event.after_render_args() {
-      self.on_after_render::<Event>(&event, &after_render)?;
+      self.on_after_render(event, &after_render)?;
     }
     if let Some(close) = event.close_args() {
-      self.on_close::<Event>(&event, &close)?;
+      self.on_close(event, &close)?;
     }
     if let Some(cursor) = event.cursor_args() {
-      self.on_cursor::<Event>(&event, cursor)?;
+      self.on_cursor(event, cursor)?;
     }
     if let Some(focus) = event.focus_args() {
-      self.on_focus::<Event>(&event, focus)?;
+      self.on_focus(event, focus)?;
     }
     if let Some(render) = event.render_args() {
-      self.on_render::<Event>(&event, &render)?;
+      self.on_render(event, &render)?;
     }
     if let Some(resize) = event.resize_args() {
-      self.on_resize::<Event>(&event, &resize)?;
+      self.on_resize(event, &resize)?;
     }
 
-    self.after_event::<Event>(&event)?;
+    self.after_event(event)?;
 
     Ok(())
   }
@@ -15,69 +15,77 @@ extern crate graphics;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use animation;
 use assets;
 use entity;
 use error;
 use level;
+use save;
 use sound;
 
-type Texture = piston_window::G2dTexture;
-type SceneRcRef = Rc<RefCell<sprite::Scene<Texture>>>;
-pub type HeroRcRef = Rc<RefCell<Hero>>;
+type SceneRcRef<Texture> = Rc<RefCell<sprite::Scene<Texture>>>;
+pub type HeroRcRef<Texture> = Rc<RefCell<Hero<Texture>>>;
 
 
 const TRANSPARENT_OPACITY: f32 = 0.4;
 const TRANSPARENT_SCALE_FACTOR: f64 = 0.8;
 
-#[derive(Copy, Clone)]
-enum HeroState {
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum HeroState {
   Idle,
   Ascend,
   Done,
 }
 
-pub struct Hero {
+pub struct Hero<Texture>
+where
+  Texture: graphics::ImageSize,
+{
   name: String,
   position: entity::WorldPoint2,
   velocity: entity::WorldVector2,
   scale: f64,
   width: f64,
+  shape: Option<level::Shape>,
   text: String,
   text_time: f64,
   visible: bool,
   active: bool,
   sprite_id: uuid::Uuid,
-  scene: SceneRcRef,
-  idle: Rc<assets::ImageAsset>,
-  ascend: Rc<assets::ImageAsset>,
-  frame: usize,
-  next_frame: f64,
+  scene: SceneRcRef<Texture>,
+  // "idle" loops until the hero ascends, then "ascend" plays once
+  animation: animation::Animation<Texture>,
+  sounds: Vec<String>, // waiting to be taken by take_sounds
   transparent: bool,
   state: HeroState,
   next_state: HeroState,
 }
 
 
-impl Hero {
+impl<Texture> Hero<Texture>
+where
+  Texture: graphics::ImageSize,
+{
   pub fn new(
     actor: &level::Hero,
-    assets: &assets::AssetMap,
-    scene: SceneRcRef,
-  ) -> Hero {
+    assets: &assets::AssetMap<Texture>,
+    scene: SceneRcRef<Texture>,
+  ) -> error::Result<Hero<Texture>> {
+    use error::ResultExt; // chain_err
+
     // Get the idle asset and add it to our internal state to asset map
-    let hero_idle = assets.get(&actor.idle)
-        .expect("Could not find asset")
-        .clone();
-    let hero_ascend = assets.get(&actor.ascend)
-        .expect("Could not find asset")
-        .clone();
+    let hero_idle = assets::get_asset(assets, &actor.idle)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
+    let hero_ascend = assets::get_asset(assets, &actor.ascend)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
 
-    // Set the remaining frame time
-    let frame : usize = 0;
-    let frame0 = hero_idle.frames.get(0).unwrap();
-    let next_frame = frame0.frame_time;
+    let mut animation = animation::Animation::new(
+      "idle", hero_idle, animation::PlayMode::Loop)?;
+    animation.add_clip("ascend", hero_ascend, animation::PlayMode::Once)?;
+    animation::add_frame_sounds(&mut animation, &actor.frame_sounds)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
 
-    let hero_texture = frame0.texture.clone();
+    let hero_texture = animation.texture();
 
     let mut hero_sprite = sprite::Sprite::from_texture(hero_texture);
 
@@ -88,26 +96,25 @@ impl Hero {
 
     let hero_id: uuid::Uuid = scene.borrow_mut().add_child(hero_sprite);
 
-    Hero {
+    Ok(Hero {
       name: actor.name.to_owned(),
       position: entity::WorldPoint2::new(actor.position.x, actor.position.y),
       velocity: entity::WorldVector2::new(0.0, 0.0),
       scale: actor.scale,
-      width: (actor.width as f64) * actor.scale,
+      width: f64::from(actor.width) * actor.scale,
+      shape: actor.shape.clone(),
       text: String::from(""),
       text_time: 0.0,
       visible: true,
       active: true,
       sprite_id: hero_id,
-      scene: scene,
-      idle: hero_idle.clone(),
-      ascend: hero_ascend.clone(),
-      frame,
-      next_frame,
+      scene,
+      animation,
+      sounds: Vec::new(),
       transparent: true,
       state: HeroState::Idle,
       next_state: HeroState::Idle,
-    }
+    })
   }
 
   pub fn is_transparent(&self) -> bool {
@@ -148,10 +155,57 @@ impl Hero {
       _ => false,
     }
   }
+
+  pub fn save(&self) -> save::Hero {
+    save::Hero {
+      transparent: self.transparent,
+      state: self.state,
+      next_state: self.next_state,
+      next_frame: self.animation.next_frame(),
+    }
+  }
+
+  /// Wait seconds before the next animation frame, e.g. when restoring a save.
+  pub fn set_next_frame(&mut self, seconds: f64) {
+    self.animation.set_next_frame(seconds);
+  }
+
+  pub fn restore(&mut self, saved: &save::Hero) -> error::Result<()> {
+    self.state = saved.state;
+    self.next_state = saved.next_state;
+    self.play_state()?;
+    if saved.transparent && !self.transparent {
+      self.turn_transparent()?;
+    } else if !saved.transparent && self.transparent {
+      self.turn_opaque()?;
+    }
+    Ok(())
+  }
+
+  // Play the animation for the current state
+  fn play_state(&mut self) -> error::Result<()> {
+    match self.state {
+      HeroState::Idle => self.animation.play("idle"),
+      HeroState::Ascend => self.animation.play("ascend"),
+      // Stay on the last frame of ascending
+      HeroState::Done => {
+        if self.animation.clip() != "ascend" {
+          self.animation.play("ascend")?;
+        }
+        let last = self.animation.frame_count() - 1;
+        self.animation.set_frame(last);
+        self.animation.stop();
+        Ok(())
+      },
+    }
+  }
 }
 
 
-impl entity::Actor for Hero {
+impl<Texture> entity::Actor for Hero<Texture>
+where
+  Texture: graphics::ImageSize,
+{
   fn name(&self) -> String {
     self.name.clone()
   }
@@ -184,6 +238,10 @@ impl entity::Actor for Hero {
     self.width
   }
 
+  fn shape(&self) -> Option<&level::Shape> {
+    self.shape.as_ref()
+  }
+
   fn bb(&self) -> graphics::types::Rectangle {
     self.scene.borrow_mut().child_mut(self.sprite_id).unwrap().bounding_box()
   }
@@ -227,6 +285,18 @@ impl entity::Actor for Hero {
     Ok(())
   }
 
+  fn frame(&self) -> usize {
+    self.animation.frame()
+  }
+
+  fn set_frame(&mut self, frame: usize) -> error::Result<()> {
+    self.animation.set_frame(frame);
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_texture(self.animation.texture());
+    }
+    Ok(())
+  }
+
   fn text(&self) -> &String {
     &self.text
   }
@@ -240,51 +310,24 @@ impl entity::Actor for Hero {
     let new_position = self.position + self.velocity * update_args.dt;
     self.set_position(new_position)?;
 
-    // update time to next frame
-    self.next_frame -= update_args.dt;
-
-    if self.next_frame <= 0.0 {
-      // if it's time for the next frame, get the asset
-      let asset = match self.state {
-        HeroState::Idle => &self.idle,
-        HeroState::Ascend => &self.ascend,
-        HeroState::Done => &self.ascend,
-      };
-
-      // get the index of the next frame
-      self.frame += 1;
-      if self.frame >= asset.frames.len() {
-          // TODO(austin): this is where we could update state to chain in the
-          // next animation
-          self.frame = 0;
+    for event in self.animation.update(update_args.dt) {
+      match event {
+        // States only change once the current animation is done
+        animation::Event::Finished(_) => {
           self.state = self.next_state;
-
           self.next_state = match self.next_state {
             HeroState::Idle => HeroState::Idle,
             HeroState::Ascend => HeroState::Done,
             HeroState::Done => HeroState::Done,
           };
-      }
-
-      let asset = match self.state {
-        HeroState::Idle => &self.idle,
-        HeroState::Ascend => &self.ascend,
-        HeroState::Done => &self.ascend,
-      };
-
-      // Get the next frame
-      self.frame = match self.state{
-        HeroState::Done => asset.frames.len()-1,
-        _ => self.frame,
-      };
-      let frame = asset.frames.get(self.frame).unwrap();
-
-      // Set the frame time and the update the sprite
-      self.next_frame += frame.frame_time;
-      if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
-          sprite.set_texture(frame.texture.clone());
+          self.play_state()?;
+        },
+        animation::Event::Frame { name, .. } => self.sounds.push(name),
       }
     }
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_texture(self.animation.texture());
+    }
 
     // Reset text after timeout
     if self.text_time > 0.0 {
@@ -296,6 +339,10 @@ impl entity::Actor for Hero {
     Ok(())
   }
 
+  fn take_sounds(&mut self) -> Vec<String> {
+    self.sounds.drain(..).collect()
+  }
+
   fn interact_hero(&mut self, _sounds: &mut sound::SoundEffects) {
     println!("Hero interacted with Hero!");
   }
@@ -0,0 +1,94 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use entity;
+use error;
+use physics;
+
+/// Where a carried item is held, relative to the hero, in pixels.
+const CARRY_OFFSET: [f64; 2] = [0.0, -40.0];
+
+/// An item the hero is holding.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Carried {
+  pub name: String,
+  pub ground: f64, // y the item was picked up from, where it's put back down
+}
+
+/// What the materialized ghost is carrying. Items are the level's carryable
+/// actors; the ghost can hold one at a time.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Inventory {
+  carried: Option<Carried>,
+}
+
+impl Inventory {
+  pub fn new() -> Inventory {
+    Inventory {
+      carried: None,
+    }
+  }
+
+  /// Name of the item being carried, if any.
+  pub fn carrying(&self) -> Option<&str> {
+    self.carried.as_ref().map(|carried| carried.name.as_str())
+  }
+
+  /// Pick up the item called name. Carried items are inactive, so the
+  /// detective doesn't notice them while they're in the air.
+  pub fn pick_up(&mut self, name: &str, entities: &entity::EntityMap) -> error::Result<()> {
+    let mut item = get_item(entities, name)?.borrow_mut();
+    item.set_active(false)?;
+    self.carried = Some(Carried {
+      name: name.to_owned(),
+      ground: item.position().y,
+    });
+    Ok(())
+  }
+
+  /// Put the carried item back on the ground under position, returning its
+  /// name.
+  pub fn put_down(
+    &mut self,
+    position: entity::WorldPoint2,
+    entities: &entity::EntityMap,
+    physics: &mut physics::Physics,
+  ) -> error::Result<Option<String>> {
+    let carried = match self.carried.take() {
+      Some(carried) => carried,
+      None => return Ok(None),
+    };
+
+    let position = entity::WorldPoint2::new(position.x, carried.ground);
+    {
+      let mut item = get_item(entities, &carried.name)?.borrow_mut();
+      item.set_position(position)?;
+      item.set_active(true)?;
+    }
+    physics.place(&carried.name, position);
+    Ok(Some(carried.name))
+  }
+
+  /// Hold the carried item above the hero at hero_position.
+  pub fn update(
+    &self,
+    hero_position: entity::WorldPoint2,
+    entities: &entity::EntityMap,
+    physics: &mut physics::Physics,
+  ) -> error::Result<()> {
+    if let Some(ref carried) = self.carried {
+      let position = hero_position + entity::WorldVector2::new(CARRY_OFFSET[0], CARRY_OFFSET[1]);
+      get_item(entities, &carried.name)?.borrow_mut().set_position(position)?;
+      physics.place(&carried.name, position);
+    }
+    Ok(())
+  }
+}
+
+/// The entity of the item called name.
+pub fn get_item<'a>(entities: &'a entity::EntityMap, name: &str)
+  -> error::Result<&'a Rc<RefCell<entity::Actor>>>
+{
+  entities.get(name)
+    .ok_or_else(|| error::Error::from(format!("No item named {}", name)))
+}
@@ -0,0 +1,492 @@
+extern crate piston_window;
+extern crate sprite;
+extern crate uuid;
+extern crate graphics;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use animation;
+use assets;
+use entity;
+use error;
+use item;
+use level;
+use physics;
+use save;
+use sound;
+
+type SceneRcRef<Texture> = Rc<RefCell<sprite::Scene<Texture>>>;
+pub type KillerRcRef<Texture> = Rc<RefCell<Killer<Texture>>>;
+
+/// How close the killer has to get to something to work on it, in pixels.
+const REACH: f64 = 40.0;
+/// How long the killer runs when he's scared, in seconds, and how much faster
+/// than usual.
+const FLEE_SECONDS: f64 = 3.0;
+const FLEE_SPEED_FACTOR: f64 = 2.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum KillerState {
+  Patrol, // walk back and forth looking for things to tidy up
+  Approach, // walk to the target
+  Work, // close the target or pick it up
+  Carry, // take the target to the stash
+  Flee, // run from the ghost
+}
+
+/// The killer, who patrols the level undoing the ghost's work. What he goes
+/// after is decided by rust_rider::State, which can see the other actors;
+/// the killer walks where he's told and says when he's done.
+pub struct Killer<Texture>
+where
+  Texture: graphics::ImageSize,
+{
+  name: String,
+  position: entity::WorldPoint2,
+  scale: f64,
+  speed: f64,
+  perception: f64,
+  patrol: (f64, f64),
+  stash: f64,
+  width: f64,
+  shape: Option<level::Shape>,
+  text: String,
+  text_time: f64,
+  visible: bool,
+  active: bool,
+  sprite_id: uuid::Uuid,
+  scene: SceneRcRef<Texture>,
+  // "idle" and "walk" loop, "work" plays once
+  animation: animation::Animation<Texture>,
+  sounds: Vec<String>, // waiting to be taken by take_sounds
+  scared_sound: String,
+  state: KillerState,
+  target: String, // name of the actor he's after
+  target_x: f64,
+  direction: bool, // true: right
+  flee_time: f64, // seconds of fleeing left
+  work_done: bool, // the work animation finished since work started
+  inventory: item::Inventory,
+}
+
+impl<Texture> Killer<Texture>
+where
+  Texture: graphics::ImageSize,
+{
+  pub fn new(
+    actor: &level::Killer,
+    assets: &assets::AssetMap<Texture>,
+    scene: SceneRcRef<Texture>,
+  ) -> error::Result<Killer<Texture>> {
+    use error::ResultExt; // chain_err
+
+    let idle = assets::get_asset(assets, &actor.idle)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
+    let walk = assets::get_asset(assets, &actor.walk)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
+    let work = assets::get_asset(assets, &actor.work)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
+
+    let mut animation = animation::Animation::new(
+      "walk", walk, animation::PlayMode::Loop)?;
+    animation.add_clip("idle", idle, animation::PlayMode::Loop)?;
+    animation.add_clip("work", work, animation::PlayMode::Once)?;
+    animation::add_frame_sounds(&mut animation, &actor.frame_sounds)
+        .chain_err(|| format!("Failed to create {}", actor.name))?;
+
+    let mut killer_sprite = sprite::Sprite::from_texture(animation.texture());
+    killer_sprite.set_position(actor.position.x, actor.position.y);
+    killer_sprite.set_scale(actor.scale, actor.scale);
+
+    let killer_id: uuid::Uuid = scene.borrow_mut().add_child(killer_sprite);
+
+    Ok(Killer {
+      name: actor.name.to_owned(),
+      position: entity::WorldPoint2::new(actor.position.x, actor.position.y),
+      scale: actor.scale,
+      speed: actor.speed,
+      perception: actor.perception,
+      patrol: actor.patrol,
+      stash: actor.stash,
+      width: f64::from(actor.width) * actor.scale,
+      shape: actor.shape.clone(),
+      text: String::from(""),
+      text_time: 0.0,
+      visible: true,
+      active: true,
+      sprite_id: killer_id,
+      scene,
+      animation,
+      sounds: Vec::new(),
+      scared_sound: actor.scared_sound.to_owned(),
+      state: KillerState::Patrol,
+      target: String::from(""),
+      target_x: 0.0,
+      direction: true,
+      flee_time: 0.0,
+      work_done: false,
+      inventory: item::Inventory::new(),
+    })
+  }
+
+  pub fn state(&self) -> KillerState {
+    self.state
+  }
+
+  /// Name of the actor he's after, when approaching, working or carrying.
+  pub fn target(&self) -> &str {
+    &self.target
+  }
+
+  /// Whether position is in front of the killer and close enough for him to
+  /// notice.
+  pub fn sees(&self, position: entity::WorldPoint2) -> bool {
+    let ahead = if self.direction {
+      position.x - self.position.x
+    } else {
+      self.position.x - position.x
+    };
+    ahead >= 0.0 && ahead <= self.perception
+  }
+
+  /// Whether something at x is already in his stash.
+  pub fn in_stash(&self, x: f64) -> bool {
+    (x - self.stash).abs() <= REACH
+  }
+
+  /// Go over to the actor called name at x.
+  pub fn approach(&mut self, name: &str, x: f64) {
+    self.state = KillerState::Approach;
+    self.target = name.to_owned();
+    self.target_x = x;
+  }
+
+  /// Keep up with the target moving to x.
+  pub fn follow(&mut self, x: f64) {
+    self.target_x = x;
+  }
+
+  /// Stop whatever he's doing and go back to patrolling.
+  pub fn patrol(&mut self) {
+    self.state = KillerState::Patrol;
+    self.target = String::from("");
+    self.work_done = false;
+  }
+
+  /// Take whether the work animation has finished since the last call.
+  pub fn take_work_done(&mut self) -> bool {
+    let work_done = self.work_done;
+    self.work_done = false;
+    work_done
+  }
+
+  /// Pick up the target and head for the stash.
+  pub fn pick_up(&mut self, entities: &entity::EntityMap) -> error::Result<()> {
+    self.inventory.pick_up(&self.target, entities)?;
+    self.state = KillerState::Carry;
+    Ok(())
+  }
+
+  /// Name of the item he's carrying, if any.
+  pub fn carrying(&self) -> Option<&str> {
+    self.inventory.carrying()
+  }
+
+  /// Whether he's carrying something and has reached the stash.
+  pub fn at_stash(&self) -> bool {
+    self.state == KillerState::Carry && self.in_stash(self.position.x)
+  }
+
+  /// Put down what he's carrying where he stands, returning its name.
+  pub fn put_down(
+    &mut self,
+    entities: &entity::EntityMap,
+    physics: &mut physics::Physics,
+  ) -> error::Result<Option<String>> {
+    self.inventory.put_down(self.position, entities, physics)
+  }
+
+  /// Hold what he's carrying in front of him.
+  pub fn update_carried(
+    &self,
+    entities: &entity::EntityMap,
+    physics: &mut physics::Physics,
+  ) -> error::Result<()> {
+    self.inventory.update(self.position, entities, physics)
+  }
+
+  /// Run from the ghost, like the detective does. Whatever he's carrying is
+  /// left for the caller to put down.
+  pub fn run_away(&mut self) -> error::Result<()> {
+    use entity::Actor;
+    if self.state == KillerState::Flee {
+      return Ok(());
+    }
+    self.patrol();
+    self.state = KillerState::Flee;
+    self.flee_time = FLEE_SECONDS;
+    let dir = !self.direction;
+    self.set_direction(dir);
+    if !self.scared_sound.is_empty() {
+      self.sounds.push(self.scared_sound.clone());
+    }
+    self.set_text(String::from("Who's there?!"), 2.0)
+  }
+
+  pub fn set_direction(&mut self, dir: bool) {
+    self.direction = dir;
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_flip_x(!self.direction);
+    }
+  }
+
+  pub fn save(&self) -> save::Killer {
+    save::Killer {
+      state: self.state,
+      target: self.target.clone(),
+      target_x: self.target_x,
+      direction: self.direction,
+      flee_time: self.flee_time,
+      inventory: self.inventory.clone(),
+    }
+  }
+
+  pub fn restore(&mut self, saved: &save::Killer) -> error::Result<()> {
+    self.state = saved.state;
+    self.target = saved.target.clone();
+    self.target_x = saved.target_x;
+    self.flee_time = saved.flee_time;
+    self.inventory = saved.inventory.clone();
+    self.set_direction(saved.direction);
+    let clip = self.clip();
+    self.animation.play(clip)
+  }
+
+  // Whether he stands still rather than walking in the current state
+  fn standing(&self) -> bool {
+    match self.state {
+      // Nowhere to patrol; stand guard
+      KillerState::Patrol => self.patrol.0 >= self.patrol.1,
+      KillerState::Approach => (self.target_x - self.position.x).abs() <= REACH,
+      KillerState::Work => true,
+      KillerState::Carry => self.in_stash(self.position.x),
+      KillerState::Flee => false,
+    }
+  }
+
+  // The animation clip for the current state
+  fn clip(&self) -> &'static str {
+    match self.state {
+      KillerState::Work => "work",
+      _ if self.standing() => "idle",
+      _ => "walk",
+    }
+  }
+
+  // Speed to walk at in the current state, turning to face where he's going
+  fn walk_speed(&mut self, dt: f64) -> error::Result<f64> {
+    if self.state == KillerState::Approach && self.standing() {
+      self.state = KillerState::Work;
+      self.work_done = false;
+      self.animation.stop();
+      self.animation.play("work")?;
+    }
+    if self.standing() {
+      return Ok(0.0);
+    }
+
+    let face = |killer: &mut Killer<Texture>, x: f64| {
+      let dir = x > killer.position.x;
+      if dir != killer.direction {
+        killer.set_direction(dir);
+      }
+    };
+
+    Ok(match self.state {
+      KillerState::Patrol => {
+        let (min, max) = self.patrol;
+        if self.position.x >= max {
+          self.set_direction(false);
+        } else if self.position.x <= min {
+          self.set_direction(true);
+        }
+        self.speed
+      },
+      KillerState::Approach => {
+        let target_x = self.target_x;
+        face(self, target_x);
+        self.speed
+      },
+      KillerState::Carry => {
+        let stash = self.stash;
+        face(self, stash);
+        self.speed
+      },
+      KillerState::Flee => {
+        self.flee_time -= dt;
+        if self.flee_time <= 0.0 {
+          self.patrol();
+        }
+        self.speed * FLEE_SPEED_FACTOR
+      },
+      KillerState::Work => 0.0,
+    })
+  }
+}
+
+
+impl<Texture> entity::Actor for Killer<Texture>
+where
+  Texture: graphics::ImageSize,
+{
+  fn name(&self) -> String {
+    self.name.clone()
+  }
+
+  fn position(&self) -> entity::WorldPoint2 {
+    self.position
+  }
+
+  fn velocity(&self) -> entity::WorldVector2 {
+    entity::WorldVector2::new(0.0, 0.0)
+  }
+
+  fn scale(&self) -> f64 {
+    self.scale
+  }
+
+  fn visible(&self) -> bool {
+    self.visible
+  }
+
+  fn active(&self) -> bool {
+    self.active
+  }
+
+  fn sprite_id(&self) -> uuid::Uuid {
+    self.sprite_id
+  }
+
+  fn width(&self) -> f64 {
+    self.width
+  }
+
+  fn shape(&self) -> Option<&level::Shape> {
+    self.shape.as_ref()
+  }
+
+  fn direction(&self) -> bool {
+    self.direction
+  }
+
+  fn bb(&self) -> graphics::types::Rectangle {
+    self.scene.borrow_mut().child_mut(self.sprite_id).unwrap().bounding_box()
+  }
+
+  fn set_position(&mut self, position: entity::WorldPoint2) -> error::Result<()> {
+    self.position = position;
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_position(self.position.x, self.position.y);
+    }
+    Ok(())
+  }
+
+  fn set_velocity(&mut self, _velocity: entity::WorldVector2) -> error::Result<()> {
+    Ok(())
+  }
+
+  fn set_scale(&mut self, scale: f64) -> error::Result<()> {
+    self.scale = scale;
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_scale(self.scale, self.scale);
+    }
+    Ok(())
+  }
+
+  fn set_visible(&mut self, visible: bool) -> error::Result<()> {
+    self.visible = visible;
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_visible(self.visible);
+    }
+    Ok(())
+  }
+
+  fn set_active(&mut self, active: bool) -> error::Result<()> {
+    self.active = active;
+    Ok(())
+  }
+
+  fn frame(&self) -> usize {
+    self.animation.frame()
+  }
+
+  fn set_frame(&mut self, frame: usize) -> error::Result<()> {
+    self.animation.set_frame(frame);
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_texture(self.animation.texture());
+    }
+    Ok(())
+  }
+
+  fn text(&self) -> &String {
+    &self.text
+  }
+  fn set_text(&mut self, new_text: String, time: f64) -> error::Result<()> {
+    self.text = new_text;
+    self.text_time = time;
+    Ok(())
+  }
+
+  fn on_update(&mut self, update_args: &piston_window::UpdateArgs) -> error::Result<()> {
+    let speed = self.walk_speed(update_args.dt)?;
+    // Walk faster when fleeing
+    self.animation.set_speed(if speed > 0.0 { speed / self.speed } else { 1.0 });
+    if speed > 0.0 {
+      let velocity = if self.direction {
+        entity::WorldVector2::new(speed, 0.0)
+      } else {
+        entity::WorldVector2::new(-speed, 0.0)
+      };
+      let new_position = self.position + velocity * update_args.dt;
+      self.set_position(new_position)?;
+    }
+
+    // Work plays once and isn't restarted when it finishes
+    if self.state != KillerState::Work {
+      let clip = self.clip();
+      self.animation.play(clip)?;
+    }
+    for event in self.animation.update(update_args.dt) {
+      match event {
+        animation::Event::Finished(ref finished) if finished == "work" => self.work_done = true,
+        animation::Event::Finished(_) => {},
+        animation::Event::Frame { name, .. } => self.sounds.push(name),
+      }
+    }
+    if let Some(sprite) = self.scene.borrow_mut().child_mut(self.sprite_id) {
+      sprite.set_texture(self.animation.texture());
+    }
+
+    // Reset text after timeout
+    if self.text_time > 0.0 {
+      self.text_time -= update_args.dt;
+    } else {
+      self.text = String::from("");
+    }
+
+    Ok(())
+  }
+
+  fn take_sounds(&mut self) -> Vec<String> {
+    self.sounds.drain(..).collect()
+  }
+
+  fn interact_hero(&mut self, _sounds: &mut sound::SoundEffects) {
+    println!("Hero interacted with Killer!");
+  }
+
+  fn interact_detective(&mut self) {
+    println!("Detective interacted with Killer!");
+  }
+}
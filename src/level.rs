@@ -1,22 +1,100 @@
 extern crate serde_json;
 extern crate std;
 
+use animation;
 use error;
+use tmx;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Point {
   pub x: f64,
   pub y: f64,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum ActorType {
   Static,
   Obstacle,
   Clue(bool), // true if this is the macguffin; false otherwise
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Collision shape of an actor, in unscaled image pixels relative to the
+/// center of its sprite.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Shape {
+  Aabb { half_width: f64, half_height: f64 },
+  Circle { radius: f64 },
+  Polygon(Vec<Point>), // must be convex
+}
+
+/// Makes an actor a rigid body moved by physics instead of by its velocity.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Body {
+  pub density: f64,
+  pub restitution: f64,
+  pub friction: f64,
+}
+
+/// Static level geometry that rigid bodies collide with. The shape is in world
+/// units relative to position.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Geometry {
+  pub position: Point,
+  pub shape: Shape,
+}
+
+/// Play sound whenever frame of an animation clip is shown, e.g. footsteps.
+/// Actors have a clip for each of their states, or "open" and "close" clips
+/// if they don't declare any; the hero has "idle" and "ascend", and the
+/// detective "idle", "walk" and "clue".
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FrameSound {
+  pub clip: String,
+  pub frame: usize,
+  pub sound: String,
+}
+
+/// Something that can move an actor from one of its states to another.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StateEvent {
+  HeroInteract,
+  DetectiveOverlap, // the detective has just reached the actor
+  AnimationFinished,
+  Timer(f64), // seconds since the state was entered
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateTransition {
+  pub on: StateEvent,
+  pub to: String, // state name
+}
+
+/// One state of an actor's state machine, e.g. a drawer that is closed, open
+/// or empty. The state's animation is a clip with the state's name.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ActorState {
+  pub name: String,
+  // Defaults to the actor's image
+  #[serde(default)]
+  pub image: Option<String>,
+  #[serde(default = "default_play_mode")]
+  pub mode: animation::PlayMode,
+  #[serde(default)]
+  pub sound: String, // played when the state is entered
+  // Whether entering the state makes the actor active or not; leaves it be
+  // when not given
+  #[serde(default)]
+  pub active: Option<bool>,
+  // The first that matches an event is taken
+  #[serde(default)]
+  pub transitions: Vec<StateTransition>,
+}
+
+pub fn default_play_mode() -> animation::PlayMode {
+  animation::PlayMode::Once
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Actor {
   pub name: String,
   pub actor_type: ActorType,
@@ -30,9 +108,24 @@ pub struct Actor {
   pub active: bool,
   #[serde(default)]
   pub reversible: bool,
+  // The materialized ghost can pick it up and carry it; see item::Inventory
+  #[serde(default)]
+  pub carryable: bool,
+  // Defaults to a box as wide as width and as tall as the image
+  #[serde(default)]
+  pub shape: Option<Shape>,
+  #[serde(default)]
+  pub body: Option<Body>,
+  #[serde(default)]
+  pub frame_sounds: Vec<FrameSound>,
+  // The actor starts in the first. Without states, interacting with the
+  // actor plays its image once and toggles active, and if it's reversible,
+  // interacting again plays it backwards.
+  #[serde(default)]
+  pub states: Vec<ActorState>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Hero {
   pub name: String,
   pub idle: String,
@@ -41,9 +134,52 @@ pub struct Hero {
   pub position: Point,
   pub scale: f64,
   pub width: u32,
+  #[serde(default)]
+  pub shape: Option<Shape>,
+  #[serde(default)]
+  pub frame_sounds: Vec<FrameSound>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Leaf nodes of the detective's behavior tree.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DetectiveAction {
+  // Actions
+  Idle, // stand still; never finishes
+  Walk, // walk in the current direction; never finishes
+  Wander, // walk until something is noticed
+  TurnAround,
+  Inspect, // inspect the clue that was just found
+  Flee, // scream, turn around and walk away
+  // Waits; these finish once the event has happened
+  WaitForHero, // the hero has interacted with the detective
+  WaitForGhost, // the detective has seen the materialized ghost
+  WaitForMacguffin, // the detective has inspected the macguffin
+  // Conditions; these succeed if the event happened this update and fail
+  // otherwise
+  HeroInteracted,
+  SeesGhost,
+  HitObstacle,
+  FoundClue,
+  ItemDropped, // the ghost put an item down in front of the detective
+}
+
+/// A behavior tree for the detective. This mirrors ai_behavior::Behavior so
+/// trees can be written in level files.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Behavior {
+  Action(DetectiveAction),
+  Wait(f64), // seconds
+  WaitForever,
+  Fail(Box<Behavior>), // inverts the result of the behavior
+  AlwaysSucceed(Box<Behavior>),
+  Select(Vec<Behavior>),
+  Sequence(Vec<Behavior>),
+  While(Box<Behavior>, Vec<Behavior>),
+  WhenAll(Vec<Behavior>),
+  WhenAny(Vec<Behavior>),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Detective {
   pub name: String,
   pub idle: String,
@@ -54,21 +190,105 @@ pub struct Detective {
   pub scale: f64,
   pub width: u32,
   pub speed: f64,
+  #[serde(default)]
+  pub shape: Option<Shape>,
+  // Defaults to detective::default_behavior when not given
+  #[serde(default)]
+  pub behavior: Option<Behavior>,
+  #[serde(default)]
+  pub frame_sounds: Vec<FrameSound>,
+}
+
+/// The killer, who patrols the level undoing the ghost's work: closing props
+/// that were opened and carrying clues off to his stash.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Killer {
+  pub name: String,
+  pub idle: String,
+  pub walk: String,
+  pub work: String, // closing a prop or picking up a clue
+  #[serde(default)]
+  pub scared_sound: String,
+  pub position: Point,
+  pub scale: f64,
+  pub width: u32,
+  pub speed: f64,
+  pub perception: f64, // how far ahead he notices things, in pixels
+  pub patrol: (f64, f64), // x coordinates he walks between
+  pub stash: f64, // x coordinate he carries clues off to
+  #[serde(default)]
+  pub shape: Option<Shape>,
+  #[serde(default)]
+  pub frame_sounds: Vec<FrameSound>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Something a trigger can do to the level when it fires.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+  Show(String), // actor name
+  Hide(String),
+  Activate(String),
+  Deactivate(String),
+  ToggleActive(String),
+  PlaySound(String), // sound effect name
+  Say { text: String, duration: u64 }, // queue a line of story text
+  ShakeCamera { strength: f64, duration: f64 }, // pixels, seconds
+  LookAt { name: String, duration: f64 }, // pan the camera to an actor
+}
+
+/// Actions to run when the hero or detective interacts with the actor called
+/// `name`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Trigger {
   pub name: String,
-  pub on_hero_interact: String,
-  pub on_detective_interact: String,
+  #[serde(default)]
+  pub on_hero_interact: Vec<Action>,
+  #[serde(default)]
+  pub on_detective_interact: Vec<Action>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Music for a level, by sound name. The level crossfades from explore to
+/// tension when the detective sees the ghost, and back once he calms down,
+/// then to resolution when he finds the body.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Music {
+  pub explore: String,
+  #[serde(default)]
+  pub tension: Option<String>,
+  #[serde(default)]
+  pub resolution: Option<String>,
+}
+
+impl Default for Music {
+  fn default() -> Music {
+    Music {
+      explore: String::from("background_theme"),
+      tension: None,
+      resolution: None,
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Level {
   pub world_bounds: (Point, Point),
   pub hero: Hero,
   pub detective: Detective,
+  #[serde(default)]
+  pub killer: Option<Killer>,
   pub actors: Vec<Actor>,
+  #[serde(default)]
+  pub triggers: Vec<Trigger>,
+  #[serde(default)]
+  pub geometry: Vec<Geometry>,
+  #[serde(default = "default_gravity")]
+  pub gravity: Point, // pixels per second squared; y points down
+  #[serde(default)]
+  pub music: Music,
+}
+
+pub fn default_gravity() -> Point {
+  Point { x: 0.0, y: 2000.0 }
 }
 
 impl Level {
@@ -76,9 +296,57 @@ impl Level {
     Self::from_path(&std::path::Path::new(path_str))
   }
 
+  /// Load a level from JSON, or from a Tiled map if the file ends in .tmx.
   pub fn from_path(path: &std::path::Path) -> error::Result<Level> {
+    if path.extension().map_or(false, |extension| extension == "tmx") {
+      return tmx::load_level(path);
+    }
     let file = std::fs::File::open(path)?;
-    let config = serde_json::from_reader(file)?;
+    let config: Level = serde_json::from_reader(file)?;
+    config.check_shapes()?;
     Ok(config)
   }
+
+  // Catch shapes that can't be turned into colliders
+  fn check_shapes(&self) -> error::Result<()> {
+    let shapes = self.actors.iter()
+      .map(|actor| (&actor.name, &actor.shape))
+      .chain(std::iter::once((&self.hero.name, &self.hero.shape)))
+      .chain(std::iter::once((&self.detective.name, &self.detective.shape)));
+    for (name, shape) in shapes {
+      if let Some(Shape::Polygon(ref points)) = *shape {
+        if points.len() < 3 {
+          return Err(error::Error::from(format!(
+            "The polygon of {} has {} points, but needs at least 3",
+            name, points.len(),
+          )));
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn polygons_need_three_points() {
+    let mut level = Level::from_path_str("assets/levels/sample.json").unwrap();
+    let triangle = vec![
+      Point { x: 0.0, y: 0.0 },
+      Point { x: 10.0, y: 0.0 },
+      Point { x: 0.0, y: 10.0 },
+    ];
+    level.actors[0].shape = Some(Shape::Polygon(triangle.clone()));
+    assert!(level.check_shapes().is_ok());
+
+    level.actors[0].shape = Some(Shape::Polygon(triangle[..2].to_vec()));
+    assert!(level.check_shapes().is_err());
+
+    level.actors[0].shape = None;
+    level.hero.shape = Some(Shape::Polygon(triangle[..1].to_vec()));
+    assert!(level.check_shapes().is_err());
+  }
 }
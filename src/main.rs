@@ -20,29 +20,100 @@ extern crate piston_window;
 extern crate serde_derive;
 extern crate serde_json;
 
+mod animation;
 mod application;
 mod assets;
 mod camera;
+mod campaign;
+mod collision;
 mod config;
 mod default_actor;
 mod detective;
+mod editor;
 mod entity;
 mod error;
 mod font;
 mod handler;
 mod hero;
 mod item;
+mod killer;
 mod level;
+mod menu;
+mod mixer;
+mod physics;
+mod replay;
 mod rust_rider;
+mod save;
+mod script;
+mod simulation;
 mod sound;
+mod tmx;
+mod validate;
+mod watch;
 
 use std::cell::RefCell;
 use std::rc::Rc;
 
+/// Get the value following a command line option, e.g. `--record out.json`.
+fn option_value(args: &[String], name: &str) -> Option<String> {
+  args
+    .iter()
+    .position(|arg| arg == name)
+    .and_then(|index| args.get(index + 1))
+    .cloned()
+}
+
+/// A menu item for every level of the campaign, named after its file.
+fn level_select_items(campaign: &campaign::Campaign) -> Vec<menu::Item> {
+  let mut items = Vec::new();
+  for (step, campaign_step) in campaign.steps.iter().enumerate() {
+    if let campaign::Step::Level(ref path) = *campaign_step {
+      let label = std::path::Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_owned());
+      items.push(menu::Item::play(&label, step));
+    }
+  }
+  items.push(menu::Item::go("Back", handler::Transition::Pop));
+  items
+}
+
 fn run() -> error::Result<()> {
   use error::ResultExt; // chain_err
   use std::convert::TryFrom; // try_from
 
+  // `--simulate level [--seconds n]` plays a level without a window, e.g. on
+  // a CI box
+  let args: Vec<String> = std::env::args().collect();
+  let seconds = match option_value(&args, "--seconds") {
+    Some(seconds) => seconds.parse().chain_err(|| format!("Invalid --seconds {}", seconds))?,
+    None => 60.0,
+  };
+  if let Some(path) = option_value(&args, "--simulate") {
+    return simulation::run(&path, seconds)
+      .chain_err(|| format!("Failed to simulate {}", path));
+  }
+  // `--headless` plays a replay or saved game the same way
+  if args.iter().any(|arg| arg == "--headless") {
+    if let Some(path) = option_value(&args, "--replay") {
+      return simulation::run_replay(&path)
+        .chain_err(|| format!("Failed to play replay {}", path));
+    }
+    if let Some(path) = option_value(&args, "--load") {
+      return simulation::run_save(&path, seconds)
+        .chain_err(|| format!("Failed to resume saved game {}", path));
+    }
+  }
+  // `--validate [level...]` checks levels and exits without opening a window
+  if let Some(index) = args.iter().position(|arg| arg == "--validate") {
+    let paths: Vec<String> = args[index + 1..].iter()
+      .take_while(|arg| !arg.starts_with("--"))
+      .cloned()
+      .collect();
+    return validate::run(&paths).chain_err(|| "Validation failed");
+  }
+
   let config = config::Config::from_path_str("config.json").chain_err(|| {
     "Failed to create config"
   })?;
@@ -54,16 +125,133 @@ fn run() -> error::Result<()> {
     )?,
   ));
 
-  let mut app = application::Application::<_, _>::new(window.clone());
-  app
-    .add_application_mode(
-      "rust_rider",
-      Box::new(rust_rider::GameMode::<_>::new(window.clone())),
-    )
-    .chain_err(|| "Failed to add rust rider application mode")?;
-  app.set_active_application_mode("rust_rider").chain_err(
+  let campaign = campaign::Campaign::from_path_str("assets/campaign.json")
+    .chain_err(|| "Failed to load campaign")?;
+
+  // Catch levels that refer to sounds we don't have before anything plays
+  let sounds = sound::SoundManifest::from_path_str(sound::MANIFEST_PATH)
+    .chain_err(|| "Failed to load sound manifest")?;
+  for step in campaign.steps.iter() {
+    if let campaign::Step::Level(ref path) = *step {
+      let level = level::Level::from_path_str(path)
+        .chain_err(|| format!("Failed to load level {}", path))?;
+      sounds.validate(&level)
+        .chain_err(|| format!("Level {} refers to missing sounds", path))?;
+    }
+  }
+
+  // Replays and saved games go straight into the game
+  let skip_title = option_value(&args, "--replay").is_some() ||
+    option_value(&args, "--load").is_some();
+  let level_select = level_select_items(&campaign);
+  // `--edit level` opens a level in the editor; otherwise the editor opens
+  // the first level of the campaign
+  let edit_path = option_value(&args, "--edit");
+  let editor_path = edit_path.clone().or_else(|| {
+    campaign.steps.iter().filter_map(|step| match *step {
+      campaign::Step::Level(ref path) => Some(path.to_owned()),
+      _ => None,
+    }).next()
+  });
+
+  let mut game_mode = if let Some(path) = option_value(&args, "--replay") {
+    let replay = replay::Replay::from_path_str(&path).chain_err(|| {
+      format!("Failed to load replay {}", path)
+    })?;
+    rust_rider::GameMode::<_>::new_replay(window.clone(), replay)
+      .chain_err(|| "Failed to load assets")?
+  } else if let Some(path) = option_value(&args, "--load") {
+    let saved = save::SaveGame::from_path_str(&path).chain_err(|| {
+      format!("Failed to load saved game {}", path)
+    })?;
+    rust_rider::GameMode::<_>::from_save(window.clone(), campaign, saved)
+      .chain_err(|| "Failed to restore saved game")?
+  } else {
+    rust_rider::GameMode::<_>::new(window.clone(), campaign)
+      .chain_err(|| "Failed to load assets")?
+  };
+  let session = Rc::new(RefCell::new(menu::Session {
+    start_step: None,
+    audio: config.audio().clone(),
+  }));
+  game_mode.set_input(config.input().clone());
+  game_mode.set_session(session.clone());
+  if let Some(path) = option_value(&args, "--record") {
+    game_mode.record_to(path);
+  }
+  if args.iter().any(|arg| arg == "--watch") {
+    game_mode.watch();
+  }
+  let game_mode_assets = game_mode.assets().clone();
+
+  let glyphs = font::load_font(String::from("Pixel-Noir.ttf"), &mut window.borrow_mut());
+  let make_menu = |title: &str, items: Vec<menu::Item>, back: Option<handler::Transition>| {
+    let mut menu = menu::Menu::new(window.clone(), glyphs.clone(), session.clone(), title, items);
+    menu.set_input(config.input().clone());
+    if let Some(back) = back {
+      menu.set_back(back);
+    }
+    menu
+  };
+  let push = |name: &str| handler::Transition::Push(String::from(name));
+
+  let mut title_items = vec![
+    menu::Item::play("New Game", 0),
+    menu::Item::go("Select Level", push("level_select")),
+    menu::Item::go("Options", push("options")),
+  ];
+  // The editor needs a level to open; a campaign without one has no editor
+  if editor_path.is_some() {
+    title_items.push(
+      menu::Item::go("Level Editor", handler::Transition::Switch(String::from("editor"))));
+  }
+  title_items.push(menu::Item::go("Quit", handler::Transition::Quit));
+  let title = make_menu("Rust Or Bust", title_items, None);
+  let mut pause = make_menu("Paused", vec![
+    menu::Item::go("Resume", handler::Transition::Pop),
+    menu::Item::go("Options", push("options")),
+    menu::Item::go("Quit to Title", handler::Transition::Switch(String::from("title"))),
+  ], Some(handler::Transition::Pop));
+  pause.set_overlay(true);
+  let options = make_menu("Options", vec![
+    menu::Item::volume("Volume", menu::Volume::Master),
+    menu::Item::volume("Music", menu::Volume::Music),
+    menu::Item::volume("Sound Effects", menu::Volume::Sfx),
+    menu::Item::go("Back", handler::Transition::Pop),
+  ], Some(handler::Transition::Pop));
+  let level_select = make_menu("Select Level", level_select,
+                               Some(handler::Transition::Pop));
+
+  let mut app = application::Application::new(window.clone());
+  let mut modes: Vec<(&str, Box<handler::EventHandler>)> = vec![
+    ("rust_rider", Box::new(game_mode)),
+    ("title", Box::new(title)),
+    ("pause", Box::new(pause)),
+    ("options", Box::new(options)),
+    ("level_select", Box::new(level_select)),
+  ];
+  if let Some(path) = editor_path {
+    let mut editor = editor::Editor::new(window.clone(), glyphs.clone(), game_mode_assets, &path)
+      .chain_err(|| format!("Failed to open {} in the editor", path))?;
+    editor.set_back(handler::Transition::Switch(String::from("title")));
+    modes.push(("editor", Box::new(editor)));
+  }
+  for (name, application_mode) in modes {
+    app
+      .add_application_mode(name, application_mode)
+      .chain_err(|| format!("Failed to add {} application mode", name))?;
+  }
+
+  let first_mode = if edit_path.is_some() {
+    "editor"
+  } else if skip_title {
+    "rust_rider"
+  } else {
+    "title"
+  };
+  app.set_active_application_mode(first_mode).chain_err(
     || {
-      "Failed to activate rust rider application mode"
+      format!("Failed to activate {} application mode", first_mode)
     },
   )?;
   app.spin().chain_err(|| "Failed to spin")?;
@@ -0,0 +1,267 @@
+extern crate graphics;
+extern crate piston_window;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use config;
+use error;
+use font;
+use handler;
+
+/// How much one press of left or right changes a volume.
+const VOLUME_STEP: f32 = 0.1;
+
+/// Settings shared between the menus and the game.
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+  pub start_step: Option<usize>, // campaign step the game should start at next
+  pub audio: config::AudioConfig, // volumes, starting out as configured
+}
+
+pub type SessionRcRef = Rc<RefCell<Session>>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Volume {
+  Master,
+  Music,
+  Sfx,
+}
+
+/// One line of a menu.
+#[derive(Clone, Debug)]
+pub enum Item {
+  Go { label: String, transition: handler::Transition },
+  Play { label: String, step: usize }, // start the game at a campaign step
+  Volume { label: String, volume: Volume }, // changed with left and right
+}
+
+impl Item {
+  pub fn go(label: &str, transition: handler::Transition) -> Item {
+    Item::Go { label: String::from(label), transition }
+  }
+
+  pub fn play(label: &str, step: usize) -> Item {
+    Item::Play { label: String::from(label), step }
+  }
+
+  pub fn volume(label: &str, volume: Volume) -> Item {
+    Item::Volume { label: String::from(label), volume }
+  }
+}
+
+/// A list of items to pick from with up, down and interact. Menus are drawn
+/// over a black screen, or over the modes under them if they're an overlay.
+pub struct Menu<Window>
+where
+  Window: piston_window::Window,
+{
+  title: String,
+  items: Vec<Item>,
+  selection: usize,
+  overlay: bool,
+  back: Option<handler::Transition>, // where the pause action goes
+  transition: Option<handler::Transition>,
+  input: config::InputConfig,
+  session: SessionRcRef,
+  window: Rc<RefCell<piston_window::PistonWindow<Window>>>,
+  glyphs: font::GlyphsRcRef,
+}
+
+impl<Window> Menu<Window>
+where
+  Window: piston_window::Window,
+{
+  pub fn new(
+    window: Rc<RefCell<piston_window::PistonWindow<Window>>>,
+    glyphs: font::GlyphsRcRef,
+    session: SessionRcRef,
+    title: &str,
+    items: Vec<Item>,
+  ) -> Menu<Window> {
+    Menu {
+      title: String::from(title),
+      items,
+      selection: 0,
+      overlay: false,
+      back: None,
+      transition: None,
+      input: config::InputConfig::default(),
+      session,
+      window,
+      glyphs,
+    }
+  }
+
+  /// Draw over the modes under this one instead of clearing the screen.
+  pub fn set_overlay(&mut self, overlay: bool) {
+    self.overlay = overlay;
+  }
+
+  /// Make the pause action leave the menu with back.
+  pub fn set_back(&mut self, back: handler::Transition) {
+    self.back = Some(back);
+  }
+
+  /// Use key bindings from input instead of the defaults.
+  pub fn set_input(&mut self, input: config::InputConfig) {
+    self.input = input;
+  }
+
+  fn press_action(&mut self, action: config::Action) {
+    if self.items.is_empty() {
+      if action == config::Action::Pause {
+        self.transition = self.back.clone();
+      }
+      return;
+    }
+
+    match action {
+      config::Action::MoveUp => {
+        self.selection = (self.selection + self.items.len() - 1) % self.items.len();
+      },
+      config::Action::MoveDown => {
+        self.selection = (self.selection + 1) % self.items.len();
+      },
+      config::Action::MoveLeft => self.change_volume(-VOLUME_STEP),
+      config::Action::MoveRight => self.change_volume(VOLUME_STEP),
+      config::Action::Interact => self.choose(),
+      config::Action::Pause => {
+        self.transition = self.back.clone();
+      },
+      _ => {},
+    }
+  }
+
+  fn choose(&mut self) {
+    match self.items[self.selection] {
+      Item::Go { ref transition, .. } => {
+        self.transition = Some(transition.clone());
+      },
+      Item::Play { step, .. } => {
+        self.session.borrow_mut().start_step = Some(step);
+        self.transition = Some(handler::Transition::Switch(String::from("rust_rider")));
+      },
+      Item::Volume { .. } => {},
+    }
+  }
+
+  fn change_volume(&mut self, change: f32) {
+    if let Item::Volume { volume, .. } = self.items[self.selection] {
+      let mut session = self.session.borrow_mut();
+      let value = match volume {
+        Volume::Master => &mut session.audio.master_volume,
+        Volume::Music => &mut session.audio.music_volume,
+        Volume::Sfx => &mut session.audio.sfx_volume,
+      };
+      *value = (*value + change).max(0.0).min(1.0);
+    }
+  }
+
+  fn label(&self, item: &Item) -> String {
+    match *item {
+      Item::Go { ref label, .. } | Item::Play { ref label, .. } => label.to_owned(),
+      Item::Volume { ref label, volume } => {
+        let session = self.session.borrow();
+        let value = match volume {
+          Volume::Master => session.audio.master_volume,
+          Volume::Music => session.audio.music_volume,
+          Volume::Sfx => session.audio.sfx_volume,
+        };
+        format!("{}: < {:.0}% >", label, value * 100.0)
+      },
+    }
+  }
+}
+
+/// How Menu responds to input-events.
+impl<Window> handler::InputHandler for Menu<Window>
+where Window: piston_window::Window,
+{
+  fn on_press(
+    &mut self,
+    _event: &piston_window::Event,
+    button: &piston_window::Button,
+  ) -> error::Result<()> {
+    if let Some(action) = self.input.button_action(button) {
+      self.press_action(action);
+    }
+    Ok(())
+  }
+}
+
+/// Menus don't change over time.
+impl<Window> handler::UpdateHandler for Menu<Window>
+where Window: piston_window::Window,
+{}
+
+/// How Menu responds to window-events.
+impl<Window> handler::WindowHandler for Menu<Window>
+where Window: piston_window::OpenGLWindow,
+{
+  fn on_render(
+    &mut self,
+    event: &piston_window::Event,
+    _render_args: &piston_window::RenderArgs,
+  ) -> error::Result<()> {
+    use piston_window::Window; // size
+    use self::graphics::Transformed; // piston_window::Context.trans
+
+    let window_size = self.window.borrow().size();
+    let labels: Vec<String> = self.items.iter().map(|item| self.label(item)).collect();
+
+    self.window.borrow_mut().draw_2d(event, |context, graphics| {
+      if self.overlay {
+        piston_window::rectangle(
+          [0.0, 0.0, 0.0, 0.6],
+          [0.0, 0.0, f64::from(window_size.width), f64::from(window_size.height)],
+          context.transform,
+          graphics,
+        );
+      } else {
+        piston_window::clear([0.0, 0.0, 0.0, 1.0], graphics);
+      }
+
+      let transform = context.transform.trans(50.0, 100.0);
+      piston_window::text::Text::new_color([1.0, 1.0, 1.0, 1.0], 6).draw(
+          &self.title,
+          &mut *self.glyphs.borrow_mut(),
+          &context.draw_state,
+          transform,
+          graphics
+      ).expect("Failed drawing menu title");
+
+      for (index, label) in labels.iter().enumerate() {
+        let color = if index == self.selection {
+          [1.0, 0.8, 0.2, 1.0]
+        } else {
+          [1.0, 1.0, 1.0, 1.0]
+        };
+        let transform = context.transform.trans(100.0, 220.0 + index as f64 * 60.0);
+        piston_window::text::Text::new_color(color, 4).draw(
+            label,
+            &mut *self.glyphs.borrow_mut(),
+            &context.draw_state,
+            transform,
+            graphics
+        ).expect("Failed drawing menu item");
+      }
+    });
+
+    Ok(())
+  }
+}
+
+/// Inherit default implementation of EventHandler::on_event.
+impl<Window> handler::EventHandler for Menu<Window>
+where Window: piston_window::OpenGLWindow,
+{
+  fn on_activate(&mut self) -> error::Result<()> {
+    self.transition = None;
+    Ok(())
+  }
+
+  fn transition(&mut self) -> Option<handler::Transition> {
+    self.transition.take()
+  }
+}
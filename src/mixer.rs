@@ -0,0 +1,612 @@
+#[cfg(unix)]
+extern crate ears;
+extern crate std;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+#[cfg(unix)]
+use self::ears::{AudioController, Music, Sound};
+
+use error;
+use sound;
+
+/// Most sounds that can play at once. Starting another steals the oldest
+/// sound effect.
+const MAX_VOICES: usize = 16;
+/// How often the mixer thread updates fades and reaps finished voices.
+const TICK_MS: u64 = 10;
+
+/// A sound loaded by a Backend, ready to play.
+pub trait Voice {
+  fn play(&mut self);
+  fn stop(&mut self);
+  fn is_playing(&self) -> bool;
+  fn set_volume(&mut self, volume: f32);
+  fn set_looping(&mut self, looping: bool);
+  // -1 is fully left, 1 fully right
+  fn set_pan(&mut self, pan: f32);
+}
+
+/// Loads sounds for the mixer. Backends are created on and only used from the
+/// mixer thread.
+pub trait Backend {
+  fn load(&mut self, path: &str, sound: &sound::SoundDef) -> error::Result<Box<Voice>>;
+}
+
+/// A backend whose voices play silently until stopped, for running without an
+/// audio device.
+pub struct NullBackend;
+
+struct NullVoice {
+  playing: bool,
+}
+
+impl Voice for NullVoice {
+  fn play(&mut self) {
+    self.playing = true;
+  }
+
+  fn stop(&mut self) {
+    self.playing = false;
+  }
+
+  fn is_playing(&self) -> bool {
+    self.playing
+  }
+
+  fn set_volume(&mut self, _volume: f32) {}
+
+  fn set_looping(&mut self, _looping: bool) {}
+
+  fn set_pan(&mut self, _pan: f32) {}
+}
+
+impl Backend for NullBackend {
+  fn load(&mut self, _path: &str, _sound: &sound::SoundDef) -> error::Result<Box<Voice>> {
+    Ok(Box::new(NullVoice { playing: false }))
+  }
+}
+
+/// Plays sounds through OpenAL. Music is streamed, effects are loaded whole.
+#[cfg(unix)]
+pub struct EarsBackend;
+
+// Pan an OpenAL source by placing it on a unit circle around the listener
+#[cfg(unix)]
+fn pan_position(pan: f32) -> [f32; 3] {
+  [pan, 0.0, -(1.0 - pan * pan).max(0.0).sqrt()]
+}
+
+#[cfg(unix)]
+impl Voice for Sound {
+  fn play(&mut self) {
+    AudioController::play(self);
+  }
+
+  fn stop(&mut self) {
+    AudioController::stop(self);
+  }
+
+  fn is_playing(&self) -> bool {
+    AudioController::is_playing(self)
+  }
+
+  fn set_volume(&mut self, volume: f32) {
+    AudioController::set_volume(self, volume);
+  }
+
+  fn set_looping(&mut self, looping: bool) {
+    AudioController::set_looping(self, looping);
+  }
+
+  fn set_pan(&mut self, pan: f32) {
+    AudioController::set_relative(self, true);
+    AudioController::set_position(self, pan_position(pan));
+  }
+}
+
+#[cfg(unix)]
+impl Voice for Music {
+  fn play(&mut self) {
+    AudioController::play(self);
+  }
+
+  fn stop(&mut self) {
+    AudioController::stop(self);
+  }
+
+  fn is_playing(&self) -> bool {
+    AudioController::is_playing(self)
+  }
+
+  fn set_volume(&mut self, volume: f32) {
+    AudioController::set_volume(self, volume);
+  }
+
+  fn set_looping(&mut self, looping: bool) {
+    AudioController::set_looping(self, looping);
+  }
+
+  fn set_pan(&mut self, pan: f32) {
+    AudioController::set_relative(self, true);
+    AudioController::set_position(self, pan_position(pan));
+  }
+}
+
+#[cfg(unix)]
+impl Backend for EarsBackend {
+  fn load(&mut self, path: &str, sound: &sound::SoundDef) -> error::Result<Box<Voice>> {
+    let voice: Option<Box<Voice>> = match sound.category {
+      sound::Category::Music => Music::new(path).map(|music| Box::new(music) as Box<Voice>),
+      sound::Category::Sfx => Sound::new(path).map(|effect| Box::new(effect) as Box<Voice>),
+    };
+    voice.ok_or_else(|| error::Error::from(format!("Could not load sound file {}", path)))
+  }
+}
+
+/// Where a sound is heard from: how loud it is for its distance, and how far
+/// left or right it is.
+#[derive(Copy, Clone, Debug)]
+pub struct Placement {
+  pub gain: f32,
+  pub pan: f32, // -1 is fully left, 1 fully right
+}
+
+impl Placement {
+  /// Full volume, straight ahead.
+  pub fn centered() -> Placement {
+    Placement { gain: 1.0, pan: 0.0 }
+  }
+}
+
+/// Identifies a sound started with Mixer::play.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VoiceId(u64);
+
+enum Command {
+  Play { id: VoiceId, path: String, sound: sound::SoundDef, placement: Placement, fade_in: f64 },
+  Stop(VoiceId),
+  FadeOut { id: VoiceId, seconds: f64 },
+  StopAll,
+  SetMasterVolume(f32),
+  SetCategoryVolume(sound::Category, f32),
+  Shutdown,
+}
+
+/// Audio service that plays every sound on a single mixer thread. Calls only
+/// queue commands for the thread, so they never block on the audio device.
+pub struct Mixer {
+  commands: mpsc::Sender<Command>,
+  thread: Option<thread::JoinHandle<()>>,
+  next_id: u64,
+}
+
+impl Mixer {
+  /// Start a mixer thread playing through the backend make_backend creates.
+  pub fn new<F>(make_backend: F) -> Mixer
+  where
+    F: FnOnce() -> Box<Backend> + Send + 'static,
+  {
+    let (sender, receiver) = mpsc::channel();
+    let thread = thread::spawn(move || {
+      MixerThread::new(make_backend()).run(receiver);
+    });
+    Mixer {
+      commands: sender,
+      thread: Some(thread),
+      next_id: 0,
+    }
+  }
+
+  /// A mixer that doesn't need an audio device.
+  pub fn null() -> Mixer {
+    Mixer::new(|| Box::new(NullBackend) as Box<Backend>)
+  }
+
+  /// A mixer playing on the audio device, where there is one.
+  #[cfg(unix)]
+  pub fn default_device() -> Mixer {
+    Mixer::new(|| Box::new(EarsBackend) as Box<Backend>)
+  }
+
+  #[cfg(not(unix))]
+  pub fn default_device() -> Mixer {
+    println!("Error: Cannot play sound on Windows");
+    Mixer::null()
+  }
+
+  /// Start playing the sound file at path, fading it in from silence over
+  /// fade_in seconds. With a fade_in of 0 it starts at full volume.
+  pub fn play(
+    &mut self,
+    path: String,
+    sound: &sound::SoundDef,
+    placement: Placement,
+    fade_in: f64,
+  ) -> VoiceId {
+    let id = VoiceId(self.next_id);
+    self.next_id += 1;
+    self.send(Command::Play { id, path, sound: sound.clone(), placement, fade_in });
+    id
+  }
+
+  pub fn stop(&self, id: VoiceId) {
+    self.send(Command::Stop(id));
+  }
+
+  /// Fade a sound out over seconds, then stop it.
+  pub fn fade_out(&self, id: VoiceId, seconds: f64) {
+    self.send(Command::FadeOut { id, seconds });
+  }
+
+  pub fn stop_all(&self) {
+    self.send(Command::StopAll);
+  }
+
+  pub fn set_master_volume(&self, volume: f32) {
+    self.send(Command::SetMasterVolume(volume));
+  }
+
+  pub fn set_category_volume(&self, category: sound::Category, volume: f32) {
+    self.send(Command::SetCategoryVolume(category, volume));
+  }
+
+  fn send(&self, command: Command) {
+    // The mixer thread only goes away when the Mixer is dropped
+    if self.commands.send(command).is_err() {
+      println!("Audio mixer thread has stopped");
+    }
+  }
+}
+
+impl Drop for Mixer {
+  fn drop(&mut self) {
+    self.send(Command::Shutdown);
+    if let Some(thread) = self.thread.take() {
+      if thread.join().is_err() {
+        println!("Audio mixer thread panicked");
+      }
+    }
+  }
+}
+
+// A voice that is playing, with the state needed to mix it
+struct Playing {
+  id: VoiceId,
+  voice: Box<Voice>,
+  sound: sound::SoundDef,
+  gain: f32, // from the sound's placement
+  elapsed: f64,
+  fade: Option<Fade>,
+}
+
+struct Fade {
+  elapsed: f64,
+  seconds: f64,
+  out: bool, // fading out to a stop rather than in
+}
+
+impl Fade {
+  fn gain(&self) -> f32 {
+    let progress = if self.seconds > 0.0 {
+      (self.elapsed / self.seconds).min(1.0)
+    } else {
+      1.0
+    };
+    if self.out {
+      (1.0 - progress) as f32
+    } else {
+      progress as f32
+    }
+  }
+
+  fn done(&self) -> bool {
+    self.elapsed >= self.seconds
+  }
+}
+
+struct MixerThread {
+  backend: Box<Backend>,
+  voices: Vec<Playing>, // oldest first
+  master_volume: f32,
+  music_volume: f32,
+  sfx_volume: f32,
+}
+
+impl MixerThread {
+  fn new(backend: Box<Backend>) -> MixerThread {
+    MixerThread {
+      backend,
+      voices: Vec::new(),
+      master_volume: 1.0,
+      music_volume: 1.0,
+      sfx_volume: 1.0,
+    }
+  }
+
+  fn run(mut self, commands: mpsc::Receiver<Command>) {
+    let tick = time::Duration::from_millis(TICK_MS);
+    let mut last_update = time::Instant::now();
+    loop {
+      match commands.recv_timeout(tick) {
+        Ok(Command::Shutdown) | Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        Ok(command) => self.handle(command),
+        Err(mpsc::RecvTimeoutError::Timeout) => (),
+      }
+
+      let now = time::Instant::now();
+      let elapsed = now - last_update;
+      last_update = now;
+      self.update(elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9);
+    }
+
+    for playing in self.voices.iter_mut() {
+      playing.voice.stop();
+    }
+  }
+
+  fn handle(&mut self, command: Command) {
+    match command {
+      Command::Play { id, path, sound, placement, fade_in } => {
+        self.play(id, &path, sound, placement, fade_in)
+      },
+      Command::Stop(id) => {
+        if let Some(index) = self.voices.iter().position(|playing| playing.id == id) {
+          self.voices.remove(index).voice.stop();
+        }
+      },
+      Command::FadeOut { id, seconds } => self.fade(id, seconds, true),
+      Command::StopAll => {
+        for mut playing in self.voices.drain(..) {
+          playing.voice.stop();
+        }
+      },
+      Command::SetMasterVolume(volume) => self.master_volume = volume,
+      Command::SetCategoryVolume(sound::Category::Music, volume) => self.music_volume = volume,
+      Command::SetCategoryVolume(sound::Category::Sfx, volume) => self.sfx_volume = volume,
+      Command::Shutdown => (),
+    }
+  }
+
+  fn play(
+    &mut self,
+    id: VoiceId,
+    path: &str,
+    sound: sound::SoundDef,
+    placement: Placement,
+    fade_in: f64,
+  ) {
+    if self.voices.len() >= MAX_VOICES {
+      // Steal the oldest sound effect; music is never cut off for an effect
+      let oldest = self.voices.iter()
+        .position(|playing| playing.sound.category == sound::Category::Sfx);
+      match oldest {
+        Some(index) => self.voices.remove(index).voice.stop(),
+        None => {
+          println!("No free voice for {}", path);
+          return;
+        },
+      }
+    }
+
+    let mut voice = match self.backend.load(path, &sound) {
+      Ok(voice) => voice,
+      Err(e) => {
+        println!("Failed to play sound: {}", e);
+        return;
+      },
+    };
+    // Start a fading in voice silent, rather than at full volume until the
+    // fade first gets applied
+    let fade = if fade_in > 0.0 {
+      Some(Fade {
+        elapsed: 0.0,
+        seconds: fade_in,
+        out: false,
+      })
+    } else {
+      None
+    };
+    voice.set_looping(sound.looping);
+    voice.set_pan(placement.pan);
+    voice.set_volume(self.volume(&sound, placement.gain, fade.as_ref()));
+    voice.play();
+    self.voices.push(Playing {
+      id,
+      voice,
+      sound,
+      gain: placement.gain,
+      elapsed: 0.0,
+      fade,
+    });
+  }
+
+  fn fade(&mut self, id: VoiceId, seconds: f64, out: bool) {
+    if let Some(playing) = self.voices.iter_mut().find(|playing| playing.id == id) {
+      playing.fade = Some(Fade {
+        elapsed: 0.0,
+        seconds,
+        out,
+      });
+    }
+  }
+
+  // Advance fades and time limits, apply volumes and reap finished voices
+  fn update(&mut self, dt: f64) {
+    for playing in self.voices.iter_mut() {
+      playing.elapsed += dt;
+      if let Some(ref mut fade) = playing.fade {
+        fade.elapsed += dt;
+      }
+    }
+
+    let mut index = 0;
+    while index < self.voices.len() {
+      let finished = {
+        let playing = &self.voices[index];
+        let timed_out = playing.sound.max_duration
+          .map_or(false, |max_duration| playing.elapsed >= max_duration);
+        let faded_out = playing.fade.as_ref().map_or(false, |fade| fade.out && fade.done());
+        timed_out || faded_out || !playing.voice.is_playing()
+      };
+      if finished {
+        self.voices.remove(index).voice.stop();
+      } else {
+        index += 1;
+      }
+    }
+
+    let volumes: Vec<f32> = self.voices.iter()
+      .map(|playing| self.volume(&playing.sound, playing.gain, playing.fade.as_ref()))
+      .collect();
+    for (playing, volume) in self.voices.iter_mut().zip(volumes) {
+      playing.voice.set_volume(volume);
+      // A finished fade in leaves the voice at full volume
+      if playing.fade.as_ref().map_or(false, |fade| !fade.out && fade.done()) {
+        playing.fade = None;
+      }
+    }
+  }
+
+  fn volume(&self, sound: &sound::SoundDef, gain: f32, fade: Option<&Fade>) -> f32 {
+    let category = match sound.category {
+      sound::Category::Music => self.music_volume,
+      sound::Category::Sfx => self.sfx_volume,
+    };
+    let fade = fade.map_or(1.0, |fade| fade.gain());
+    sound.volume * gain * category * self.master_volume * fade
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  // A backend whose voices record every volume they're set to
+  struct RecordingBackend {
+    volumes: Rc<RefCell<Vec<f32>>>,
+  }
+
+  struct RecordingVoice {
+    volumes: Rc<RefCell<Vec<f32>>>,
+  }
+
+  impl Voice for RecordingVoice {
+    fn play(&mut self) {}
+
+    fn stop(&mut self) {}
+
+    fn is_playing(&self) -> bool {
+      true
+    }
+
+    fn set_volume(&mut self, volume: f32) {
+      self.volumes.borrow_mut().push(volume);
+    }
+
+    fn set_looping(&mut self, _looping: bool) {}
+
+    fn set_pan(&mut self, _pan: f32) {}
+  }
+
+  impl Backend for RecordingBackend {
+    fn load(&mut self, _path: &str, _sound: &sound::SoundDef) -> error::Result<Box<Voice>> {
+      Ok(Box::new(RecordingVoice { volumes: self.volumes.clone() }))
+    }
+  }
+
+  // Play music through a mixer thread, updating it a second at a time, and
+  // record the volumes the music was set to in volumes
+  fn play_music(volumes: &Rc<RefCell<Vec<f32>>>, fade_in: f64, updates: usize) {
+    let mut mixer = MixerThread::new(Box::new(RecordingBackend { volumes: volumes.clone() }));
+    mixer.handle(Command::Play {
+      id: VoiceId(0),
+      path: String::from("music.ogg"),
+      sound: sound::SoundDef {
+        file: String::from("music.ogg"),
+        volume: 1.0,
+        max_duration: None,
+        looping: true,
+        category: sound::Category::Music,
+      },
+      placement: Placement::centered(),
+      fade_in,
+    });
+    for _ in 0..updates {
+      mixer.update(1.0);
+    }
+  }
+
+  #[test]
+  fn fading_in_starts_silent() {
+    let volumes = Rc::new(RefCell::new(Vec::new()));
+    play_music(&volumes, 2.0, 2);
+    assert_eq!(*volumes.borrow(), vec![0.0, 0.5, 1.0]);
+  }
+
+  #[test]
+  fn playing_without_a_fade_starts_at_full_volume() {
+    let volumes = Rc::new(RefCell::new(Vec::new()));
+    play_music(&volumes, 0.0, 1);
+    assert_eq!(*volumes.borrow(), vec![1.0, 1.0]);
+  }
+
+  fn music() -> sound::SoundDef {
+    sound::SoundDef {
+      file: String::from("music.ogg"),
+      volume: 1.0,
+      max_duration: None,
+      looping: true,
+      category: sound::Category::Music,
+    }
+  }
+
+  fn playing(mixer: &MixerThread) -> Vec<VoiceId> {
+    mixer.voices.iter().map(|playing| playing.id).collect()
+  }
+
+  #[test]
+  fn crossfading_fades_the_old_music_out_then_reaps_it() {
+    let mut mixer = MixerThread::new(Box::new(NullBackend));
+    let play = |id, fade_in| Command::Play {
+      id: VoiceId(id),
+      path: String::from("music.ogg"),
+      sound: music(),
+      placement: Placement::centered(),
+      fade_in,
+    };
+    mixer.handle(play(0, 0.0));
+    mixer.handle(Command::FadeOut { id: VoiceId(0), seconds: 2.0 });
+    mixer.handle(play(1, 2.0));
+
+    mixer.update(1.0);
+    assert_eq!(playing(&mixer), vec![VoiceId(0), VoiceId(1)]);
+    let volumes: Vec<f32> = mixer.voices.iter()
+      .map(|playing| mixer.volume(&playing.sound, playing.gain, playing.fade.as_ref()))
+      .collect();
+    assert_eq!(volumes, vec![0.5, 0.5]);
+
+    mixer.update(1.0);
+    assert_eq!(playing(&mixer), vec![VoiceId(1)]);
+    assert!(mixer.voices[0].fade.is_none());
+  }
+
+  #[test]
+  fn stopping_removes_the_voice_right_away() {
+    let mut mixer = MixerThread::new(Box::new(NullBackend));
+    for id in 0..2 {
+      mixer.handle(Command::Play {
+        id: VoiceId(id),
+        path: String::from("music.ogg"),
+        sound: music(),
+        placement: Placement::centered(),
+        fade_in: 0.0,
+      });
+    }
+    mixer.handle(Command::Stop(VoiceId(0)));
+    assert_eq!(playing(&mixer), vec![VoiceId(1)]);
+  }
+}
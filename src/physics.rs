@@ -0,0 +1,248 @@
+extern crate nalgebra;
+extern crate ncollide;
+extern crate nphysics2d;
+
+use std::collections::BTreeMap;
+
+use self::nalgebra::{Point2, Translation2, Vector2};
+use self::ncollide::shape::{Ball, ConvexHull, Cuboid};
+use self::nphysics2d::object::{RigidBody, RigidBodyHandle};
+use self::nphysics2d::world::World;
+
+use entity;
+use error;
+use level;
+
+// Material of level geometry and the world bounds
+const GEOMETRY_RESTITUTION: f64 = 0.1;
+const GEOMETRY_FRICTION: f64 = 0.8;
+// How far the world bounds walls extend past the bounds
+const WALL_THICKNESS: f64 = 1000.0;
+
+/// Rigid body simulation for the actors of a level that declare a body. Bodies
+/// collide with the level geometry and the world bounds, and their actors are
+/// moved to follow them after every update.
+pub struct Physics {
+  world: World<f64>,
+  bodies: BTreeMap<String, RigidBodyHandle<f64>>, // actor name to its body
+}
+
+impl Physics {
+  /// An empty world without gravity.
+  pub fn new() -> Physics {
+    Physics {
+      world: World::new(),
+      bodies: BTreeMap::new(),
+    }
+  }
+
+  /// Build the world of a level. Actor bodies are placed at their entities,
+  /// so the entities must already be loaded.
+  pub fn load(level: &level::Level, entities: &entity::EntityMap) -> Physics {
+    let mut physics = Physics::new();
+    physics.world.set_gravity(Vector2::new(level.gravity.x, level.gravity.y));
+
+    // Walls below and beside the world bounds
+    let (ref min, ref max) = level.world_bounds;
+    let width = max.x - min.x + 2.0 * WALL_THICKNESS;
+    let height = max.y - min.y + 2.0 * WALL_THICKNESS;
+    let center_x = (min.x + max.x) / 2.0;
+    let center_y = (min.y + max.y) / 2.0;
+    let walls = vec![
+      (center_x, max.y + WALL_THICKNESS / 2.0, width / 2.0, WALL_THICKNESS / 2.0),
+      (min.x - WALL_THICKNESS / 2.0, center_y, WALL_THICKNESS / 2.0, height / 2.0),
+      (max.x + WALL_THICKNESS / 2.0, center_y, WALL_THICKNESS / 2.0, height / 2.0),
+    ];
+    for (x, y, half_width, half_height) in walls {
+      let shape = level::Shape::Aabb { half_width, half_height };
+      physics.add_static(&shape, x, y);
+    }
+
+    for geometry in level.geometry.iter() {
+      physics.add_static(&geometry.shape, geometry.position.x, geometry.position.y);
+    }
+
+    for actor in level.actors.iter() {
+      let body = match actor.body {
+        Some(ref body) => body,
+        None => continue,
+      };
+      let entity = match entities.get(&actor.name) {
+        Some(entity) => entity.borrow(),
+        None => continue,
+      };
+
+      // Without a declared shape, use the same box the actor collides with
+      let shape = match actor.shape {
+        Some(ref shape) => shape.clone(),
+        None => level::Shape::Aabb {
+          half_width: f64::from(actor.width) / 2.0,
+          half_height: entity.bb()[3] / actor.scale / 2.0,
+        },
+      };
+
+      let mut rigid_body = make_rigid_body(&shape, actor.scale, Some(body));
+      let position = entity.position();
+      rigid_body.append_translation(&Translation2::new(position.x, position.y));
+      let handle = physics.world.add_rigid_body(rigid_body);
+      physics.bodies.insert(actor.name.to_owned(), handle);
+    }
+
+    physics
+  }
+
+  /// Step the simulation and move every actor with a body to follow it.
+  pub fn on_update(&mut self, dt: f64, entities: &entity::EntityMap) -> error::Result<()> {
+    if self.bodies.is_empty() {
+      return Ok(());
+    }
+
+    self.world.step(dt);
+
+    for (name, handle) in self.bodies.iter() {
+      if let Some(entity) = entities.get(name) {
+        let body = handle.borrow();
+        let position = body.position();
+        let translation = position.translation.vector;
+        let mut entity = entity.borrow_mut();
+        entity.set_position(entity::WorldPoint2::new(translation.x, translation.y))?;
+        entity.set_rotation(position.rotation.angle().to_degrees())?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Push every body within radius of origin away from it. Bodies at origin
+  /// get the full strength, in pixels per second, falling off to nothing at
+  /// radius.
+  pub fn blow(&mut self, origin: entity::WorldPoint2, radius: f64, strength: f64) {
+    for handle in self.bodies.values() {
+      let mut body = handle.borrow_mut();
+      let translation = body.position().translation.vector;
+      let offset = Point2::new(translation.x, translation.y) - origin;
+      let distance = offset.norm();
+      if distance <= 0.0 || distance >= radius {
+        continue;
+      }
+
+      let push = offset / distance * strength * (1.0 - distance / radius);
+      let velocity = body.lin_vel() + push;
+      body.set_lin_vel(velocity);
+      body.activate(1.0);
+    }
+  }
+
+  /// Move every body to its actor and bring it to rest, e.g. after actors were
+  /// restored from a save.
+  pub fn reset(&mut self, entities: &entity::EntityMap) {
+    for (name, handle) in self.bodies.iter() {
+      if let Some(entity) = entities.get(name) {
+        rest_at(handle, entity.borrow().position());
+      }
+    }
+  }
+
+  /// Move the body of the actor called name to position and bring it to
+  /// rest, e.g. while it's being carried. Does nothing for actors without a
+  /// body.
+  pub fn place(&mut self, name: &str, position: entity::WorldPoint2) {
+    if let Some(handle) = self.bodies.get(name) {
+      rest_at(handle, position);
+    }
+  }
+
+  fn add_static(&mut self, shape: &level::Shape, x: f64, y: f64) {
+    let mut rigid_body = make_rigid_body(shape, 1.0, None);
+    rigid_body.append_translation(&Translation2::new(x, y));
+    self.world.add_rigid_body(rigid_body);
+  }
+}
+
+// Move a body to position and stop it
+fn rest_at(handle: &RigidBodyHandle<f64>, position: entity::WorldPoint2) {
+  let mut body = handle.borrow_mut();
+  body.set_translation(Translation2::new(position.x, position.y));
+  body.set_lin_vel(Vector2::new(0.0, 0.0));
+  body.set_ang_vel(nalgebra::zero());
+  body.activate(1.0);
+}
+
+// Dynamic when given a body, static level geometry otherwise
+fn make_rigid_body(
+  shape: &level::Shape,
+  scale: f64,
+  body: Option<&level::Body>,
+) -> RigidBody<f64> {
+  match *shape {
+    level::Shape::Aabb { half_width, half_height } => {
+      let cuboid = Cuboid::new(Vector2::new(half_width * scale, half_height * scale));
+      match body {
+        Some(body) => {
+          RigidBody::new_dynamic(cuboid, body.density, body.restitution, body.friction)
+        },
+        None => RigidBody::new_static(cuboid, GEOMETRY_RESTITUTION, GEOMETRY_FRICTION),
+      }
+    },
+    level::Shape::Circle { radius } => {
+      let ball = Ball::new(radius * scale);
+      match body {
+        Some(body) => {
+          RigidBody::new_dynamic(ball, body.density, body.restitution, body.friction)
+        },
+        None => RigidBody::new_static(ball, GEOMETRY_RESTITUTION, GEOMETRY_FRICTION),
+      }
+    },
+    level::Shape::Polygon(ref points) => {
+      let hull = ConvexHull::new(points.iter()
+        .map(|point| Point2::new(point.x * scale, point.y * scale))
+        .collect());
+      match body {
+        Some(body) => {
+          RigidBody::new_dynamic(hull, body.density, body.restitution, body.friction)
+        },
+        None => RigidBody::new_static(hull, GEOMETRY_RESTITUTION, GEOMETRY_FRICTION),
+      }
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use assets;
+  use level;
+  use simulation;
+
+  // Where the actor called name is, going by a save of the simulation
+  fn position(simulation: &simulation::Simulation, name: &str) -> level::Point {
+    let saved = simulation.state().save(0);
+    let actor = saved.actors.into_iter().find(|actor| actor.name == name).unwrap();
+    actor.position
+  }
+
+  #[test]
+  fn a_body_falls_onto_geometry_and_settles() {
+    let mut level = level::Level::from_path_str("assets/levels/sample.json").unwrap();
+    {
+      // A 100 pixel square 130 pixels above a floor
+      let cairn = level.actors.iter_mut().find(|actor| actor.name == "cairn").unwrap();
+      cairn.shape = Some(level::Shape::Aabb { half_width: 5.0, half_height: 5.0 });
+      cairn.body = Some(level::Body { density: 1.0, restitution: 0.0, friction: 0.8 });
+    }
+    level.geometry.push(level::Geometry {
+      position: level::Point { x: 2000.0, y: 500.0 },
+      shape: level::Shape::Aabb { half_width: 300.0, half_height: 20.0 },
+    });
+    let assets = assets::load_null_assets().unwrap();
+    let mut simulation = simulation::Simulation::new(level, &assets).unwrap();
+
+    simulation.run_until(360, |_| false).unwrap();
+    let landed = position(&simulation, "cairn");
+    assert!((landed.y - 430.0).abs() < 2.0, "cairn stopped at y = {}", landed.y);
+    assert!((landed.x - 2000.0).abs() < 1.0);
+
+    simulation.run_until(120, |_| false).unwrap();
+    let settled = position(&simulation, "cairn");
+    assert!((settled.y - landed.y).abs() < 0.1);
+  }
+}
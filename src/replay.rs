@@ -0,0 +1,152 @@
+extern crate serde_json;
+extern crate std;
+
+use config;
+use error;
+
+/// A single recorded input. Actions are recorded rather than buttons, so
+/// replays play back the same with any key bindings.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Input {
+  Press(config::Action),
+  Release(config::Action),
+}
+
+/// An input along with the number of updates that had run in the level when
+/// it arrived.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Event {
+  pub tick: u64,
+  pub input: Input,
+}
+
+/// Every input received while playing a level. Playing the events back against
+/// the same level, stepping each update with its recorded dt, reproduces the
+/// run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Replay {
+  pub level: String, // path to the level file
+  pub hero_speed: f64,
+  pub dts: Vec<f64>, // dt of every update, in order
+  pub events: Vec<Event>,
+}
+
+impl Replay {
+  pub fn new(level: String, hero_speed: f64) -> Replay {
+    Replay {
+      level,
+      hero_speed,
+      dts: Vec::new(),
+      events: Vec::new(),
+    }
+  }
+
+  /// Number of updates the run lasted.
+  pub fn ticks(&self) -> u64 {
+    self.dts.len() as u64
+  }
+
+  pub fn from_path_str(path_str: &str) -> error::Result<Replay> {
+    Self::from_path(&std::path::Path::new(path_str))
+  }
+
+  pub fn from_path(path: &std::path::Path) -> error::Result<Replay> {
+    let file = std::fs::File::open(path)?;
+    let replay = serde_json::from_reader(file)?;
+    Ok(replay)
+  }
+
+  pub fn to_path_str(&self, path_str: &str) -> error::Result<()> {
+    let file = std::fs::File::create(std::path::Path::new(path_str))?;
+    serde_json::to_writer_pretty(file, self)?;
+    Ok(())
+  }
+}
+
+/// Records inputs into a replay file. Only the most recently started level is
+/// kept, so the file describes the level that was being played last.
+pub struct Recorder {
+  path: String,
+  replay: Option<Replay>,
+}
+
+impl Recorder {
+  pub fn new(path: String) -> Recorder {
+    Recorder {
+      path,
+      replay: None,
+    }
+  }
+
+  /// Start recording a level played at hero_speed, discarding whatever was
+  /// recorded before.
+  pub fn begin(&mut self, level: String, hero_speed: f64) {
+    self.replay = Some(Replay::new(level, hero_speed));
+  }
+
+  pub fn record(&mut self, tick: u64, input: Input) {
+    if let Some(ref mut replay) = self.replay {
+      replay.events.push(Event {
+        tick,
+        input,
+      });
+    }
+  }
+
+  /// Note that an update of dt seconds ran.
+  pub fn update(&mut self, dt: f64) {
+    if let Some(ref mut replay) = self.replay {
+      replay.dts.push(dt);
+    }
+  }
+
+  pub fn save(&self) -> error::Result<()> {
+    match self.replay {
+      Some(ref replay) => {
+        println!("Saving replay of {} ({} updates) to {}",
+                 replay.level, replay.ticks(), self.path);
+        replay.to_path_str(&self.path)
+      },
+      None => Ok(()),
+    }
+  }
+}
+
+/// Hands out the inputs of a replay as the ticks they were recorded on come
+/// around.
+pub struct Player {
+  replay: Replay,
+  next: usize,
+}
+
+impl Player {
+  pub fn new(replay: Replay) -> Player {
+    Player {
+      replay,
+      next: 0,
+    }
+  }
+
+  pub fn replay(&self) -> &Replay {
+    &self.replay
+  }
+
+  /// Inputs to apply before running update number tick.
+  pub fn inputs_for(&mut self, tick: u64) -> Vec<Input> {
+    let mut inputs = Vec::new();
+    while let Some(event) = self.replay.events.get(self.next) {
+      if event.tick > tick {
+        break;
+      }
+      inputs.push(event.input.clone());
+      self.next += 1;
+    }
+    inputs
+  }
+
+  /// The dt update number tick ran with, or None once every recorded update
+  /// has been played back.
+  pub fn dt(&self, tick: u64) -> Option<f64> {
+    self.replay.dts.get(tick as usize).cloned()
+  }
+}
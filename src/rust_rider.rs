@@ -6,10 +6,14 @@ extern crate piston_window;
 extern crate sprite;
 
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::rc::Rc;
 
 use assets;
 use camera;
+use campaign;
+use config;
+use collision;
 use default_actor;
 use entity;
 use entity::Actor;
@@ -18,36 +22,98 @@ use font;
 use handler;
 use hero;
 use detective;
+use item;
+use killer;
 use level;
+use menu;
+use physics;
+use replay;
+use save;
+use script;
 use sound;
+use watch;
 
-type Texture = piston_window::G2dTexture;
-type SceneRcRef = Rc<RefCell<sprite::Scene<Texture>>>;
-type Scene = sprite::Scene<Texture>;
+type SceneRcRef<Texture> = Rc<RefCell<sprite::Scene<Texture>>>;
+type Scene<Texture> = sprite::Scene<Texture>;
 
-/// The game-ion of the Rust Rider game. The state should act as the save data
-/// for a resumable session of the game.
-pub struct State {
+/// Where F5 quick saves to and F9 loads from.
+const QUICK_SAVE_PATH: &str = "save.json";
+
+/// How far the ghost's wind reaches, and how hard it pushes right next to him.
+const WIND_RADIUS: f64 = 600.0;
+const WIND_STRENGTH: f64 = 1500.0;
+
+/// Seconds the music takes to fade out once the campaign is over.
+const END_MUSIC_FADE: f64 = 3.0;
+/// How long music takes to crossfade between tracks.
+const MUSIC_CROSSFADE: f64 = 2.0;
+/// How long tension music keeps playing after the detective last saw the
+/// ghost.
+const TENSION_SECONDS: f64 = 10.0;
+
+/// How long the camera looks at the body once the detective finds it, and how
+/// far it zooms in.
+const FOUND_SHOT_SECONDS: f64 = 4.0;
+const FOUND_SHOT_ZOOM: f64 = 1.5;
+/// How hard the screen shakes when the detective screams, in pixels.
+const SCREAM_SHAKE: f64 = 12.0;
+const SCREAM_SHAKE_SECONDS: f64 = 0.6;
+
+/// The game-ion of the Rust Rider game. State::save captures it as the save
+/// data for a resumable session of the game.
+///
+/// State is generic over the texture type so a level can be simulated without
+/// a window (see simulation::Simulation).
+pub struct State<Texture>
+where
+  Texture: graphics::ImageSize,
+{
   level: level::Level,
   camera: camera::Camera2,
+  scene: SceneRcRef<Texture>,
   entities: entity::EntityMap,
-  hero: Option<hero::HeroRcRef>,
-  detective: Option<detective::DetectiveRcRef>,
+  broad_phase: collision::BroadPhase,
+  hero: Option<hero::HeroRcRef<Texture>>,
+  detective: Option<detective::DetectiveRcRef<Texture>>,
+  killer: Option<killer::KillerRcRef<Texture>>,
+  physics: physics::Physics,
+  inventory: item::Inventory,
+  hero_speed: f64,
+  // Seconds of tension music left; 0 when exploring
+  tension: f64,
+  // Names of the entities the detective overlapped on the last update, so
+  // detective triggers only fire when he first reaches something.
+  touching: BTreeSet<String>,
   found: bool,
   win: bool,
   title_text: font::FontTransition,
   hint_text: font::FontTransition,
 }
 
-impl State {
+impl<Texture> State<Texture>
+where
+  Texture: graphics::ImageSize + 'static,
+{
   /// Create a State with default values for a new game.
-  pub fn new(level: level::Level, camera: camera::Camera2) -> State {
+  pub fn new(
+    level: level::Level,
+    camera: camera::Camera2,
+    scene: SceneRcRef<Texture>,
+  ) -> State<Texture> {
     State {
-      level: level,
-      camera: camera,
+      level,
+      camera,
+      scene,
       entities: entity::EntityMap::new(),
+      broad_phase: collision::BroadPhase::new(),
       hero: None,
       detective: None,
+      killer: None,
+      physics: physics::Physics::new(),
+      inventory: item::Inventory::new(),
+      hero_speed: config::default_input_hero_speed(),
+      tension: 0.0,
+      touching: BTreeSet::new(),
       win: false,
       found: false,
       title_text: font::FontTransition::new(vec![
@@ -59,153 +125,610 @@ impl State {
       hint_text: font::FontTransition::new(vec![
                                              String::from("Use the arrow keys to haunt around"),
                                              String::from("LShift to materialize, Space to interact"),
+                                             String::from("B to blow wind while materialized"),
                                            ],
                                            15),
     }
   }
 
-  pub fn get_hero(&self) -> hero::HeroRcRef {
+  /// Create the State for a level in a fresh scene, adding a sprite for every
+  /// actor.
+  pub fn load(
+    level: level::Level,
+    assets: &assets::AssetMap<Texture>,
+  ) -> error::Result<State<Texture>> {
+    // TODO: should be loaded as an actor from level
+    let camera = camera::Camera2::new();
+    let scene = Rc::new(RefCell::new(Scene::new()));
+    let mut state = State::new(level.clone(), camera, scene.clone());
+
+    for actor in level.actors.iter() {
+      state.entities.insert(
+        actor.name.to_owned(),
+        make_actor(&actor, &assets, scene.clone())?,
+      );
+    }
+    state.physics = physics::Physics::load(&level, &state.entities);
+
+    // insert detective
+    let detective_cfg = level.detective;
+    let detective = make_detective(&detective_cfg, &assets, scene.clone())?;
+    // TODO: like the hero's padding, this should account for his size
+    detective.borrow_mut().set_walk_bounds(
+      level.world_bounds.0.x + 150.0,
+      level.world_bounds.1.x - 150.0,
+    );
+    state.detective = Some(detective.clone());
+    state.entities.insert(detective_cfg.name.to_owned(), detective);
+
+    // insert killer
+    if let Some(ref killer_cfg) = level.killer {
+      let killer = make_killer(killer_cfg, &assets, scene.clone())?;
+      state.killer = Some(killer.clone());
+      state.entities.insert(killer_cfg.name.to_owned(), killer);
+    }
+
+    // insert hero
+    let hero_cfg = level.hero;
+    let hero = make_hero(&hero_cfg, &assets, scene.clone())?;
+    state.hero = Some(hero.clone());
+    state.entities.insert(hero_cfg.name.to_owned(), hero);
+
+    Ok(state)
+  }
+
+  /// Rebuild the State from a changed level or changed images, keeping the
+  /// camera, the hero's position and the progress through the level. Actors
+  /// whose definition and images haven't changed carry on where they were.
+  /// If the new level can't be built, the State is left as it was.
+  pub fn reload(
+    &mut self,
+    level: level::Level,
+    assets: &assets::AssetMap<Texture>,
+    changed_images: &BTreeSet<String>,
+  ) -> error::Result<()> {
+    let mut state = State::load(level, assets)?;
+
+    state.camera.jump_to(self.camera.position, self.camera.zoom);
+
+    for actor in self.level.actors.iter() {
+      let unchanged = state.level.actors.iter()
+        .any(|new_actor| new_actor == actor) &&
+        !changed_images.contains(&actor.image);
+      if !unchanged {
+        continue;
+      }
+      let old = self.entities[&actor.name].borrow();
+      let mut new = state.entities[&actor.name].borrow_mut();
+      new.set_position(old.position())?;
+      new.set_active(old.active())?;
+      new.set_visible(old.visible())?;
+      if let Some(state) = old.machine_state() {
+        new.set_machine_state(&state)?;
+      }
+      new.set_frame(old.frame())?;
+    }
+    state.physics.reset(&state.entities);
+
+    let detective_unchanged = {
+      let detective = &self.level.detective;
+      *detective == state.level.detective &&
+        !changed_images.contains(&detective.idle) &&
+        !changed_images.contains(&detective.walk) &&
+        !changed_images.contains(&detective.clue)
+    };
+    if detective_unchanged {
+      let old = self.get_detective();
+      let old = old.borrow();
+      let new = state.get_detective();
+      let mut new = new.borrow_mut();
+      new.restore(&old.save())?;
+      new.set_position(old.position())?;
+      new.set_frame(old.frame())?;
+    }
+
+    if let (Some(old), Some(new)) = (self.killer.as_ref(), state.killer.as_ref()) {
+      let killer = self.level.killer.as_ref().unwrap();
+      let killer_unchanged = Some(killer) == state.level.killer.as_ref() &&
+        !changed_images.contains(&killer.idle) &&
+        !changed_images.contains(&killer.walk) &&
+        !changed_images.contains(&killer.work);
+      // Whatever he's carrying has to still be in the level too
+      let carrying = old.borrow().carrying()
+        .map_or(true, |name| state.entities.contains_key(name));
+      if killer_unchanged && carrying {
+        let old = old.borrow();
+        let mut new = new.borrow_mut();
+        new.restore(&old.save())?;
+        new.set_position(old.position())?;
+        new.set_frame(old.frame())?;
+      }
+    }
+
+    let old_hero = self.get_hero();
+    let new_hero = state.get_hero();
+    new_hero.borrow_mut().restore(&old_hero.borrow().save())?;
+    new_hero.borrow_mut().set_position(old_hero.borrow().position())?;
+
+    // Keep carrying the item if it's still in the level
+    let carrying = self.inventory.carrying().map(|name| state.entities.contains_key(name));
+    if carrying == Some(true) {
+      state.inventory = self.inventory.clone();
+    }
+    state.hero_speed = self.hero_speed;
+    state.tension = self.tension;
+    state.found = self.found;
+    state.win = self.win;
+    ::std::mem::swap(&mut state.title_text, &mut self.title_text);
+    ::std::mem::swap(&mut state.hint_text, &mut self.hint_text);
+
+    *self = state;
+    Ok(())
+  }
+
+  pub fn get_hero(&self) -> hero::HeroRcRef<Texture> {
     let hero_opt = self.hero.clone();
     hero_opt.unwrap()
   }
 
-  pub fn get_detective(&self) -> detective::DetectiveRcRef {
+  pub fn get_detective(&self) -> detective::DetectiveRcRef<Texture> {
     let detective_opt = self.detective.clone();
     detective_opt.unwrap()
   }
-}
 
-pub struct GameMode<Window>
-where
-  Window: piston_window::Window,
-{
-  state: State,
-  window: Rc<RefCell<piston_window::PistonWindow<Window>>>,
-  //assets: assets::AssetMap,
-  scene: SceneRcRef,
-  sound_effects: sound::SoundEffects,
-  glyphs: Rc<RefCell<piston_window::Glyphs>>,
-}
+  pub fn scene(&self) -> SceneRcRef<Texture> {
+    self.scene.clone()
+  }
 
-/// How GameMode responds to input-events.
-impl<Window> handler::InputHandler for GameMode<Window>
-where Window: piston_window::Window,
-{
-  fn on_press<Event: piston_window::GenericEvent>(
+  pub fn entities(&self) -> &entity::EntityMap {
+    &self.entities
+  }
+
+  /// Whether the detective has found the hero's body.
+  pub fn found(&self) -> bool {
+    self.found
+  }
+
+  /// Whether the hero has finished ascending and the level is over.
+  pub fn won(&self) -> bool {
+    self.win
+  }
+
+  /// Capture everything needed to resume this level later.
+  pub fn save(&self, step: usize) -> save::SaveGame {
+    let detective = self.get_detective();
+    let hero = self.get_hero();
+    let detective = detective.borrow().save();
+    let hero = hero.borrow().save();
+    let killer = self.killer.as_ref().map(|killer| killer.borrow().save());
+    let actors = self.entities.values().map(|entity| {
+      let entity = entity.borrow();
+      let position = entity.position();
+      save::Actor {
+        name: entity.name(),
+        position: level::Point { x: position.x, y: position.y },
+        active: entity.active(),
+        visible: entity.visible(),
+        frame: entity.frame(),
+        state: entity.machine_state(),
+      }
+    }).collect();
+
+    save::SaveGame {
+      step,
+      level: self.level.clone(),
+      camera: save::Camera {
+        position: level::Point {
+          x: self.camera.position.x,
+          y: self.camera.position.y,
+        },
+        zoom: self.camera.zoom,
+      },
+      actors,
+      detective,
+      hero,
+      inventory: self.inventory.clone(),
+      killer,
+      found: self.found,
+      win: self.win,
+    }
+  }
+
+  /// Apply a save game to a State freshly loaded from the saved level.
+  pub fn restore(&mut self, saved: &save::SaveGame) -> error::Result<()> {
+    self.camera.jump_to(
+      camera::WorldPoint2::new(saved.camera.position.x, saved.camera.position.y),
+      saved.camera.zoom,
+    );
+
+    // Restore the hero and detective state machines first; the frame restored
+    // below indexes into the animation of their current state.
+    let detective = self.get_detective();
+    let hero = self.get_hero();
+    detective.borrow_mut().restore(&saved.detective)?;
+    hero.borrow_mut().restore(&saved.hero)?;
+    if let (Some(killer), Some(saved)) = (self.killer.as_ref(), saved.killer.as_ref()) {
+      killer.borrow_mut().restore(saved)?;
+    }
+
+    for actor in saved.actors.iter() {
+      if let Some(entity) = self.entities.get(&actor.name) {
+        let mut entity = entity.borrow_mut();
+        entity.set_position(
+          entity::WorldPoint2::new(actor.position.x, actor.position.y),
+        )?;
+        entity.set_active(actor.active)?;
+        entity.set_visible(actor.visible)?;
+        if let Some(ref state) = actor.state {
+          entity.set_machine_state(state)?;
+        }
+        entity.set_frame(actor.frame)?;
+      }
+    }
+    // Setting their frames started them over, so the hero and detective pick
+    // up their animations where the save left off afterwards
+    detective.borrow_mut().set_next_frame(saved.detective.next_frame);
+    hero.borrow_mut().set_next_frame(saved.hero.next_frame);
+    self.physics.reset(&self.entities);
+
+    self.inventory = saved.inventory.clone();
+    self.found = saved.found;
+    self.win = saved.win;
+    Ok(())
+  }
+
+  // Put down whatever the hero is carrying, letting the detective notice
+  fn put_down(&mut self, sound_effects: &mut sound::SoundEffects) -> error::Result<()> {
+    let hero = self.get_hero();
+    let position = hero.borrow().position();
+    if let Some(name) = self.inventory.put_down(position, &self.entities, &mut self.physics)? {
+      println!("Hero put down {}", name);
+      let item = item::get_item(&self.entities, &name)?.clone();
+      let item = item.borrow();
+      sound_effects.play_at(self.actor_sound(&name), item.position());
+      let detective = self.get_detective();
+      detective.borrow_mut().notice_drop(&*item)?;
+    }
+    Ok(())
+  }
+
+  // Where the actor called name is, if the killer wants to tidy it up: it's
+  // been opened, or it's a clue lying around outside his stash
+  fn untidy(
+    &self,
+    name: &str,
+    killer: &killer::Killer<Texture>,
+  ) -> Option<entity::WorldPoint2> {
+    if name == killer.name() || self.inventory.carrying() == Some(name) {
+      return None;
+    }
+    let entity = self.entities.get(name)?.borrow();
+    let position = entity.position();
+    let clue = match entity.actor_type() {
+      level::ActorType::Clue(_) => true,
+      _ => false,
+    };
+    let loose_clue = clue && entity.carryable() && entity.active() &&
+      !killer.in_stash(position.x);
+    if entity.disturbed() || loose_clue {
+      Some(position)
+    } else {
+      None
+    }
+  }
+
+  // Let the killer undo the ghost's work: close what's been opened and carry
+  // clues off to his stash. The materialized ghost scares him away.
+  fn update_killer(&mut self, sound_effects: &mut sound::SoundEffects) -> error::Result<()> {
+    let killer = match self.killer {
+      Some(ref killer) => killer.clone(),
+      None => return Ok(()),
+    };
+    let hero = self.get_hero();
+
+    let hero_position = hero.borrow().position();
+    let state = killer.borrow().state();
+    if state != killer::KillerState::Flee && !hero.borrow().is_transparent() &&
+      killer.borrow().sees(hero_position)
+    {
+      let dropped = {
+        let mut killer = killer.borrow_mut();
+        killer.run_away()?;
+        killer.put_down(&self.entities, &mut self.physics)?
+      };
+      if let Some(name) = dropped {
+        let item = item::get_item(&self.entities, &name)?.clone();
+        let item = item.borrow();
+        sound_effects.play_at(self.actor_sound(&name), item.position());
+        let detective = self.get_detective();
+        detective.borrow_mut().notice_drop(&*item)?;
+      }
+      return Ok(());
+    }
+
+    match state {
+      killer::KillerState::Patrol => {
+        // Go for the nearest thing he notices
+        let target = {
+          let killer = killer.borrow();
+          let x = killer.position().x;
+          self.entities.keys()
+            .filter_map(|name| self.untidy(name, &*killer).map(|position| (name, position)))
+            .filter(|&(_, position)| killer.sees(position))
+            .map(|(name, position)| (name.clone(), position.x))
+            .fold(None, |nearest: Option<(String, f64)>, (name, target_x)| match nearest {
+              Some(ref nearest) if (nearest.1 - x).abs() <= (target_x - x).abs() =>
+                Some(nearest.clone()),
+              _ => Some((name, target_x)),
+            })
+        };
+        if let Some((name, x)) = target {
+          killer.borrow_mut().approach(&name, x);
+        }
+      },
+      killer::KillerState::Approach | killer::KillerState::Work => {
+        let target = killer.borrow().target().to_owned();
+        let untidy = self.untidy(&target, &*killer.borrow());
+        let mut killer = killer.borrow_mut();
+        match untidy {
+          // The ghost got there first
+          None => killer.patrol(),
+          Some(position) => {
+            killer.follow(position.x);
+            if killer.take_work_done() {
+              match self.entities.get(&target) {
+                Some(entity) if entity.borrow().disturbed() => {
+                  entity.borrow_mut().revert()?;
+                  killer.patrol();
+                },
+                _ => killer.pick_up(&self.entities)?,
+              }
+            }
+          },
+        }
+      },
+      killer::KillerState::Carry => {
+        let mut killer = killer.borrow_mut();
+        killer.update_carried(&self.entities, &mut self.physics)?;
+        if killer.at_stash() {
+          killer.put_down(&self.entities, &mut self.physics)?;
+          killer.patrol();
+        }
+      },
+      killer::KillerState::Flee => {},
+    }
+    Ok(())
+  }
+
+  // The sound of the level actor called name, or "" for none
+  fn actor_sound(&self, name: &str) -> &str {
+    self.level.actors.iter()
+      .find(|actor| actor.name == name)
+      .map_or("", |actor| actor.sound.as_str())
+  }
+
+  /// Crossfade to the music that fits what is happening in the level.
+  pub fn start_music(&self, sound_effects: &mut sound::SoundEffects) {
+    let music = &self.level.music;
+    let name = if self.found {
+      music.resolution.as_ref().unwrap_or(&music.explore)
+    } else if self.tension > 0.0 {
+      music.tension.as_ref().unwrap_or(&music.explore)
+    } else {
+      &music.explore
+    };
+    sound_effects.play_music(name, MUSIC_CROSSFADE);
+  }
+
+  pub fn set_hero_speed(&mut self, hero_speed: f64) {
+    self.hero_speed = hero_speed;
+  }
+
+  pub fn on_press(
     &mut self,
-    _event: &Event,
-    button: &piston_window::Button,
+    action: config::Action,
+    sound_effects: &mut sound::SoundEffects,
   ) -> error::Result<()> {
-    match button {
-      &piston_window::Button::Keyboard(key) => match key {
-        piston_window::Key::X => {
-          self.sound_effects.play("clue");
-        },
-        // TODO: these speeds should come from config.
-        piston_window::Key::Left => {
-          let mut hero = self.state.get_hero();
-          let mut velocity = hero.borrow().velocity();
-          velocity.x = -500.0;
-          hero.borrow_mut().set_velocity(velocity)?;
-        },
-        piston_window::Key::Right => {
-          let mut hero = self.state.get_hero();
-          let mut velocity = hero.borrow().velocity();
-          velocity.x = 500.0;
-          hero.borrow_mut().set_velocity(velocity)?;
-        },
-        piston_window::Key::LShift => {
-          let mut hero = self.state.get_hero();
-          hero.borrow_mut().set_text(String::from("Boo!"), 1.0)?;
-          hero.borrow_mut().turn_opaque()?;
-        },
-        piston_window::Key::Space => {
-          let mut hero = self.state.get_hero();
-          for (ref _name, ref entity) in self.state.entities.iter() {
-            if !hero.borrow().is_transparent() && entity.borrow().overlap(&*hero.borrow()) {
-              println!("Hero interacting with {}", entity.borrow().name());
-              entity.borrow_mut().interact_hero(&mut self.sound_effects);
+    match action {
+      config::Action::DebugSound => {
+        sound_effects.play("clue");
+      },
+      config::Action::MoveLeft => {
+        let mut hero = self.get_hero();
+        let mut velocity = hero.borrow().velocity();
+        velocity.x = -self.hero_speed;
+        hero.borrow_mut().set_velocity(velocity)?;
+      },
+      config::Action::MoveRight => {
+        let mut hero = self.get_hero();
+        let mut velocity = hero.borrow().velocity();
+        velocity.x = self.hero_speed;
+        hero.borrow_mut().set_velocity(velocity)?;
+      },
+      config::Action::MoveUp => {
+        let mut hero = self.get_hero();
+        let mut velocity = hero.borrow().velocity();
+        velocity.y = -self.hero_speed;
+        hero.borrow_mut().set_velocity(velocity)?;
+      },
+      config::Action::MoveDown => {
+        let mut hero = self.get_hero();
+        let mut velocity = hero.borrow().velocity();
+        velocity.y = self.hero_speed;
+        hero.borrow_mut().set_velocity(velocity)?;
+      },
+      config::Action::Materialize => {
+        let mut hero = self.get_hero();
+        hero.borrow_mut().set_text(String::from("Boo!"), 1.0)?;
+        hero.borrow_mut().turn_opaque()?;
+      },
+      config::Action::Blow => {
+        let hero = self.get_hero();
+        if !hero.borrow().is_transparent() {
+          let position = hero.borrow().position();
+          self.physics.blow(position, WIND_RADIUS, WIND_STRENGTH);
+        }
+      },
+      config::Action::Interact => {
+        let mut hero = self.get_hero();
+        let mut interacted = Vec::new();
+        if !hero.borrow().is_transparent() {
+          self.broad_phase.update(&self.entities);
+          let hero_name = hero.borrow().name();
+          let touching = self.broad_phase.overlapping(&hero_name);
+          // Items in the killer's hands are out of reach
+          let killer_item = self.killer.as_ref()
+            .and_then(|killer| killer.borrow().carrying().map(|name| name.to_owned()));
+          let item = touching.iter()
+            .find(|name| {
+              self.entities.get(*name).map_or(false, |entity| entity.borrow().carryable()) &&
+                Some(*name) != killer_item.as_ref()
+            })
+            .cloned();
+          // Carrying takes both hands: put down what the hero holds, or pick
+          // up an item, before interacting with anything else
+          if self.inventory.carrying().is_some() {
+            self.put_down(sound_effects)?;
+          } else if let Some(name) = item {
+            println!("Hero picking up {}", name);
+            self.inventory.pick_up(&name, &self.entities)?;
+            sound_effects.play_at(self.actor_sound(&name), hero.borrow().position());
+            interacted.push(name);
+          } else {
+            for name in touching {
+              if let Some(entity) = self.entities.get(&name) {
+                println!("Hero interacting with {}", name);
+                entity.borrow_mut().interact_hero(sound_effects);
+              }
+              interacted.push(name);
             }
           }
         }
-        _ => {},
+
+        // Run triggers after the loop; their actions may need to borrow any
+        // of the entities.
+        for name in interacted.iter() {
+          script::fire(&self.level.triggers,
+                       name,
+                       script::Interaction::Hero,
+                       &self.entities,
+                       sound_effects,
+                       &mut self.camera,
+                       &mut self.title_text)?;
+        }
       },
-      _ => {},
+      // Saving, loading and pausing are handled by GameMode
+      config::Action::QuickSave | config::Action::QuickLoad | config::Action::Pause => {},
     }
 
     Ok(())
   }
 
-  fn on_release<Event: piston_window::GenericEvent>(
+  pub fn on_release(
     &mut self,
-    _event: &Event,
-    button: &piston_window::Button,
+    action: config::Action,
+    sound_effects: &mut sound::SoundEffects,
   ) -> error::Result<()> {
-    match button {
-      &piston_window::Button::Keyboard(key) => match key {
-        piston_window::Key::Left | piston_window::Key::Right => {
-          let mut hero = self.state.get_hero();
-          let mut velocity = hero.borrow().velocity();
-          velocity.x = 0.0;
-          hero.borrow_mut().set_velocity(velocity)?;
-        },
-        piston_window::Key::LShift => {
-          let mut hero = self.state.get_hero();
-          hero.borrow_mut().turn_transparent()?;
-        },
-        _ => {},
+    match action {
+      config::Action::MoveLeft | config::Action::MoveRight => {
+        let mut hero = self.get_hero();
+        let mut velocity = hero.borrow().velocity();
+        velocity.x = 0.0;
+        hero.borrow_mut().set_velocity(velocity)?;
+      },
+      config::Action::MoveUp | config::Action::MoveDown => {
+        let mut hero = self.get_hero();
+        let mut velocity = hero.borrow().velocity();
+        velocity.y = 0.0;
+        hero.borrow_mut().set_velocity(velocity)?;
+      },
+      config::Action::Materialize => {
+        // A ghost can't hold on to anything
+        self.put_down(sound_effects)?;
+        let mut hero = self.get_hero();
+        hero.borrow_mut().turn_transparent()?;
       },
       _ => {},
     }
 
     Ok(())
   }
-}
 
-fn clamp<T: ::std::cmp::PartialOrd>(x: T, min: T, max: T) -> T {
-  if x < min {
-    min
-  } else if x > max {
-    max
-  } else {
-    x
-  }
-}
-
-/// How GameMode responds to update-events.
-impl<Window> handler::UpdateHandler for GameMode<Window>
-where Window: piston_window::Window,
-{
-  fn on_update<Event: piston_window::GenericEvent>(
+  /// Advance the simulation of the level by one update. view_size is the size
+  /// of the area the camera shows.
+  #[allow(clippy::trivially_copy_pass_by_ref)]
+  pub fn on_update(
     &mut self,
-    _event: &Event,
     update_args: &piston_window::UpdateArgs,
+    view_size: piston_window::Size,
+    sound_effects: &mut sound::SoundEffects,
   ) -> error::Result<()> {
-    use piston_window::Window; // size
+    let hero = self.get_hero();
+    let detective = self.get_detective();
 
-    let hero = self.state.get_hero();
-    let detective = self.state.get_detective();
+    self.title_text.update(update_args.dt);
+    self.hint_text.update(update_args.dt);
 
     // Call on_update on entities, to move them and update their animations
-    for (ref _name, ref entity) in self.state.entities.iter() {
-      entity.borrow_mut().on_update(update_args)?;
+    for (ref _name, ref entity) in self.entities.iter() {
+      let mut entity = entity.borrow_mut();
+      entity.on_update(update_args)?;
+      for sound in entity.take_sounds() {
+        sound_effects.play_at(&sound, entity.position());
+      }
     }
 
+    // Actors with bodies follow physics instead of their velocity
+    self.physics.on_update(update_args.dt, &self.entities)?;
+
     // Give the detective a chance to interact with other active objects in the
     // scene
-    for (ref _name, ref entity) in self.state.entities.iter() {
-      let entity = entity.borrow();
-      if entity.name() != "detective" {
-        if entity.overlap(&*detective.borrow()) {
-          detective.borrow_mut().interact_entity(&*entity, &mut self.sound_effects);
-        }
+    let mut touching = BTreeSet::new();
+    self.broad_phase.update(&self.entities);
+    let detective_name = detective.borrow().name();
+    for name in self.broad_phase.overlapping(&detective_name) {
+      let entity = self.entities[&name].borrow();
+      detective.borrow_mut().interact_entity(&*entity, sound_effects);
+      touching.insert(name);
+    }
+
+    // Fire detective triggers for anything he just reached.
+    let reached: Vec<String> =
+      touching.difference(&self.touching).cloned().collect();
+    self.touching = touching;
+    for name in reached.iter() {
+      self.entities[name].borrow_mut().interact_detective();
+      script::fire(&self.level.triggers,
+                   name,
+                   script::Interaction::Detective,
+                   &self.entities,
+                   sound_effects,
+                   &mut self.camera,
+                   &mut self.title_text)?;
+    }
+
+    if self.tension > 0.0 {
+      self.tension -= update_args.dt;
+      if self.tension <= 0.0 {
+        self.tension = 0.0;
+        self.start_music(sound_effects);
       }
     }
 
-    if detective.borrow().done() && !self.state.found {
+    if detective.borrow().done() && !self.found {
       hero.borrow_mut().ascend();
-      self.state.found = true;
-      self.state.title_text = font::FontTransition::new(vec![
+      self.found = true;
+      // Show the player what the detective found
+      let entities = &self.entities;
+      let macguffin = self.level.actors.iter()
+        .find(|actor| actor.actor_type == level::ActorType::Clue(true))
+        .and_then(|actor| entities.get(&actor.name));
+      if let Some(macguffin) = macguffin {
+        self.camera.look_at(macguffin.borrow().position(), FOUND_SHOT_SECONDS);
+        self.camera.zoom_to(FOUND_SHOT_ZOOM, FOUND_SHOT_SECONDS * 0.5);
+      }
+      self.start_music(sound_effects);
+      self.title_text = font::FontTransition::new(vec![
           String::from("The detective found your body!"),
           String::from("You may finally move on to the afterlife"),
           String::from("You win! ... ?"),
@@ -213,13 +736,13 @@ where Window: piston_window::Window,
         4);
     }
 
-    if hero.borrow().won() && !self.state.win {
+    if hero.borrow().won() && !self.win {
       println!("The detective found your body! You win!");
-      self.state.win = true;
+      self.win = true;
     }
 
-    // If the detective sees the hero, make him turn around and go the other
-    // way. Maybe add some text and screaming?
+    // If the detective sees the hero, let him know; his behavior tree decides
+    // whether to turn around and go the other way.
     {
       let hero_position = hero.borrow().position();
       let det_position = detective.borrow().position();
@@ -231,12 +754,23 @@ where Window: piston_window::Window,
         // and detective is facing the ghost...
         if (hero_position.x > det_position.x) == det_direction {
           println!("Detective sees the ghost!");
-          detective.borrow_mut().run_away();
+          detective.borrow_mut().see_ghost();
+          // He screams when he first notices
+          if self.tension <= 0.0 {
+            self.camera.shake(SCREAM_SHAKE, SCREAM_SHAKE_SECONDS);
+          }
+          self.tension = TENSION_SECONDS;
+          self.start_music(sound_effects);
           // TODO(austin): startled or scream noise
         }
       }
     }
 
+    // Let the detective decide what to do about everything he noticed
+    detective.borrow_mut().think(update_args, sound_effects)?;
+
+    self.update_killer(sound_effects)?;
+
     let mut hero_position = hero.borrow().position();
     // TODO: find a better solution than padding here.
     // Intersection with bounds should account for size for actor.
@@ -244,18 +778,301 @@ where Window: piston_window::Window,
     // Other size might be based on size of sprite or collision box.
     hero_position.x = clamp(
       hero_position.x,
-      self.state.level.world_bounds.0.x + 75.0,
-      self.state.level.world_bounds.1.x - 75.0,
+      self.level.world_bounds.0.x + 75.0,
+      self.level.world_bounds.1.x - 75.0,
+    );
+    hero_position.y = clamp(
+      hero_position.y,
+      self.level.world_bounds.0.y + 75.0,
+      self.level.world_bounds.1.y - 75.0,
     );
     hero.borrow_mut().set_position(hero_position)?;
+    self.inventory.update(hero_position, &self.entities, &mut self.physics)?;
 
-    let window_size = self.window.borrow().size();
-    self.state.camera.position.x = hero_position.x;
-    self.state.camera.position.x = clamp(
-      self.state.camera.position.x,
-      self.state.level.world_bounds.0.x + window_size.width as f64 * 0.5,
-      self.state.level.world_bounds.1.x - window_size.width as f64 * 0.5,
+    let (ref min, ref max) = self.level.world_bounds;
+    self.camera.update(
+      hero_position,
+      update_args.dt,
+      [f64::from(view_size.width), f64::from(view_size.height)],
+      (camera::WorldPoint2::new(min.x, min.y), camera::WorldPoint2::new(max.x, max.y)),
     );
+    sound_effects.set_listener(self.camera.position);
+
+    Ok(())
+  }
+}
+
+fn clamp<T: ::std::cmp::PartialOrd>(x: T, min: T, max: T) -> T {
+  if x < min {
+    min
+  } else if x > max {
+    max
+  } else {
+    x
+  }
+}
+
+fn make_actor<Texture>(
+  actor: &level::Actor,
+  assets: &assets::AssetMap<Texture>,
+  scene: SceneRcRef<Texture>,
+) -> error::Result<Rc<RefCell<entity::Actor>>>
+where
+  Texture: graphics::ImageSize + 'static,
+{
+  let actor = default_actor::DefaultActor::new(actor, assets, scene.clone())?;
+  Ok(Rc::new(RefCell::new(actor)))
+}
+
+fn make_hero<Texture>(
+  actor: &level::Hero,
+  assets: &assets::AssetMap<Texture>,
+  scene: SceneRcRef<Texture>,
+) -> error::Result<hero::HeroRcRef<Texture>>
+where
+  Texture: graphics::ImageSize + 'static,
+{
+  Ok(Rc::new(RefCell::new(hero::Hero::new(actor, assets, scene.clone())?)))
+}
+
+fn make_detective<Texture>(
+  actor: &level::Detective,
+  assets: &assets::AssetMap<Texture>,
+  scene: SceneRcRef<Texture>,
+) -> error::Result<detective::DetectiveRcRef<Texture>>
+where
+  Texture: graphics::ImageSize + 'static,
+{
+  Ok(Rc::new(RefCell::new(detective::Detective::new(actor, assets, scene.clone())?)))
+}
+
+fn make_killer<Texture>(
+  actor: &level::Killer,
+  assets: &assets::AssetMap<Texture>,
+  scene: SceneRcRef<Texture>,
+) -> error::Result<killer::KillerRcRef<Texture>>
+where
+  Texture: graphics::ImageSize + 'static,
+{
+  Ok(Rc::new(RefCell::new(killer::Killer::new(actor, assets, scene.clone())?)))
+}
+
+// An error and everything that caused it, one per line
+fn describe_error(e: &error::Error) -> String {
+  use error_chain::ChainedError; // display_chain
+
+  e.display_chain().to_string()
+}
+
+// Draw an error message in the top left corner, over whatever else is there
+fn draw_load_error<G>(
+  message: &str,
+  glyphs: &mut piston_window::Glyphs,
+  context: &piston_window::Context,
+  graphics: &mut G,
+)
+where
+  G: graphics::Graphics<Texture = piston_window::G2dTexture>,
+{
+  use self::graphics::Transformed; // piston_window::Context.trans
+
+  for (index, line) in message.lines().enumerate() {
+    let transform = context.transform.trans(20.0, 40.0 + index as f64 * 30.0);
+    piston_window::text::Text::new_color([1.0, 0.2, 0.2, 1.0], 2).draw(
+        line,
+        glyphs,
+        &context.draw_state,
+        transform,
+        graphics
+    ).expect("Failed drawing load error");
+  }
+}
+
+// Sound effects from the sound manifest, or muted ones and the reason if the
+// manifest can't be loaded
+fn load_sound_effects() -> (sound::SoundEffects, Option<String>) {
+  match sound::SoundEffects::new() {
+    Ok(sound_effects) => (sound_effects, None),
+    Err(e) => {
+      let message = describe_error(&e);
+      println!("Playing without sound: {}", message);
+      (sound::SoundEffects::muted(), Some(message))
+    },
+  }
+}
+
+/// A text screen shown between levels of a campaign.
+pub struct Interstitial {
+  title: String,
+  text: font::FontTransition,
+  remaining: f64,
+}
+
+impl Interstitial {
+  pub fn new(title: String, lines: Vec<String>, duration: u64) -> Interstitial {
+    let remaining = (lines.len() as u64 * duration) as f64;
+    Interstitial {
+      title,
+      text: font::FontTransition::new(lines, duration),
+      remaining,
+    }
+  }
+}
+
+pub struct GameMode<Window>
+where
+  Window: piston_window::Window,
+{
+  // Only one of state and interstitial is set at a time, depending on which
+  // kind of campaign step is active.
+  state: Option<State<piston_window::G2dTexture>>,
+  interstitial: Option<Interstitial>,
+  campaign: campaign::Campaign,
+  step: usize,
+  // Number of updates run since the current level started.
+  tick: u64,
+  recorder: Option<replay::Recorder>,
+  player: Option<replay::Player>,
+  input: config::InputConfig,
+  // Action held by each gamepad axis, keyed by controller id and axis
+  axes: BTreeMap<(i32, u8), config::Action>,
+  // Movement actions pressed in the level and not released yet
+  held: BTreeSet<config::Action>,
+  window: Rc<RefCell<piston_window::PistonWindow<Window>>>,
+  assets: assets::AssetMap<piston_window::G2dTexture>,
+  sound_effects: sound::SoundEffects,
+  glyphs: Rc<RefCell<piston_window::Glyphs>>,
+  session: Option<menu::SessionRcRef>,
+  transition: Option<handler::Transition>,
+  // Set in watch mode, where changed levels and images are reloaded.
+  watcher: Option<watch::Watcher>,
+  // Shown on screen until the level loads successfully.
+  load_error: Option<String>,
+  // Why the game is muted, shown on screen whenever there's no load_error.
+  sound_error: Option<String>,
+}
+
+/// How GameMode responds to input-events.
+impl<Window> handler::InputHandler for GameMode<Window>
+where Window: piston_window::Window,
+{
+  fn on_press(
+    &mut self,
+    _event: &piston_window::Event,
+    button: &piston_window::Button,
+  ) -> error::Result<()> {
+    match self.input.button_action(button) {
+      Some(action) => self.press_action(action),
+      None => Ok(()),
+    }
+  }
+
+  fn on_release(
+    &mut self,
+    _event: &piston_window::Event,
+    button: &piston_window::Button,
+  ) -> error::Result<()> {
+    match self.input.button_action(button) {
+      Some(action) => self.release_action(action),
+      None => Ok(()),
+    }
+  }
+
+  fn on_controller_axis(
+    &mut self,
+    _event: &piston_window::Event,
+    axis: &piston_window::ControllerAxisArgs,
+  ) -> error::Result<()> {
+    // Axes act like buttons that are pressed while the axis is pushed past the
+    // dead zone.
+    let action = self.input.axis_action(axis);
+    let held = self.axes.get(&(axis.id, axis.axis)).cloned();
+    if action == held {
+      return Ok(());
+    }
+    if let Some(held) = held {
+      self.axes.remove(&(axis.id, axis.axis));
+      self.release_action(held)?;
+    }
+    if let Some(action) = action {
+      self.axes.insert((axis.id, axis.axis), action);
+      self.press_action(action)?;
+    }
+    Ok(())
+  }
+}
+
+/// How GameMode responds to update-events.
+impl<Window> handler::UpdateHandler for GameMode<Window>
+where Window: piston_window::Window,
+{
+  fn on_update(
+    &mut self,
+    _event: &piston_window::Event,
+    update_args: &piston_window::UpdateArgs,
+  ) -> error::Result<()> {
+    use piston_window::Window; // size
+
+    let changed = match self.watcher {
+      Some(ref mut watcher) => watcher.poll(update_args.dt),
+      None => Vec::new(),
+    };
+    if !changed.is_empty() {
+      self.hot_reload(changed);
+    }
+
+    if let Some(ref mut interstitial) = self.interstitial {
+      interstitial.remaining -= update_args.dt;
+      interstitial.text.update(update_args.dt);
+    }
+    let interstitial_done = match self.interstitial {
+      Some(ref interstitial) => interstitial.remaining <= 0.0,
+      None => false,
+    };
+    if interstitial_done {
+      self.advance_campaign();
+      return Ok(());
+    }
+
+    let won = match self.state {
+      Some(ref mut state) => {
+        // Replays step every update with the dt it was recorded with, and
+        // hold on the last one once they run out.
+        let mut dt = update_args.dt;
+        if let Some(ref mut player) = self.player {
+          match player.dt(self.tick) {
+            Some(recorded) => dt = recorded,
+            None => return Ok(()),
+          }
+          // Feed in the replayed inputs that arrived before this update.
+          for input in player.inputs_for(self.tick) {
+            match input {
+              replay::Input::Press(action) => {
+                state.on_press(action, &mut self.sound_effects)?
+              },
+              replay::Input::Release(action) => {
+                state.on_release(action, &mut self.sound_effects)?
+              },
+            }
+          }
+        }
+
+        let update_args = piston_window::UpdateArgs { dt };
+        let window_size = self.window.borrow().size();
+        state.on_update(&update_args, window_size, &mut self.sound_effects)?;
+        self.tick += 1;
+        if let Some(ref mut recorder) = self.recorder {
+          recorder.update(dt);
+        }
+        state.won()
+      },
+      None => false,
+    };
+
+    // Move on to the next step of the campaign once the hero has ascended.
+    if won {
+      self.advance_campaign();
+    }
 
     Ok(())
   }
@@ -265,9 +1082,9 @@ where Window: piston_window::Window,
 impl<Window> handler::WindowHandler for GameMode<Window>
 where Window: piston_window::OpenGLWindow,
 {
-  fn on_render<Event: piston_window::GenericEvent>(
+  fn on_render(
     &mut self,
-    event: &Event,
+    event: &piston_window::Event,
     _render_args: &piston_window::RenderArgs,
   ) -> error::Result<()> {
     use piston_window::Window; // size
@@ -278,22 +1095,50 @@ where Window: piston_window::OpenGLWindow,
     let window_size = self.window.borrow().size();
 
     self.window.borrow_mut().draw_2d(event, |context, graphics| {
-      let translation = self.state.camera.position;
-      let transform = context
-        .trans(
-          window_size.width as f64 * 0.5 - translation.x,
-          window_size.height as f64 * 0.5 + translation.y,
-        )
-        .zoom(self.state.camera.zoom)
-        .transform;
+      if let Some(ref interstitial) = self.interstitial {
+        piston_window::clear([0.0, 0.0, 0.0, 1.0], graphics);
+
+        let transform = context.transform.trans(50.0, 100.0);
+        piston_window::text::Text::new_color([1.0, 1.0, 1.0, 1.0], 6).draw(
+            &interstitial.title,
+            &mut *self.glyphs.borrow_mut(),
+            &context.draw_state,
+            transform,
+            graphics
+        ).expect("Failed drawing interstitial title");
+
+        let transform = context.transform.trans(50.0, 200.0);
+        piston_window::text::Text::new_color([1.0, 1.0, 1.0, 1.0], 4).draw(
+            &interstitial.text.current_text(),
+            &mut *self.glyphs.borrow_mut(),
+            &context.draw_state,
+            transform,
+            graphics
+        ).expect("Failed drawing interstitial text");
+        return;
+      }
+
+      let state = match self.state {
+        Some(ref state) => state,
+        None => {
+          piston_window::clear([0.0, 0.0, 0.0, 1.0], graphics);
+          if let Some(load_error) = self.load_error.as_ref().or_else(|| self.sound_error.as_ref()) {
+            draw_load_error(load_error, &mut *self.glyphs.borrow_mut(), &context, graphics);
+          }
+          return;
+        },
+      };
+
+      let view_size = [f64::from(window_size.width), f64::from(window_size.height)];
+      let transform = state.camera.transform(context.transform, view_size);
 
       piston_window::clear([1.0; 4], graphics);
-      self.scene.borrow_mut().draw(transform, graphics);
+      state.scene.borrow_mut().draw(transform, graphics);
 
       // Draw text labels over all actors with text
-      for (ref _name, ref entity) in self.state.entities.iter() {
+      for (ref _name, ref entity) in state.entities.iter() {
         let entity = entity.borrow();
-        if entity.text().len() > 0 {
+        if !entity.text().is_empty() {
           // Add some magic numbers to make the text line up in the right spot
           // TODO(daniel): Figure out how to center the text
           let label_tf = transform.trans(entity.position().x - 35.0,
@@ -310,133 +1155,505 @@ where Window: piston_window::OpenGLWindow,
 
       let transform = context.transform.trans(50.0, 100.0);
       piston_window::text::Text::new_color([0.0, 0.0, 0.0, 1.0], 6).draw(
-          &self.state.title_text.current_text(),
+          &state.title_text.current_text(),
           &mut *self.glyphs.borrow_mut(),
           &context.draw_state,
           transform,
           graphics
       ).expect("Failed drawing main story text");
 
-      let transform = context.transform.trans((window_size.width/2 + 200) as f64,
-                                              (window_size.height - 35) as f64);
+      let transform = context.transform.trans(f64::from(window_size.width/2 + 200),
+                                              f64::from(window_size.height - 35));
       piston_window::text::Text::new_color([1.0, 1.0, 1.0, 1.0], 3).draw(
-          &self.state.hint_text.current_text(),
+          &state.hint_text.current_text(),
           &mut *self.glyphs.borrow_mut(),
           &context.draw_state,
           transform,
           graphics
       ).expect("Failed drawing hint text");
+
+      // The level keeps running after a failed reload, so show why the
+      // changes aren't there
+      if let Some(load_error) = self.load_error.as_ref().or_else(|| self.sound_error.as_ref()) {
+        draw_load_error(load_error, &mut *self.glyphs.borrow_mut(), &context, graphics);
+      }
     });
 
     Ok(())
   }
+
+  fn on_close(
+    &mut self,
+    _event: &piston_window::Event,
+    _close_args: &piston_window::CloseArgs,
+  ) -> error::Result<()> {
+    if let Some(ref recorder) = self.recorder {
+      recorder.save()?;
+    }
+    self.sound_effects.stop_music(0.0);
+    Ok(())
+  }
 }
 
 /// Inherit default implementation of EventHandler::on_event.
 impl<Window> handler::EventHandler for GameMode<Window>
 where Window: piston_window::OpenGLWindow,
 {
-  fn before_event<Event: piston_window::GenericEvent>(
+  fn before_event(
     &mut self,
-    event: &Event,
+    event: &piston_window::Event,
   ) -> error::Result<()> {
-    self.scene.borrow_mut().event(event);
+    if let Some(ref state) = self.state {
+      state.scene.borrow_mut().event(event);
+    }
     Ok(())
   }
-}
 
-fn make_actor(
-  actor: &level::Actor,
-  assets: &assets::AssetMap,
-  scene: SceneRcRef,
-) -> Rc<RefCell<entity::Actor>> {
-  Rc::new(RefCell::new(default_actor::DefaultActor::new(actor, assets, scene.clone())))
-}
+  fn on_activate(&mut self) -> error::Result<()> {
+    self.transition = None;
+    self.release_movement()?;
+    let session = match self.session {
+      Some(ref session) => session.borrow().clone(),
+      None => menu::Session::default(),
+    };
 
-fn make_hero(
-  actor: &level::Hero,
-  assets: &assets::AssetMap,
-  scene: SceneRcRef,
-) -> hero::HeroRcRef {
-  Rc::new(RefCell::new(hero::Hero::new(actor, assets, scene.clone())))
+    // Coming from a menu that picked where to start, or starting the game
+    if let Some(step) = session.start_step {
+      if let Some(ref mut session) = self.session {
+        session.borrow_mut().start_step = None;
+      }
+      self.step = step;
+      self.start_campaign_step();
+    } else if self.state.is_none() && self.interstitial.is_none() {
+      self.start_campaign_step();
+    }
+
+    self.sound_effects.set_volumes(&session.audio);
+    Ok(())
+  }
+
+  fn on_deactivate(&mut self) -> error::Result<()> {
+    self.release_movement()
+  }
+
+  fn on_leave(&mut self) -> error::Result<()> {
+    // Runs can end by quitting from a menu rather than closing the window
+    if let Some(ref recorder) = self.recorder {
+      recorder.save()?;
+    }
+    // Don't keep the level's sounds playing over the title screen
+    self.sound_effects.stop_all();
+    Ok(())
+  }
+
+  fn transition(&mut self) -> Option<handler::Transition> {
+    self.transition.take()
+  }
 }
 
-fn make_detective(
-  actor: &level::Detective,
-  assets: &assets::AssetMap,
-  scene: SceneRcRef,
-) -> detective::DetectiveRcRef {
-  Rc::new(RefCell::new(detective::Detective::new(actor, assets, scene.clone())))
+impl<Window> GameMode<Window>
+where
+  Window: piston_window::Window,
+{
+  fn press_action(&mut self, action: config::Action) -> error::Result<()> {
+    // Pausing works even during interstitials and replays.
+    if action == config::Action::Pause {
+      if self.session.is_some() {
+        self.transition = Some(handler::Transition::Push(String::from("pause")));
+      }
+      return Ok(());
+    }
+
+    // Interstitials can be skipped with the interact action.
+    if self.interstitial.is_some() {
+      if action == config::Action::Interact {
+        self.advance_campaign();
+      }
+      return Ok(());
+    }
+
+    // Live input is ignored while a replay is playing.
+    if self.player.is_some() {
+      return Ok(());
+    }
+
+    match action {
+      config::Action::QuickSave => {
+        if let Err(e) = self.quick_save() {
+          println!("Failed to save game: {}", e);
+        }
+        return Ok(());
+      },
+      config::Action::QuickLoad => {
+        if let Err(e) = self.quick_load() {
+          println!("Failed to load game: {}", e);
+        }
+        return Ok(());
+      },
+      _ => {},
+    }
+    if let Some(ref mut recorder) = self.recorder {
+      recorder.record(self.tick, replay::Input::Press(action));
+    }
+    if action.is_movement() {
+      self.held.insert(action);
+    }
+
+    match self.state {
+      Some(ref mut state) => state.on_press(action, &mut self.sound_effects),
+      None => Ok(()),
+    }
+  }
+
+  // Let go of every held movement action, so the hero doesn't keep moving on
+  // a key or axis released while another mode had the input. The player
+  // didn't release them, so they're left out of the replay.
+  fn release_movement(&mut self) -> error::Result<()> {
+    self.axes.clear();
+    let held = ::std::mem::replace(&mut self.held, BTreeSet::new());
+    if let Some(ref mut state) = self.state {
+      for action in held {
+        state.on_release(action, &mut self.sound_effects)?;
+      }
+    }
+    Ok(())
+  }
+
+  fn release_action(&mut self, action: config::Action) -> error::Result<()> {
+    if self.interstitial.is_some() || self.player.is_some() {
+      return Ok(());
+    }
+    if let Some(ref mut recorder) = self.recorder {
+      recorder.record(self.tick, replay::Input::Release(action));
+    }
+    self.held.remove(&action);
+
+    match self.state {
+      Some(ref mut state) => state.on_release(action, &mut self.sound_effects),
+      None => Ok(()),
+    }
+  }
+
+  /// Tear down the current campaign step and start the next one.
+  fn advance_campaign(&mut self) {
+    if let Some(ref recorder) = self.recorder {
+      if let Err(e) = recorder.save() {
+        println!("Failed to save replay: {}", e);
+      }
+    }
+    let next = self.campaign.next_step(self.step);
+    if next != self.step {
+      self.step = next;
+      self.start_campaign_step();
+    }
+  }
+
+  /// The speed the hero moves at; replays use the one they were recorded
+  /// with.
+  fn hero_speed(&self) -> f64 {
+    match self.player {
+      Some(ref player) => player.replay().hero_speed,
+      None => self.input.hero_speed,
+    }
+  }
+
+  fn quick_save(&self) -> error::Result<()> {
+    match self.state {
+      Some(ref state) => {
+        println!("Saving game to {}", QUICK_SAVE_PATH);
+        state.save(self.step).to_path_str(QUICK_SAVE_PATH)
+      },
+      None => Ok(()),
+    }
+  }
+
+  fn quick_load(&mut self) -> error::Result<()> {
+    println!("Loading game from {}", QUICK_SAVE_PATH);
+    let saved = save::SaveGame::from_path_str(QUICK_SAVE_PATH)?;
+    let mut state = State::load(saved.level.clone(), &self.assets)?;
+    state.restore(&saved)?;
+    state.set_hero_speed(self.hero_speed());
+    state.start_music(&mut self.sound_effects);
+
+    self.interstitial = None;
+    self.state = Some(state);
+    self.step = saved.step;
+    self.tick = 0;
+    if self.recorder.is_some() {
+      // The inputs so far no longer lead to the current state.
+      println!("Loaded a saved game; replay recording stopped");
+      self.recorder = None;
+    }
+    Ok(())
+  }
+
+  /// The path of the current campaign step's level, if it is a level.
+  fn level_path(&self) -> Option<String> {
+    match self.campaign.steps.get(self.step) {
+      Some(&campaign::Step::Level(ref path)) => Some(path.to_owned()),
+      _ => None,
+    }
+  }
+
+  /// Load the changed files again and rebuild the level from them. Errors are
+  /// shown on screen, and the level keeps running as it was until the files
+  /// are fixed.
+  fn hot_reload(&mut self, changed: Vec<::std::path::PathBuf>) {
+    let level_path = match self.level_path() {
+      Some(path) => path,
+      None => return,
+    };
+    let level_file = ::std::fs::canonicalize(&level_path).ok();
+
+    let mut level_changed = false;
+    let mut changed_images = BTreeSet::new();
+    for path in changed {
+      if ::std::fs::canonicalize(&path).ok() == level_file {
+        level_changed = true;
+        continue;
+      }
+      match assets::reload_asset(&mut self.window.borrow_mut(), &mut self.assets, &path) {
+        Ok(Some(name)) => {
+          changed_images.insert(name);
+        },
+        Ok(None) => {},
+        Err(e) => {
+          self.load_error = Some(describe_error(&e));
+          return;
+        },
+      }
+    }
+    if !level_changed && changed_images.is_empty() {
+      return;
+    }
+
+    println!("Reloading {}", level_path);
+    if self.state.is_none() {
+      // The level failed to load before, so there's nothing to keep
+      self.start_campaign_step();
+      return;
+    }
+    let assets = &self.assets;
+    let result = match self.state {
+      Some(ref mut state) => level::Level::from_path_str(&level_path)
+        .and_then(|level| state.reload(level, assets, &changed_images)),
+      None => Ok(()),
+    };
+    match result {
+      Ok(()) => {
+        self.load_error = None;
+        if let Some(ref state) = self.state {
+          state.start_music(&mut self.sound_effects);
+        }
+      },
+      Err(e) => {
+        let message = describe_error(&e);
+        println!("Failed to reload level {}: {}", level_path, message);
+        self.load_error = Some(message);
+      },
+    }
+  }
+
+  /// Build the State or Interstitial for the current campaign step, replacing
+  /// whatever was there before.
+  fn start_campaign_step(&mut self) {
+    self.state = None;
+    self.interstitial = None;
+
+    match self.campaign.steps.get(self.step).cloned() {
+      Some(campaign::Step::Level(path)) => {
+        if let Some(ref mut watcher) = self.watcher {
+          watcher.watch_file(::std::path::Path::new(&path));
+        }
+        let loaded = level::Level::from_path_str(&path)
+          .and_then(|level| State::load(level, &self.assets));
+        let mut state = match loaded {
+          Ok(state) => state,
+          Err(e) => {
+            let message = describe_error(&e);
+            println!("Failed to load level {}: {}", path, message);
+            self.load_error = Some(message);
+            return;
+          },
+        };
+        self.load_error = None;
+        let hero_speed = self.hero_speed();
+        state.set_hero_speed(hero_speed);
+        state.start_music(&mut self.sound_effects);
+        self.state = Some(state);
+        self.tick = 0;
+        if let Some(ref mut recorder) = self.recorder {
+          recorder.begin(path, hero_speed);
+        }
+      },
+      Some(campaign::Step::Interstitial { title, lines, duration }) => {
+        self.interstitial = Some(Interstitial::new(title, lines, duration));
+      },
+      None => {
+        // Past the last step; hold on the final screen forever.
+        let mut interstitial = Interstitial::new(String::from("The End"),
+                                                 Vec::new(),
+                                                 1);
+        interstitial.remaining = ::std::f64::INFINITY;
+        self.interstitial = Some(interstitial);
+        self.sound_effects.stop_music(END_MUSIC_FADE);
+      },
+    }
+  }
 }
 
 impl<Window> GameMode<Window>
 where
   Window: piston_window::Window + piston_window::OpenGLWindow,
 {
-  /// Create a GameMode for a new game.
+  /// Create a GameMode for a new game, starting at the first step of the
+  /// campaign.
   pub fn new(
     window: Rc<RefCell<piston_window::PistonWindow<Window>>>,
-  ) -> GameMode<Window> {
-    // TODO: should be loaded as an actor from level
-    let camera = camera::Camera2::new();
+    campaign: campaign::Campaign,
+  ) -> error::Result<GameMode<Window>> {
+    // Load assets
+    let assets = assets::load_assets(&mut window.borrow_mut())?;
 
+    // Load font
+    let glyphs = font::load_font(String::from("Pixel-Noir.ttf"), &mut window.borrow_mut());
+
+    let (sound_effects, sound_error) = load_sound_effects();
+
+    // The first campaign step starts when the game mode is activated
+    let mut game_mode = GameMode::new_with_state(window,
+                                                 campaign,
+                                                 0,
+                                                 None,
+                                                 assets,
+                                                 sound_effects,
+                                                 glyphs);
+    game_mode.sound_error = sound_error;
+    Ok(game_mode)
+  }
+
+  /// Create a GameMode that resumes a saved game.
+  pub fn from_save(
+    window: Rc<RefCell<piston_window::PistonWindow<Window>>>,
+    campaign: campaign::Campaign,
+    saved: save::SaveGame,
+  ) -> error::Result<GameMode<Window>> {
     // Load assets
-    let assets = assets::load_assets(&mut window.borrow_mut());
+    let assets = assets::load_assets(&mut window.borrow_mut())?;
 
     // Load font
     let glyphs = font::load_font(String::from("Pixel-Noir.ttf"), &mut window.borrow_mut());
 
-    let level = level::Level::from_path_str("assets/levels/sample.json")
-        .expect("Failed to load level");
-    let scene = Rc::new(RefCell::new(Scene::new()));
-    let mut state = State::new(level.clone(), camera);
+    let (mut sound_effects, sound_error) = load_sound_effects();
 
-    for actor in level.actors.iter() {
-      state.entities.insert(
-        actor.name.to_owned(),
-        make_actor(&actor, &assets, scene.clone()),
-      );
+    let mut state = State::load(saved.level.clone(), &assets)?;
+    state.restore(&saved)?;
+    state.start_music(&mut sound_effects);
+
+    let mut game_mode = GameMode::new_with_state(window,
+                                                 campaign,
+                                                 saved.step,
+                                                 Some(state),
+                                                 assets,
+                                                 sound_effects,
+                                                 glyphs);
+    game_mode.sound_error = sound_error;
+    Ok(game_mode)
+  }
+
+  /// Create a GameMode that plays back a recorded replay of a level instead of
+  /// responding to input.
+  pub fn new_replay(
+    window: Rc<RefCell<piston_window::PistonWindow<Window>>>,
+    replay: replay::Replay,
+  ) -> error::Result<GameMode<Window>> {
+    let campaign = campaign::Campaign {
+      steps: vec![campaign::Step::Level(replay.level.clone())],
+    };
+    let mut game_mode = GameMode::new(window, campaign)?;
+    game_mode.player = Some(replay::Player::new(replay));
+    let hero_speed = game_mode.hero_speed();
+    if let Some(ref mut state) = game_mode.state {
+      state.set_hero_speed(hero_speed);
     }
+    Ok(game_mode)
+  }
 
-    // insert detective
-    let detective_cfg = level.detective;
-    let detective = make_detective(&detective_cfg, &assets, scene.clone());
-    state.detective = Some(detective.clone());
-    state.entities.insert(detective_cfg.name.to_owned(), detective);
+  /// The images this GameMode loaded, for other modes to share.
+  pub fn assets(&self) -> &assets::AssetMap<piston_window::G2dTexture> {
+    &self.assets
+  }
 
-    // insert hero
-    let hero_cfg = level.hero;
-    let hero = make_hero(&hero_cfg, &assets, scene.clone());
-    state.hero = Some(hero.clone());
-    state.entities.insert(hero_cfg.name.to_owned(), hero);
+  /// Use key bindings and hero speed from input instead of the defaults.
+  pub fn set_input(&mut self, input: config::InputConfig) {
+    self.input = input;
+    let hero_speed = self.hero_speed();
+    if let Some(ref mut state) = self.state {
+      state.set_hero_speed(hero_speed);
+    }
+  }
 
+  /// Share settings with the menus, and let the pause action open the
+  /// "pause" mode.
+  pub fn set_session(&mut self, session: menu::SessionRcRef) {
+    self.session = Some(session);
+  }
 
-    let mut sound_effects = sound::SoundEffects::new();
-    sound_effects.start_music();
+  /// Reload the current level and images whenever their files change, without
+  /// restarting the game.
+  pub fn watch(&mut self) {
+    let mut watcher = watch::Watcher::new();
+    match assets::asset_dir() {
+      Ok(asset_dir) => watcher.watch_dir(&asset_dir),
+      Err(e) => println!("Not watching assets: {}", e),
+    }
+    if let Some(path) = self.level_path() {
+      watcher.watch_file(::std::path::Path::new(&path));
+    }
+    self.watcher = Some(watcher);
+  }
 
-    GameMode::new_with_state(window,
-                             state,
-                             scene.clone(),
-                             sound_effects,
-                             glyphs)
+  /// Record every input into a replay file at path.
+  pub fn record_to(&mut self, path: String) {
+    let mut recorder = replay::Recorder::new(path);
+    if let Some(campaign::Step::Level(ref level)) =
+      self.campaign.steps.get(self.step).cloned()
+    {
+      recorder.begin(level.to_owned(), self.hero_speed());
+    }
+    self.recorder = Some(recorder);
   }
 
   /// Create a GameMode with an existing State.
   pub fn new_with_state(
     window: Rc<RefCell<piston_window::PistonWindow<Window>>>,
-    state: State,
-    //assets: assets::AssetMap,
-    scene: SceneRcRef,
+    campaign: campaign::Campaign,
+    step: usize,
+    state: Option<State<piston_window::G2dTexture>>,
+    assets: assets::AssetMap<piston_window::G2dTexture>,
     sound_effects: sound::SoundEffects,
     glyphs: Rc<RefCell<piston_window::Glyphs>>,
   ) -> GameMode<Window> {
     GameMode {
       window,
       state,
-      //assets,
-      scene,
+      interstitial: None,
+      campaign,
+      step,
+      tick: 0,
+      recorder: None,
+      player: None,
+      input: config::InputConfig::default(),
+      axes: BTreeMap::new(),
+      held: BTreeSet::new(),
+      assets,
       sound_effects,
       glyphs,
+      session: None,
+      transition: None,
+      watcher: None,
+      load_error: None,
+      sound_error: None,
     }
   }
 }
@@ -0,0 +1,108 @@
+extern crate ai_behavior;
+extern crate serde_json;
+extern crate std;
+
+use detective;
+use error;
+use hero;
+use item;
+use killer;
+use level;
+
+/// Saved camera placement.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Camera {
+  pub position: level::Point,
+  pub zoom: f64,
+}
+
+/// Saved state common to every actor in the level.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Actor {
+  pub name: String,
+  pub position: level::Point,
+  pub active: bool,
+  pub visible: bool,
+  pub frame: usize,
+  #[serde(default)]
+  pub state: Option<String>, // for actors with states
+}
+
+/// Saved state of the detective's state machine and behavior tree.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Detective {
+  pub state: detective::DetectiveState,
+  pub next_state: detective::DetectiveState,
+  pub last_obstacle: String,
+  pub last_clue: String,
+  pub found_macguffin: bool,
+  pub direction: bool,
+  pub done: bool,
+  pub next_frame: f64, // seconds left on the current animation frame
+  #[serde(default)]
+  pub awake: bool,
+  #[serde(default)]
+  pub behavior: Option<ai_behavior::State<level::DetectiveAction, ()>>,
+  #[serde(default)]
+  pub clue_is_macguffin: bool,
+  #[serde(default)]
+  pub inspecting: bool,
+  #[serde(default)]
+  pub clue_done: bool,
+}
+
+/// Saved state of the hero.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Hero {
+  pub transparent: bool,
+  pub state: hero::HeroState,
+  pub next_state: hero::HeroState,
+  pub next_frame: f64, // seconds left on the current animation frame
+}
+
+/// Saved state of the killer.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Killer {
+  pub state: killer::KillerState,
+  pub target: String,
+  pub target_x: f64,
+  pub direction: bool,
+  pub flee_time: f64,
+  pub inventory: item::Inventory,
+}
+
+/// Everything needed to resume a game in the middle of a level. The level
+/// itself is stored so a save can be loaded even if the level file changes.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SaveGame {
+  pub step: usize, // campaign step the level belongs to
+  pub level: level::Level,
+  pub camera: Camera,
+  pub actors: Vec<Actor>,
+  pub detective: Detective,
+  pub hero: Hero,
+  #[serde(default)]
+  pub inventory: item::Inventory,
+  #[serde(default)]
+  pub killer: Option<Killer>,
+  pub found: bool,
+  pub win: bool,
+}
+
+impl SaveGame {
+  pub fn from_path_str(path_str: &str) -> error::Result<SaveGame> {
+    Self::from_path(&std::path::Path::new(path_str))
+  }
+
+  pub fn from_path(path: &std::path::Path) -> error::Result<SaveGame> {
+    let file = std::fs::File::open(path)?;
+    let save_game = serde_json::from_reader(file)?;
+    Ok(save_game)
+  }
+
+  pub fn to_path_str(&self, path_str: &str) -> error::Result<()> {
+    let file = std::fs::File::create(std::path::Path::new(path_str))?;
+    serde_json::to_writer_pretty(file, self)?;
+    Ok(())
+  }
+}
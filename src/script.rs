@@ -0,0 +1,232 @@
+use camera;
+use entity;
+use error;
+use font;
+use level;
+use sound;
+
+/// Which kind of interaction fired a trigger.
+#[derive(Copy, Clone, Debug)]
+pub enum Interaction {
+  Hero,
+  Detective,
+}
+
+/// Run the actions of every trigger attached to the actor called `name`.
+pub fn fire(
+  triggers: &[level::Trigger],
+  name: &str,
+  interaction: Interaction,
+  entities: &entity::EntityMap,
+  sounds: &mut sound::SoundEffects,
+  camera: &mut camera::Camera2,
+  story: &mut font::FontTransition,
+) -> error::Result<()> {
+  // Sounds play from the actor the trigger is attached to
+  let emitter = entities.get(name).map(|entity| entity.borrow().position());
+  for trigger in triggers.iter().filter(|trigger| trigger.name == name) {
+    let actions = match interaction {
+      Interaction::Hero => &trigger.on_hero_interact,
+      Interaction::Detective => &trigger.on_detective_interact,
+    };
+    for action in actions.iter() {
+      run_action(action, emitter, entities, sounds, camera, story)?;
+    }
+  }
+  Ok(())
+}
+
+fn run_action(
+  action: &level::Action,
+  emitter: Option<entity::WorldPoint2>,
+  entities: &entity::EntityMap,
+  sounds: &mut sound::SoundEffects,
+  camera: &mut camera::Camera2,
+  story: &mut font::FontTransition,
+) -> error::Result<()> {
+  match *action {
+    level::Action::Show(ref name) => {
+      with_entity(entities, name, |entity| entity.set_visible(true))
+    },
+    level::Action::Hide(ref name) => {
+      with_entity(entities, name, |entity| entity.set_visible(false))
+    },
+    level::Action::Activate(ref name) => {
+      with_entity(entities, name, |entity| entity.set_active(true))
+    },
+    level::Action::Deactivate(ref name) => {
+      with_entity(entities, name, |entity| entity.set_active(false))
+    },
+    level::Action::ToggleActive(ref name) => {
+      with_entity(entities, name, |entity| {
+        let active = entity.active();
+        entity.set_active(!active)
+      })
+    },
+    level::Action::PlaySound(ref sound) => {
+      match emitter {
+        Some(emitter) => sounds.play_at(sound, emitter),
+        None => sounds.play(sound),
+      };
+      Ok(())
+    },
+    level::Action::Say { ref text, duration } => {
+      story.next(text.to_owned(), duration);
+      Ok(())
+    },
+    level::Action::ShakeCamera { strength, duration } => {
+      camera.shake(strength, duration);
+      Ok(())
+    },
+    level::Action::LookAt { ref name, duration } => {
+      with_entity(entities, name, |entity| {
+        camera.look_at(entity.position(), duration);
+        Ok(())
+      })
+    },
+  }
+}
+
+fn with_entity<F>(
+  entities: &entity::EntityMap,
+  name: &str,
+  f: F,
+) -> error::Result<()>
+where
+  F: FnOnce(&mut entity::Actor) -> error::Result<()>,
+{
+  match entities.get(name) {
+    Some(entity) => f(&mut *entity.borrow_mut()),
+    None => {
+      println!("Trigger references unknown actor: {}", name);
+      Ok(())
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  extern crate graphics;
+  extern crate piston_window;
+  extern crate uuid;
+
+  use super::*;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  // An actor that only keeps the flags triggers change
+  struct Prop {
+    name: String,
+    visible: bool,
+    active: bool,
+    text: String,
+  }
+
+  impl entity::Actor for Prop {
+    fn name(&self) -> String { self.name.to_owned() }
+    fn position(&self) -> entity::WorldPoint2 { entity::WorldPoint2::new(0.0, 0.0) }
+    fn velocity(&self) -> entity::WorldVector2 { entity::WorldVector2::new(0.0, 0.0) }
+    fn scale(&self) -> f64 { 1.0 }
+    fn visible(&self) -> bool { self.visible }
+    fn active(&self) -> bool { self.active }
+    fn sprite_id(&self) -> uuid::Uuid { uuid::Uuid::nil() }
+    fn width(&self) -> f64 { 1.0 }
+    fn text(&self) -> &String { &self.text }
+    fn frame(&self) -> usize { 0 }
+    fn bb(&self) -> graphics::types::Rectangle { [0.0, 0.0, 1.0, 1.0] }
+    fn set_text(&mut self, _new_text: String, _time: f64) -> error::Result<()> { Ok(()) }
+    fn set_position(&mut self, _position: entity::WorldPoint2) -> error::Result<()> { Ok(()) }
+    fn set_velocity(&mut self, _velocity: entity::WorldVector2) -> error::Result<()> { Ok(()) }
+    fn set_scale(&mut self, _scale: f64) -> error::Result<()> { Ok(()) }
+    fn set_frame(&mut self, _frame: usize) -> error::Result<()> { Ok(()) }
+    fn set_visible(&mut self, visible: bool) -> error::Result<()> {
+      self.visible = visible;
+      Ok(())
+    }
+    fn set_active(&mut self, active: bool) -> error::Result<()> {
+      self.active = active;
+      Ok(())
+    }
+    fn on_update(&mut self, _update_args: &piston_window::UpdateArgs) -> error::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn props(names: &[&str]) -> entity::EntityMap {
+    let mut entities = entity::EntityMap::new();
+    for name in names.iter() {
+      let prop = Prop {
+        name: name.to_string(),
+        visible: true,
+        active: false,
+        text: String::from(""),
+      };
+      entities.insert(name.to_string(), Rc::new(RefCell::new(prop)));
+    }
+    entities
+  }
+
+  fn flags(entities: &entity::EntityMap, name: &str) -> (bool, bool) {
+    let entity = entities[name].borrow();
+    (entity.visible(), entity.active())
+  }
+
+  #[test]
+  fn runs_the_actions_for_the_interaction() {
+    let triggers = vec![
+      level::Trigger {
+        name: String::from("birds"),
+        on_hero_interact: vec![
+          level::Action::Hide(String::from("birds")),
+          level::Action::ToggleActive(String::from("detective")),
+          level::Action::Say { text: String::from("The birds scatter"), duration: 2 },
+        ],
+        on_detective_interact: vec![level::Action::Show(String::from("body"))],
+      },
+    ];
+    let entities = props(&["birds", "detective", "body"]);
+    let mut sounds = sound::SoundEffects::muted();
+    let mut camera = camera::Camera2::new();
+    let mut story = font::FontTransition::new(Vec::new(), 0);
+
+    fire(&triggers, "birds", Interaction::Hero, &entities, &mut sounds, &mut camera, &mut story)
+      .unwrap();
+    assert_eq!(flags(&entities, "birds"), (false, false));
+    assert_eq!(flags(&entities, "detective"), (true, true));
+    assert_eq!(story.current_text(), "The birds scatter");
+
+    entities["body"].borrow_mut().set_visible(false).unwrap();
+    fire(&triggers, "birds", Interaction::Detective, &entities, &mut sounds, &mut camera, &mut story)
+      .unwrap();
+    assert_eq!(flags(&entities, "body"), (true, false));
+    // The hero's actions didn't run again
+    assert_eq!(flags(&entities, "detective"), (true, true));
+  }
+
+  #[test]
+  fn ignores_other_actors_and_unknown_targets() {
+    let triggers = vec![
+      level::Trigger {
+        name: String::from("car"),
+        on_hero_interact: vec![
+          level::Action::Activate(String::from("nobody")),
+          level::Action::Activate(String::from("car")),
+        ],
+        on_detective_interact: Vec::new(),
+      },
+    ];
+    let entities = props(&["car", "tree"]);
+    let mut sounds = sound::SoundEffects::muted();
+    let mut camera = camera::Camera2::new();
+    let mut story = font::FontTransition::new(Vec::new(), 0);
+
+    fire(&triggers, "tree", Interaction::Hero, &entities, &mut sounds, &mut camera, &mut story)
+      .unwrap();
+    assert_eq!(flags(&entities, "car"), (true, false));
+
+    // An unknown actor is skipped without stopping the rest of the trigger
+    fire(&triggers, "car", Interaction::Hero, &entities, &mut sounds, &mut camera, &mut story)
+      .unwrap();
+    assert_eq!(flags(&entities, "car"), (true, true));
+  }
+}
@@ -0,0 +1,547 @@
+extern crate piston_window;
+
+use assets;
+use config;
+use detective;
+use error;
+use hero;
+use level;
+use replay;
+use rust_rider;
+use save;
+use sound;
+
+/// Default update rate, matching the ups in config.json.
+const DEFAULT_UPS: f64 = 120.0;
+
+/// Runs the gameplay of a single level without a window, audio device or
+/// GPU. Assets are loaded as NullTextures, updates are stepped with a fixed dt
+/// and input is injected directly, so gameplay can be checked on a CI box.
+pub struct Simulation {
+  state: rust_rider::State<assets::NullTexture>,
+  sound_effects: sound::SoundEffects,
+  view_size: piston_window::Size,
+  dt: f64,
+  tick: u64,
+}
+
+impl Simulation {
+  /// Create a Simulation of a level using already loaded assets.
+  pub fn new(
+    level: level::Level,
+    assets: &assets::AssetMap<assets::NullTexture>,
+  ) -> error::Result<Simulation> {
+    Ok(Simulation {
+      state: rust_rider::State::load(level, assets)?,
+      sound_effects: sound::SoundEffects::muted(),
+      view_size: piston_window::Size {
+        width: 1600,
+        height: 1000,
+      },
+      dt: 1.0 / DEFAULT_UPS,
+      tick: 0,
+    })
+  }
+
+  /// Create a Simulation of the level at path_str.
+  pub fn from_path_str(path_str: &str) -> error::Result<Simulation> {
+    let level = level::Level::from_path_str(path_str)?;
+    let assets = assets::load_null_assets()?;
+    Simulation::new(level, &assets)
+  }
+
+  pub fn set_hero_speed(&mut self, hero_speed: f64) {
+    self.state.set_hero_speed(hero_speed);
+  }
+
+  pub fn press(&mut self, action: config::Action) -> error::Result<()> {
+    self.state.on_press(action, &mut self.sound_effects)
+  }
+
+  pub fn release(&mut self, action: config::Action) -> error::Result<()> {
+    self.state.on_release(action, &mut self.sound_effects)
+  }
+
+  /// Advance the simulation by a single update.
+  pub fn step(&mut self) -> error::Result<()> {
+    let dt = self.dt;
+    self.step_by(dt)
+  }
+
+  /// Advance the simulation by a single update of dt seconds.
+  pub fn step_by(&mut self, dt: f64) -> error::Result<()> {
+    let update_args = piston_window::UpdateArgs { dt };
+    self.state.on_update(&update_args, self.view_size, &mut self.sound_effects)?;
+    self.tick += 1;
+    Ok(())
+  }
+
+
+  /// Create a Simulation resuming a saved game.
+  pub fn from_save(saved: &save::SaveGame) -> error::Result<Simulation> {
+    let assets = assets::load_null_assets()?;
+    let mut simulation = Simulation::new(saved.level.clone(), &assets)?;
+    simulation.state.restore(saved)?;
+    Ok(simulation)
+  }
+
+  /// Create a Simulation of the level a replay was recorded on, with the same
+  /// hero speed.
+  pub fn from_replay(replay: &replay::Replay) -> error::Result<Simulation> {
+    let mut simulation = Simulation::from_path_str(&replay.level)?;
+    simulation.set_hero_speed(replay.hero_speed);
+    Ok(simulation)
+  }
+
+  /// Play back every input of a replay, stepping each update with the dt it
+  /// was recorded with.
+  pub fn play(&mut self, replay: &replay::Replay) -> error::Result<()> {
+    let mut player = replay::Player::new(replay.clone());
+    while let Some(dt) = player.dt(self.tick) {
+      for input in player.inputs_for(self.tick) {
+        match input {
+          replay::Input::Press(action) => self.press(action)?,
+          replay::Input::Release(action) => self.release(action)?,
+        }
+      }
+      self.step_by(dt)?;
+    }
+    Ok(())
+  }
+
+  /// Step until predicate returns true or max_ticks updates have run. Returns
+  /// whether the predicate was satisfied.
+  pub fn run_until<F>(&mut self, max_ticks: u64, mut predicate: F) -> error::Result<bool>
+  where
+    F: FnMut(&Simulation) -> bool,
+  {
+    for _ in 0..max_ticks {
+      if predicate(self) {
+        return Ok(true);
+      }
+      self.step()?;
+    }
+    Ok(predicate(self))
+  }
+
+  /// Number of updates run so far.
+  pub fn tick(&self) -> u64 {
+    self.tick
+  }
+
+  pub fn state(&self) -> &rust_rider::State<assets::NullTexture> {
+    &self.state
+  }
+
+  pub fn hero(&self) -> hero::HeroRcRef<assets::NullTexture> {
+    self.state.get_hero()
+  }
+
+  pub fn detective(&self) -> detective::DetectiveRcRef<assets::NullTexture> {
+    self.state.get_detective()
+  }
+
+  /// Print where the hero and detective ended up.
+  fn report(&self, path: &str) {
+    use entity::Actor; // position
+
+    let hero = self.hero();
+    let hero_position = hero.borrow().position();
+    let detective = self.detective();
+    let detective_position = detective.borrow().position();
+    println!("{}: after {} updates the hero is at ({}, {}) and the detective at ({}, {})",
+             path, self.tick(),
+             hero_position.x, hero_position.y,
+             detective_position.x, detective_position.y);
+    println!("{}: the body was {}found",
+             path, if self.state.found() { "" } else { "not " });
+  }
+}
+
+/// Play the level at path without a window for up to seconds, stopping early
+/// if the detective finds the body, and print how it went.
+pub fn run(path: &str, seconds: f64) -> error::Result<()> {
+  let mut simulation = Simulation::from_path_str(path)?;
+  let max_ticks = (seconds * DEFAULT_UPS).round() as u64;
+  simulation.run_until(max_ticks, |simulation| simulation.state().found())?;
+  simulation.report(path);
+  Ok(())
+}
+
+/// Resume the saved game at path without a window, the same way as run.
+pub fn run_save(path: &str, seconds: f64) -> error::Result<()> {
+  let saved = save::SaveGame::from_path_str(path)?;
+  let mut simulation = Simulation::from_save(&saved)?;
+  let max_ticks = (seconds * DEFAULT_UPS).round() as u64;
+  simulation.run_until(max_ticks, |simulation| simulation.state().found())?;
+  simulation.report(path);
+  Ok(())
+}
+
+/// Play back the replay at path without a window and print how it went.
+pub fn run_replay(path: &str) -> error::Result<()> {
+  let replay = replay::Replay::from_path_str(path)?;
+  let mut simulation = Simulation::from_replay(&replay)?;
+  simulation.play(&replay)?;
+  simulation.report(&replay.level);
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use entity;
+  use entity::Actor;
+  use killer;
+
+  const LEVEL_1: &str = "assets/levels/sample.json";
+
+  fn hero_position(simulation: &Simulation) -> entity::WorldPoint2 {
+    let hero = simulation.hero();
+    let hero = hero.borrow();
+    hero.position()
+  }
+
+  fn detective_position(simulation: &Simulation) -> entity::WorldPoint2 {
+    let detective = simulation.detective();
+    let detective = detective.borrow();
+    detective.position()
+  }
+
+  /// Fly the hero horizontally until he reaches x.
+  fn fly_to(simulation: &mut Simulation, x: f64) -> error::Result<()> {
+    let start = hero_position(simulation).x;
+    let action = if x < start { config::Action::MoveLeft } else { config::Action::MoveRight };
+    simulation.press(action)?;
+    let reached = simulation.run_until(10 * DEFAULT_UPS as u64, |simulation| {
+      let hero_x = hero_position(simulation).x;
+      if x < start { hero_x <= x } else { hero_x >= x }
+    })?;
+    simulation.release(action)?;
+    assert!(reached, "hero never reached x = {}", x);
+    Ok(())
+  }
+
+  /// Materialize and interact with whatever the hero is touching, staying
+  /// materialized for seconds to let it play out.
+  fn interact(simulation: &mut Simulation, seconds: f64) -> error::Result<()> {
+    simulation.press(config::Action::Materialize)?;
+    simulation.press(config::Action::Interact)?;
+    simulation.release(config::Action::Interact)?;
+    let ticks = (seconds * DEFAULT_UPS).round() as u64;
+    simulation.run_until(ticks, |_| false)?;
+    simulation.release(config::Action::Materialize)
+  }
+
+  // Saves go through JSON, which can read a float back a hair off what was
+  // written, so compare them to a millionth
+  fn rounded(value: ::serde_json::Value) -> ::serde_json::Value {
+    use serde_json::{Number, Value};
+
+    match value {
+      Value::Number(ref number) if number.is_f64() => {
+        let rounded = (number.as_f64().unwrap_or(0.0) * 1e6).round() / 1e6;
+        Number::from_f64(rounded).map_or(Value::Null, Value::Number)
+      },
+      Value::Array(values) => Value::Array(values.into_iter().map(rounded).collect()),
+      Value::Object(values) => {
+        Value::Object(values.into_iter().map(|(key, value)| (key, rounded(value))).collect())
+      },
+      value => value,
+    }
+  }
+
+  #[test]
+  fn detective_finds_the_body_in_level_1() {
+    let mut simulation = Simulation::from_path_str(LEVEL_1).unwrap();
+
+    // Uncover the body and knock both cairns out of the detective's way
+    interact(&mut simulation, 1.0).unwrap();
+    fly_to(&mut simulation, 2500.0).unwrap();
+    interact(&mut simulation, 1.0).unwrap();
+    fly_to(&mut simulation, 2000.0).unwrap();
+    interact(&mut simulation, 1.0).unwrap();
+    // Wake the detective
+    fly_to(&mut simulation, 750.0).unwrap();
+    interact(&mut simulation, 0.0).unwrap();
+
+    let found = simulation.run_until(120 * DEFAULT_UPS as u64, |simulation| {
+      simulation.state().found()
+    }).unwrap();
+    assert!(found, "detective didn't find the body after {} ticks", simulation.tick());
+    let detective = simulation.detective();
+    assert!(detective.borrow().done());
+  }
+
+  #[test]
+  fn replay_reproduces_the_run() {
+    // Updates of uneven length, as a real window gives them
+    let mut replay = replay::Replay::new(String::from(LEVEL_1), 300.0);
+    replay.dts = (0..240).map(|tick| if tick % 3 == 0 { 0.02 } else { 1.0 / DEFAULT_UPS }).collect();
+    replay.events = vec![
+      replay::Event { tick: 0, input: replay::Input::Press(config::Action::MoveLeft) },
+      replay::Event { tick: 120, input: replay::Input::Release(config::Action::MoveLeft) },
+    ];
+
+    let mut positions = Vec::new();
+    for _ in 0..2 {
+      let mut simulation = Simulation::from_replay(&replay).unwrap();
+      let start = hero_position(&simulation);
+      simulation.play(&replay).unwrap();
+      assert_eq!(simulation.tick(), replay.ticks());
+      // Held for the first 120 recorded updates
+      let held: f64 = replay.dts[..120].iter().sum();
+      let end = hero_position(&simulation);
+      assert!((start.x - end.x - held * replay.hero_speed).abs() < 1e-6);
+      positions.push(end);
+    }
+    assert_eq!(positions[0], positions[1]);
+  }
+
+  #[test]
+  fn a_restored_save_carries_on_like_the_original() {
+    extern crate serde_json;
+
+    let mut simulation = Simulation::from_path_str(LEVEL_1).unwrap();
+    // Wake the detective and leave him part way through the level
+    fly_to(&mut simulation, 750.0).unwrap();
+    interact(&mut simulation, 0.0).unwrap();
+    fly_to(&mut simulation, 1500.0).unwrap();
+    simulation.run_until(3 * DEFAULT_UPS as u64, |_| false).unwrap();
+
+    let saved = serde_json::to_string(&simulation.state().save(0)).unwrap();
+    let loaded: save::SaveGame = serde_json::from_str(&saved).unwrap();
+    let mut restored = Simulation::from_save(&loaded).unwrap();
+    assert_eq!(
+      serde_json::to_string(&restored.state().save(0)).unwrap(),
+      serde_json::to_string(&loaded).unwrap());
+
+    simulation.run_until(5 * DEFAULT_UPS as u64, |_| false).unwrap();
+    restored.run_until(5 * DEFAULT_UPS as u64, |_| false).unwrap();
+    assert_eq!(
+      rounded(serde_json::to_value(&restored.state().save(0)).unwrap()),
+      rounded(serde_json::to_value(&simulation.state().save(0)).unwrap())
+    );
+  }
+
+  #[test]
+  fn detective_follows_the_behavior_tree_from_the_level() {
+    extern crate serde_json;
+
+    // Wait for the hero, then walk the other way
+    let tree = r#"{"Sequence": [
+      {"Action": "WaitForHero"},
+      {"Action": "TurnAround"},
+      {"Action": "Walk"}
+    ]}"#;
+    let mut level = level::Level::from_path_str(LEVEL_1).unwrap();
+    level.detective.behavior = Some(serde_json::from_str(tree).unwrap());
+    let assets = assets::load_null_assets().unwrap();
+    let mut simulation = Simulation::new(level, &assets).unwrap();
+
+    let start = detective_position(&simulation);
+    simulation.run_until(DEFAULT_UPS as u64, |_| false).unwrap();
+    assert_eq!(detective_position(&simulation), start);
+
+    fly_to(&mut simulation, start.x).unwrap();
+    interact(&mut simulation, 0.0).unwrap();
+    simulation.run_until(DEFAULT_UPS as u64, |_| false).unwrap();
+    assert!(detective_position(&simulation).x < start.x);
+  }
+
+  fn actor_position(simulation: &Simulation, name: &str) -> entity::WorldPoint2 {
+    simulation.state().entities()[name].borrow().position()
+  }
+
+  fn detective_text(simulation: &Simulation) -> String {
+    let detective = simulation.detective();
+    let detective = detective.borrow();
+    detective.text().clone()
+  }
+
+  /// Level 1 with a detective who stands still facing right, whatever he
+  /// sees, and the hero holding the stone.
+  fn carrying_the_stone() -> error::Result<Simulation> {
+    extern crate serde_json;
+
+    let mut level = level::Level::from_path_str(LEVEL_1)?;
+    level.detective.behavior = Some(serde_json::from_str(r#"{"Action": "Idle"}"#)?);
+    let assets = assets::load_null_assets()?;
+    let mut simulation = Simulation::new(level, &assets)?;
+    fly_to(&mut simulation, 1200.0)?;
+    simulation.press(config::Action::Materialize)?;
+    simulation.press(config::Action::Interact)?;
+    simulation.release(config::Action::Interact)?;
+    Ok(simulation)
+  }
+
+  #[test]
+  fn the_hero_carries_items_and_puts_them_down() {
+    let mut simulation = carrying_the_stone().unwrap();
+    assert_eq!(simulation.state().save(0).inventory.carrying(), Some("stone"));
+    assert!(!simulation.state().entities()["stone"].borrow().active());
+
+    // The stone goes along, held above the hero
+    fly_to(&mut simulation, 1500.0).unwrap();
+    simulation.step().unwrap();
+    let offset = actor_position(&simulation, "stone") - hero_position(&simulation);
+    assert!(offset.x.abs() < 1e-6 && (offset.y + 40.0).abs() < 1e-6);
+
+    // Put back on the ground it was picked up from, too far ahead of the
+    // detective for him to notice
+    simulation.press(config::Action::Interact).unwrap();
+    simulation.release(config::Action::Interact).unwrap();
+    assert_eq!(simulation.state().save(0).inventory.carrying(), None);
+    let stone = actor_position(&simulation, "stone");
+    assert!((stone.x - hero_position(&simulation).x).abs() < 1e-6);
+    assert!((stone.y - 330.0).abs() < 1e-6);
+    assert!(simulation.state().entities()["stone"].borrow().active());
+    assert_eq!(detective_text(&simulation), "");
+  }
+
+  #[test]
+  fn the_detective_notices_items_dropped_in_front_of_him() {
+    let mut simulation = carrying_the_stone().unwrap();
+    fly_to(&mut simulation, 1000.0).unwrap();
+
+    // Letting go of Materialize drops the stone too
+    simulation.release(config::Action::Materialize).unwrap();
+    assert_eq!(simulation.state().save(0).inventory.carrying(), None);
+    assert_eq!(detective_text(&simulation), "What was that?");
+  }
+
+  /// Level 1 with a killer at x, who walks between patrol and stashes clues
+  /// at stash.
+  fn with_killer(x: f64, patrol: (f64, f64), stash: f64) -> error::Result<Simulation> {
+    let mut level = level::Level::from_path_str(LEVEL_1)?;
+    level.killer = Some(level::Killer {
+      name: String::from("killer"),
+      idle: level.detective.idle.clone(),
+      walk: level.detective.walk.clone(),
+      work: level.detective.clue.clone(),
+      scared_sound: String::from(""),
+      position: level::Point { x, y: 300.0 },
+      scale: 10.0,
+      width: 12,
+      speed: 100.0,
+      perception: 500.0,
+      patrol,
+      stash,
+      shape: None,
+      frame_sounds: Vec::new(),
+    });
+    let assets = assets::load_null_assets()?;
+    Simulation::new(level, &assets)
+  }
+
+  fn killer_save(simulation: &Simulation) -> save::Killer {
+    simulation.state().save(0).killer.unwrap()
+  }
+
+  fn killer_state(simulation: &Simulation) -> killer::KillerState {
+    killer_save(simulation).state
+  }
+
+  #[test]
+  fn the_killer_walks_his_patrol() {
+    let mut simulation = with_killer(2800.0, (2600.0, 3000.0), 3500.0).unwrap();
+    let (mut min, mut max) = (2800.0, 2800.0);
+    simulation.run_until(10 * DEFAULT_UPS as u64, |simulation| {
+      let x = actor_position(simulation, "killer").x;
+      min = if x < min { x } else { min };
+      max = if x > max { x } else { max };
+      false
+    }).unwrap();
+    assert!(min > 2590.0 && min < 2650.0, "patrolled left to {}", min);
+    assert!(max > 2950.0 && max < 3010.0, "patrolled right to {}", max);
+    assert_eq!(killer_state(&simulation), killer::KillerState::Patrol);
+  }
+
+  #[test]
+  fn the_killer_closes_opened_props() {
+    let mut simulation = with_killer(2100.0, (2100.0, 2900.0), 3500.0).unwrap();
+    simulation.state().entities()["cairn2"].borrow_mut()
+      .interact_hero(&mut sound::SoundEffects::muted());
+
+    let working = simulation.run_until(10 * DEFAULT_UPS as u64, |simulation| {
+      killer_state(simulation) == killer::KillerState::Work
+    }).unwrap();
+    assert!(working);
+    assert_eq!(killer_save(&simulation).target, "cairn2");
+    let done = simulation.run_until(10 * DEFAULT_UPS as u64, |simulation| {
+      killer_state(simulation) == killer::KillerState::Patrol
+    }).unwrap();
+    assert!(done);
+
+    // Give the prop time to finish closing
+    simulation.run_until(3 * DEFAULT_UPS as u64, |_| false).unwrap();
+    assert!(!simulation.state().entities()["cairn2"].borrow().disturbed());
+  }
+
+  #[test]
+  fn the_killer_carries_clues_off_to_his_stash() {
+    let mut simulation = with_killer(800.0, (800.0, 1000.0), 600.0).unwrap();
+    let carrying = simulation.run_until(20 * DEFAULT_UPS as u64, |simulation| {
+      killer_state(simulation) == killer::KillerState::Carry
+    }).unwrap();
+    assert!(carrying);
+    assert_eq!(killer_save(&simulation).inventory.carrying(), Some("stone"));
+
+    let stashed = simulation.run_until(20 * DEFAULT_UPS as u64, |simulation| {
+      killer_state(simulation) == killer::KillerState::Patrol
+    }).unwrap();
+    assert!(stashed);
+    assert_eq!(killer_save(&simulation).inventory.carrying(), None);
+    assert!((actor_position(&simulation, "stone").x - 600.0).abs() <= 40.0);
+  }
+
+  #[test]
+  fn the_materialized_ghost_scares_the_killer_off() {
+    // Standing guard, facing right
+    let mut simulation = with_killer(1500.0, (1500.0, 1500.0), 600.0).unwrap();
+    fly_to(&mut simulation, 1700.0).unwrap();
+    assert_eq!(killer_state(&simulation), killer::KillerState::Patrol);
+
+    simulation.press(config::Action::Materialize).unwrap();
+    simulation.step().unwrap();
+    assert_eq!(killer_state(&simulation), killer::KillerState::Flee);
+    assert_eq!(simulation.state().entities()["killer"].borrow().text(), "Who's there?!");
+    assert!(!simulation.state().entities()["killer"].borrow().direction());
+    simulation.release(config::Action::Materialize).unwrap();
+
+    // He runs off at twice his speed, then calms down
+    let start = actor_position(&simulation, "killer").x;
+    simulation.run_until(DEFAULT_UPS as u64, |_| false).unwrap();
+    assert!((start - actor_position(&simulation, "killer").x - 200.0).abs() < 5.0);
+    simulation.run_until(3 * DEFAULT_UPS as u64, |_| false).unwrap();
+    assert_eq!(killer_state(&simulation), killer::KillerState::Patrol);
+  }
+
+  #[test]
+  fn hero_and_camera_stay_in_the_world_bounds() {
+    let mut simulation = Simulation::from_path_str(LEVEL_1).unwrap();
+
+    // Fly up and left into the corner of the world
+    simulation.press(config::Action::MoveUp).unwrap();
+    simulation.press(config::Action::MoveLeft).unwrap();
+    simulation.run_until(10 * DEFAULT_UPS as u64, |_| false).unwrap();
+    simulation.release(config::Action::MoveUp).unwrap();
+    simulation.release(config::Action::MoveLeft).unwrap();
+    simulation.step().unwrap();
+
+    // The hero keeps 75 units from the bounds; the 1600x1000 view stays
+    // inside them, which leaves it no room to move on Y.
+    assert_eq!(hero_position(&simulation), entity::WorldPoint2::new(125.0, -425.0));
+    // The camera eases into place, so it's only close after 10 seconds
+    let camera = simulation.state().save(0).camera.position;
+    assert!((camera.x - 850.0).abs() < 1e-3 && camera.y.abs() < 1e-3,
+            "camera at ({}, {})", camera.x, camera.y);
+
+    // Down to the bottom edge
+    simulation.press(config::Action::MoveDown).unwrap();
+    simulation.run_until(10 * DEFAULT_UPS as u64, |_| false).unwrap();
+    simulation.release(config::Action::MoveDown).unwrap();
+    simulation.step().unwrap();
+    assert_eq!(hero_position(&simulation), entity::WorldPoint2::new(125.0, 425.0));
+  }
+}
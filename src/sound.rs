@@ -1,104 +1,322 @@
-#[cfg(unix)]
-extern crate ears;
+extern crate serde_json;
+extern crate std;
 
-use std::thread;
-use std::time;
-#[cfg(unix)]
-use self::ears::{Sound, Music, AudioController};
+use std::collections::BTreeMap;
 
+use camera;
+use config;
+use error;
+use level;
+use mixer;
+
+/// Where the sound manifest lives; sound files are relative to SOUNDS_DIR.
+pub const MANIFEST_PATH: &str = "assets/sounds.json";
+pub const SOUNDS_DIR: &str = "assets/sounds/";
+
+/// Sounds closer than this to the listener play at full volume; from there
+/// they fade out until they're silent at SILENT_DISTANCE.
+const FULL_VOLUME_DISTANCE: f64 = 800.0;
+const SILENT_DISTANCE: f64 = 4000.0;
+/// Horizontal distance at which a sound is panned fully to one side.
+const FULL_PAN_DISTANCE: f64 = 1600.0;
+
+fn default_sound_volume() -> f32 {
+  1.0
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Category {
+  Sfx,
+  Music,
+}
+
+impl Default for Category {
+  fn default() -> Category {
+    Category::Sfx
+  }
+}
+
+/// How to play a sound.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SoundDef {
+  pub file: String, // relative to assets/sounds
+  #[serde(default = "default_sound_volume")]
+  pub volume: f32,
+  #[serde(default)]
+  pub max_duration: Option<f64>, // seconds; the sound is cut off after this
+  #[serde(default)]
+  pub looping: bool,
+  #[serde(default)]
+  pub category: Category,
+}
+
+/// Every sound the game can play, by name.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SoundManifest {
+  pub sounds: BTreeMap<String, SoundDef>,
+}
+
+impl SoundManifest {
+  pub fn from_path_str(path_str: &str) -> error::Result<SoundManifest> {
+    Self::from_path(&std::path::Path::new(path_str))
+  }
+
+  pub fn from_path(path: &std::path::Path) -> error::Result<SoundManifest> {
+    let file = std::fs::File::open(path)?;
+    let manifest = serde_json::from_reader(file)?;
+    Ok(manifest)
+  }
+
+  pub fn get(&self, name: &str) -> Option<&SoundDef> {
+    self.sounds.get(name)
+  }
+
+  /// Check that every sound a level refers to is in the manifest.
+  pub fn validate(&self, level: &level::Level) -> error::Result<()> {
+    let missing = self.missing(level);
+    if missing.is_empty() {
+      Ok(())
+    } else {
+      Err(error::Error::from(format!("Unknown sounds: {}", missing.join(", "))))
+    }
+  }
+
+  /// The sounds a level refers to that aren't in the manifest, sorted.
+  pub fn missing(&self, level: &level::Level) -> Vec<String> {
+    let mut names = vec![&level.hero.sound, &level.detective.clue_sound, &level.music.explore];
+    names.extend(level.music.tension.iter());
+    names.extend(level.music.resolution.iter());
+    names.extend(level.actors.iter().map(|actor| &actor.sound));
+    names.extend(level.killer.iter().map(|killer| &killer.scared_sound));
+    let frame_sounds = level.hero.frame_sounds.iter()
+      .chain(level.detective.frame_sounds.iter())
+      .chain(level.killer.iter().flat_map(|killer| killer.frame_sounds.iter()))
+      .chain(level.actors.iter().flat_map(|actor| actor.frame_sounds.iter()));
+    names.extend(frame_sounds.map(|frame_sound| &frame_sound.sound));
+    let states = level.actors.iter().flat_map(|actor| actor.states.iter());
+    names.extend(states.map(|state| &state.sound));
+    for trigger in level.triggers.iter() {
+      for action in trigger.on_hero_interact.iter().chain(trigger.on_detective_interact.iter()) {
+        if let level::Action::PlaySound(ref name) = *action {
+          names.push(name);
+        }
+      }
+    }
+
+    // Empty names mean no sound
+    let mut missing: Vec<String> = names.into_iter()
+      .filter(|name| !name.is_empty() && !self.sounds.contains_key(name.as_str()))
+      .cloned()
+      .collect();
+    missing.sort();
+    missing.dedup();
+    missing
+  }
+
+  /// The sounds whose file doesn't exist under dir, with their file.
+  pub fn missing_files(&self, dir: &std::path::Path) -> Vec<(String, String)> {
+    self.sounds.iter()
+      .filter(|&(_, sound)| !dir.join(&sound.file).is_file())
+      .map(|(name, sound)| (name.clone(), sound.file.clone()))
+      .collect()
+  }
+}
+
+/// Plays sounds from the manifest by name.
 pub struct SoundEffects {
-  music: Option<thread::JoinHandle<()>>,
-  sounds: Vec<thread::JoinHandle<()>>,
+  manifest: SoundManifest,
+  mixer: mixer::Mixer,
+  music: Option<(String, mixer::VoiceId)>, // name and voice of the current music
+  listener: Option<camera::WorldPoint2>,
 }
 
 impl SoundEffects {
-  pub fn new() -> SoundEffects {
+  /// Create SoundEffects playing the sounds in the sound manifest.
+  pub fn new() -> error::Result<SoundEffects> {
+    use error::ResultExt; // chain_err
+
+    let manifest = SoundManifest::from_path_str(MANIFEST_PATH)
+        .chain_err(|| format!("Failed to load sound manifest {}", MANIFEST_PATH))?;
+    Ok(SoundEffects {
+      manifest,
+      mixer: mixer::Mixer::default_device(),
+      music: None,
+      listener: None,
+    })
+  }
+
+  /// Create SoundEffects that never touch the audio device. Sounds still
+  /// go through a mixer, which plays them silently.
+  pub fn muted() -> SoundEffects {
     SoundEffects {
+      manifest: SoundManifest::from_path_str(MANIFEST_PATH).unwrap_or_default(),
+      mixer: mixer::Mixer::null(),
       music: None,
-      sounds: Vec::new(),
+      listener: None,
     }
   }
 
-  #[cfg(not(unix))]
-  pub fn start_music(&mut self) {
-    println!("Error: Cannot play sound on Windows");
+  /// Crossfade from the current music to the sound called name over
+  /// crossfade seconds. Does nothing if that music is already playing.
+  pub fn play_music(&mut self, name: &str, crossfade: f64) {
+    if let Some((ref current, _)) = self.music {
+      if current == name {
+        return;
+      }
+    }
+    self.stop_music(crossfade);
+    if let Some(voice) = self.play_placed(name, mixer::Placement::centered(), crossfade) {
+      self.music = Some((name.to_owned(), voice));
+    }
   }
 
-  #[cfg(not(unix))]
-  pub fn play(&mut self, file: &str) {
-    println!("Error: Cannot play sound on Windows");
+  /// Fade the current music out over fade seconds, or stop it right away if
+  /// fade is 0.
+  pub fn stop_music(&mut self, fade: f64) {
+    if let Some((_, voice)) = self.music.take() {
+      if fade > 0.0 {
+        self.mixer.fade_out(voice, fade);
+      } else {
+        self.mixer.stop(voice);
+      }
+    }
   }
 
-  #[cfg(unix)]
-  pub fn start_music(&mut self) {
-    if self.music.is_none() {
-        let path = String::from("assets/sounds/music/background_theme.wav");
-        let handle = thread::spawn(move || {
-          let mut music = Music::new(&path).unwrap();
-          music.set_looping(true);
-          music.set_volume(0.75);
-          music.play();
-          while music.is_playing() {
-            thread::sleep(time::Duration::from_secs(1));
-            // Todo: Maybe something here
-          }
-        });
-        self.music = Some(handle);
-    } else {
-        println!("Stop right there criminal scum.");
-    }
+  /// Where positional sounds are heard from; usually the camera position.
+  pub fn set_listener(&mut self, listener: camera::WorldPoint2) {
+    self.listener = Some(listener);
   }
 
+  /// Play the sound called name, returning its voice so it can be stopped.
+  pub fn play(&mut self, name: &str) -> Option<mixer::VoiceId> {
+    self.play_placed(name, mixer::Placement::centered(), 0.0)
+  }
 
-  #[cfg(unix)]
-  pub fn play(&mut self, file: &str) {
-    let mut path = String::from("assets/sounds/effects/");
-    let mut filename = "";
-    let mut max_length = 0;
-    let mut volume = 1.0;
+  /// Play the sound called name as if it came from emitter, so it's quieter
+  /// the further it is from the listener and panned to the side it's on.
+  pub fn play_at(
+    &mut self,
+    name: &str,
+    emitter: camera::WorldPoint2,
+  ) -> Option<mixer::VoiceId> {
+    let placement = match self.listener {
+      Some(listener) => place(emitter, listener),
+      None => mixer::Placement::centered(),
+    };
+    self.play_placed(name, placement, 0.0)
+  }
 
-    match file {
-      "cans" => {
-        filename = "cans.wav";
-        max_length = 1500;
-      },
-      "car_horn" => {
-          filename = "car_horn.wav";
-          volume = 0.1;
-      },
-      "clue" => {
-          volume = 0.50;
-          filename = "clue.wav";
+  fn play_placed(
+    &mut self,
+    name: &str,
+    placement: mixer::Placement,
+    fade_in: f64,
+  ) -> Option<mixer::VoiceId> {
+    match self.manifest.get(name) {
+      Some(sound) => {
+        let path = format!("{}{}", SOUNDS_DIR, sound.file);
+        Some(self.mixer.play(path, sound, placement, fade_in))
       },
-      "crow_squawk" => filename = "crow_squawk.wav",
-      "crunchy_leaf" => filename = "crunchy_leaf.wav",
-      "foliage_rustle" => {
-          filename = "foliage_rustle.wav";
-          max_length = 2000;
+      None => {
+        println!("Could not find sound: {}", name);
+        None
       },
-      "rocks" => filename = "rocks.wav",
-      "spooked_birds" => filename = "spooked_birds.wav",
-      "twig_snap" => filename = "twig_snap.wav",
-      _ => {}
     }
+  }
 
-    if filename == "" {
-      println!("Could not find file: {}", file);
-      return ();
-    }
+  /// Stop the music and every sound effect right away.
+  pub fn stop_all(&mut self) {
+    self.mixer.stop_all();
+    self.music = None;
+  }
 
-    path.push_str(filename);
-    let handle = thread::spawn(move || {
-      let mut sound = Sound::new(&path).unwrap();
-        sound.set_volume(volume);
-        sound.play();
-        if max_length > 0 {
-            thread::sleep(time::Duration::from_millis(max_length));
-        } else {
-           while sound.is_playing() { }
-        }
-    });
+  pub fn set_volumes(&self, audio: &config::AudioConfig) {
+    self.mixer.set_master_volume(audio.master_volume);
+    self.mixer.set_category_volume(Category::Music, audio.music_volume);
+    self.mixer.set_category_volume(Category::Sfx, audio.sfx_volume);
+  }
+}
 
-    self.sounds.push(handle);
+// How a sound at emitter is heard by a listener
+fn place(emitter: camera::WorldPoint2, listener: camera::WorldPoint2) -> mixer::Placement {
+  let offset = emitter - listener;
+  let distance = offset.norm();
+  let gain = 1.0 - (distance - FULL_VOLUME_DISTANCE) / (SILENT_DISTANCE - FULL_VOLUME_DISTANCE);
+  let pan = offset.x / FULL_PAN_DISTANCE;
+  mixer::Placement {
+    gain: gain.max(0.0).min(1.0) as f32,
+    pan: pan.max(-1.0).min(1.0) as f32,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn assert_placed(emitter: camera::WorldPoint2, gain: f32, pan: f32) {
+    let placement = place(emitter, camera::WorldPoint2::new(1000.0, 0.0));
+    assert!((placement.gain - gain).abs() < 1e-6,
+            "gain {} at {}, expected {}", placement.gain, emitter, gain);
+    assert!((placement.pan - pan).abs() < 1e-6,
+            "pan {} at {}, expected {}", placement.pan, emitter, pan);
+  }
+
+  #[test]
+  fn nearby_sounds_play_at_full_volume() {
+    assert_placed(camera::WorldPoint2::new(1000.0, 0.0), 1.0, 0.0);
+    assert_placed(camera::WorldPoint2::new(1400.0, 0.0), 1.0, 0.25);
+    assert_placed(camera::WorldPoint2::new(1000.0, -800.0), 1.0, 0.0);
+  }
+
+  #[test]
+  fn distant_sounds_fade_to_silence() {
+    assert_placed(camera::WorldPoint2::new(1000.0, 2400.0), 0.5, 0.0);
+    assert_placed(camera::WorldPoint2::new(1000.0, 4000.0), 0.0, 0.0);
+    assert_placed(camera::WorldPoint2::new(1000.0, 9000.0), 0.0, 0.0);
+  }
+
+  #[test]
+  fn sounds_pan_to_the_side_they_are_on() {
+    assert_placed(camera::WorldPoint2::new(200.0, 0.0), 1.0, -0.5);
+    assert_placed(camera::WorldPoint2::new(3400.0, 0.0), 0.5, 1.0);
+    assert_placed(camera::WorldPoint2::new(-5000.0, 0.0), 0.0, -1.0);
+  }
+
+  #[test]
+  fn music_only_changes_for_a_different_track() {
+    let mut sounds = SoundEffects::muted();
+    sounds.play_music("background_theme", 2.0);
+    let theme = sounds.music.clone().unwrap();
+    sounds.play_music("background_theme", 2.0);
+    assert_eq!(sounds.music, Some(theme.clone()));
+
+    sounds.play_music("happy_ending", 2.0);
+    let ending = sounds.music.clone().unwrap();
+    assert_eq!(ending.0, "happy_ending");
+    assert!(ending.1 != theme.1);
+
+    sounds.stop_music(0.0);
+    assert_eq!(sounds.music, None);
+  }
+
+  #[test]
+  fn every_manifest_sound_has_a_file() {
+    let manifest = SoundManifest::from_path_str(MANIFEST_PATH).unwrap();
+    let missing = manifest.missing_files(std::path::Path::new(SOUNDS_DIR));
+    assert!(missing.is_empty(), "missing sound files: {:?}", missing);
+  }
+
+  #[test]
+  fn reports_sounds_without_a_file() {
+    let mut manifest = SoundManifest::from_path_str(MANIFEST_PATH).unwrap();
+    manifest.sounds.insert(String::from("nothing"), SoundDef {
+      file: String::from("effects/nothing.wav"),
+      volume: 1.0,
+      max_duration: None,
+      looping: false,
+      category: Category::Sfx,
+    });
+    let missing = manifest.missing_files(std::path::Path::new(SOUNDS_DIR));
+    assert_eq!(missing, vec![(String::from("nothing"), String::from("effects/nothing.wav"))]);
   }
 }
@@ -0,0 +1,372 @@
+extern crate std;
+extern crate tiled;
+
+use std::collections::BTreeSet;
+use std::path::{Component, Path, PathBuf};
+
+use error;
+use level;
+
+// Mask off the flip flags Tiled stores in the high bits of a gid
+const GID_MASK: u32 = 0x1FFF_FFFF;
+
+/// Load a level from a Tiled map.
+///
+/// The whole map is scaled by the map's "scale" property (1 by default), so
+/// pixel art can be laid out at its native size. Tile layers become static
+/// background actors, and only tilesets that are collections of images are
+/// supported, since every tile has to be an asset. The "music",
+/// "tension_music" and "resolution_music" map properties set the level's
+/// music. Object layers become
+/// actors:
+///  - an object of type "Hero" or "Detective" becomes the hero or detective,
+///    with their animations given by properties of the same names as in the
+///    level JSON
+///  - objects in a layer named "geometry" become static level geometry
+///  - any other object becomes an actor; its "actor_type" property is one of
+///    Static (default), Obstacle, Clue or Macguffin, and its image is its tile
+///    or else its "image" property. "sound", "active", "reversible" and
+///    "width" properties fill in the fields of the same name.
+///
+/// Tile actors are named <layer>_<column>_<row>. Every actor needs a name of
+/// its own, so an object named the same as another actor is an error.
+pub fn load_level(path: &Path) -> error::Result<level::Level> {
+  let map = tiled::parse_file(path)
+    .map_err(|e| error::Error::from(format!("Failed to parse {}: {:?}", path.display(), e)))?;
+  make_level(&map, path)
+}
+
+// Build the level of a map parsed from path
+fn make_level(map: &tiled::Map, path: &Path) -> error::Result<level::Level> {
+  let map_dir = path.parent().unwrap_or_else(|| Path::new(""));
+  let scale = float_property(&map.properties, "scale").unwrap_or(1.0);
+
+  let mut actors = Vec::new();
+  let mut geometry = Vec::new();
+  let mut hero = None;
+  let mut detective = None;
+
+  for layer in map.layers.iter() {
+    for (row, tiles) in layer.tiles.iter().enumerate() {
+      for (column, gid) in tiles.iter().enumerate() {
+        let gid = gid & GID_MASK;
+        if gid == 0 {
+          continue;
+        }
+        let image = tile_image(map, gid)?;
+        // Tiles are drawn from the bottom left corner of their cell
+        let x = f64::from(column as u32 * map.tile_width) + f64::from(image.width) / 2.0;
+        let y = f64::from((row as u32 + 1) * map.tile_height) - f64::from(image.height) / 2.0;
+        actors.push(level::Actor {
+          name: format!("{}_{}_{}", layer.name, column, row),
+          actor_type: level::ActorType::Static,
+          image: asset_key(map_dir, &image.source)?,
+          sound: String::from(""),
+          position: level::Point { x: x * scale, y: y * scale },
+          scale,
+          width: image.width as u32,
+          visible: layer.visible,
+          active: false,
+          reversible: false,
+          carryable: false,
+          shape: None,
+          body: None,
+          frame_sounds: Vec::new(),
+          states: Vec::new(),
+        });
+      }
+    }
+  }
+
+  for group in map.object_groups.iter() {
+    for object in group.objects.iter() {
+      if group.name == "geometry" {
+        geometry.push(make_geometry(object, scale));
+        continue;
+      }
+
+      // Rectangles are placed from their top left corner, tiles from their
+      // bottom left corner
+      let (object_width, object_height) = object_size(object);
+      let x = f64::from(object.x) + f64::from(object_width) / 2.0;
+      let y = if object.gid == 0 {
+        f64::from(object.y) + f64::from(object_height) / 2.0
+      } else {
+        f64::from(object.y) - f64::from(object_height) / 2.0
+      };
+      let position = level::Point { x: x * scale, y: y * scale };
+      let properties = &object.properties;
+      let width = float_property(properties, "width")
+        .map(|width| width as u32)
+        .unwrap_or(object_width as u32);
+
+      match object.obj_type.as_str() {
+        "Hero" => {
+          hero = Some(level::Hero {
+            name: name_or(&object.name, "hero"),
+            idle: required_property(properties, "idle", &object.name)?,
+            ascend: required_property(properties, "ascend", &object.name)?,
+            sound: string_property(properties, "sound").unwrap_or_default(),
+            position,
+            scale,
+            width,
+            shape: None,
+            frame_sounds: Vec::new(),
+          });
+        },
+        "Detective" => {
+          detective = Some(level::Detective {
+            name: name_or(&object.name, "detective"),
+            idle: required_property(properties, "idle", &object.name)?,
+            walk: required_property(properties, "walk", &object.name)?,
+            clue: required_property(properties, "clue", &object.name)?,
+            clue_sound: string_property(properties, "clue_sound").unwrap_or_default(),
+            position,
+            scale,
+            width,
+            speed: float_property(properties, "speed").unwrap_or(100.0),
+            shape: None,
+            behavior: None,
+            frame_sounds: Vec::new(),
+          });
+        },
+        _ => {
+          let image = if object.gid != 0 {
+            asset_key(map_dir, &tile_image(map, object.gid & GID_MASK)?.source)?
+          } else {
+            required_property(properties, "image", &object.name)?
+          };
+          let actor_type = match string_property(properties, "actor_type") {
+            None => level::ActorType::Static,
+            Some(ref actor_type) if actor_type == "Static" => level::ActorType::Static,
+            Some(ref actor_type) if actor_type == "Obstacle" => level::ActorType::Obstacle,
+            Some(ref actor_type) if actor_type == "Clue" => level::ActorType::Clue(false),
+            Some(ref actor_type) if actor_type == "Macguffin" => level::ActorType::Clue(true),
+            Some(actor_type) => {
+              return Err(error::Error::from(
+                format!("Unknown actor_type {} on {}", actor_type, object.name)));
+            },
+          };
+          actors.push(level::Actor {
+            name: object.name.to_owned(),
+            actor_type,
+            image,
+            sound: string_property(properties, "sound").unwrap_or_default(),
+            position,
+            scale,
+            width,
+            visible: object.visible,
+            active: bool_property(properties, "active").unwrap_or(false),
+            reversible: bool_property(properties, "reversible").unwrap_or(false),
+            carryable: bool_property(properties, "carryable").unwrap_or(false),
+            shape: None,
+            body: None,
+            frame_sounds: Vec::new(),
+            states: Vec::new(),
+          });
+        },
+      }
+    }
+  }
+
+  let hero = hero.ok_or_else(|| {
+    error::Error::from(format!("{} has no Hero object", path.display()))
+  })?;
+  let detective = detective.ok_or_else(|| {
+    error::Error::from(format!("{} has no Detective object", path.display()))
+  })?;
+
+  // Entities are looked up by name, so a duplicate would silently replace the
+  // actor it shares a name with
+  {
+    let mut names = BTreeSet::new();
+    let all_names = actors.iter()
+      .map(|actor| &actor.name)
+      .chain(vec![&hero.name, &detective.name]);
+    for name in all_names {
+      if !names.insert(name) {
+        return Err(error::Error::from(
+          format!("{} has more than one actor named {}", path.display(), name)));
+      }
+    }
+  }
+
+  let world_width = f64::from(map.width * map.tile_width) * scale;
+  let world_height = f64::from(map.height * map.tile_height) * scale;
+
+  Ok(level::Level {
+    world_bounds: (
+      level::Point { x: 0.0, y: 0.0 },
+      level::Point { x: world_width, y: world_height },
+    ),
+    hero,
+    detective,
+    killer: None,
+    actors,
+    triggers: Vec::new(),
+    geometry,
+    gravity: level::default_gravity(),
+    music: match string_property(&map.properties, "music") {
+      Some(explore) => level::Music {
+        explore,
+        tension: string_property(&map.properties, "tension_music"),
+        resolution: string_property(&map.properties, "resolution_music"),
+      },
+      None => level::Music::default(),
+    },
+  })
+}
+
+fn make_geometry(object: &tiled::Object, scale: f64) -> level::Geometry {
+  match object.shape {
+    tiled::ObjectShape::Polygon { ref points } => level::Geometry {
+      position: level::Point { x: f64::from(object.x) * scale, y: f64::from(object.y) * scale },
+      shape: level::Shape::Polygon(points.iter()
+        .map(|&(x, y)| level::Point { x: f64::from(x) * scale, y: f64::from(y) * scale })
+        .collect()),
+    },
+    _ => {
+      let (width, height) = object_size(object);
+      level::Geometry {
+        position: level::Point {
+          x: f64::from(object.x + width / 2.0) * scale,
+          y: f64::from(object.y + height / 2.0) * scale,
+        },
+        shape: level::Shape::Aabb {
+          half_width: f64::from(width) / 2.0 * scale,
+          half_height: f64::from(height) / 2.0 * scale,
+        },
+      }
+    },
+  }
+}
+
+// Width and height of a rectangle, ellipse or tile object; 0 for the others
+fn object_size(object: &tiled::Object) -> (f32, f32) {
+  match object.shape {
+    tiled::ObjectShape::Rect { width, height } |
+    tiled::ObjectShape::Ellipse { width, height } => (width, height),
+    _ => (0.0, 0.0),
+  }
+}
+
+// The image of the tile with the given gid
+fn tile_image(map: &tiled::Map, gid: u32) -> error::Result<&tiled::Image> {
+  let tileset = map.get_tileset_by_gid(gid)
+    .ok_or_else(|| error::Error::from(format!("No tileset has tile {}", gid)))?;
+  let id = gid - tileset.first_gid;
+  tileset.tiles.iter()
+    .find(|tile| tile.id == id)
+    .and_then(|tile| tile.images.first())
+    .ok_or_else(|| error::Error::from(format!(
+      "Tile {} of tileset {} has no image; only image collection tilesets are supported",
+      id, tileset.name)))
+}
+
+// Asset map key of an image referenced from a map in map_dir, e.g.
+// "../objects/car/car.gif" becomes "objects/car/car"
+fn asset_key(map_dir: &Path, source: &str) -> error::Result<String> {
+  let mut path = PathBuf::new();
+  for component in map_dir.join(source).components() {
+    match component {
+      Component::ParentDir => {
+        path.pop();
+      },
+      Component::CurDir => (),
+      component => path.push(component.as_os_str()),
+    }
+  }
+  path.set_extension("");
+
+  let components: Vec<String> = path.components()
+    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+    .collect();
+  match components.iter().rposition(|component| component == "assets") {
+    Some(index) => Ok(components[index + 1..].join("/")),
+    None => Err(error::Error::from(format!("Image {} is not in the assets folder", source))),
+  }
+}
+
+fn name_or(name: &str, default: &str) -> String {
+  if name.is_empty() {
+    String::from(default)
+  } else {
+    name.to_owned()
+  }
+}
+
+fn string_property(properties: &tiled::Properties, name: &str) -> Option<String> {
+  match properties.get(name) {
+    Some(&tiled::PropertyValue::StringValue(ref value)) => Some(value.to_owned()),
+    _ => None,
+  }
+}
+
+fn required_property(
+  properties: &tiled::Properties,
+  name: &str,
+  object: &str,
+) -> error::Result<String> {
+  string_property(properties, name).ok_or_else(|| {
+    error::Error::from(format!("{} is missing the {} property", object, name))
+  })
+}
+
+fn float_property(properties: &tiled::Properties, name: &str) -> Option<f64> {
+  match properties.get(name) {
+    Some(&tiled::PropertyValue::FloatValue(value)) => Some(f64::from(value)),
+    Some(&tiled::PropertyValue::IntValue(value)) => Some(f64::from(value)),
+    _ => None,
+  }
+}
+
+fn bool_property(properties: &tiled::Properties, name: &str) -> Option<bool> {
+  match properties.get(name) {
+    Some(&tiled::PropertyValue::BoolValue(value)) => Some(value),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use assets;
+  use sound;
+  use validate;
+
+  const SAMPLE_MAP: &str = "assets/levels/sample.tmx";
+
+  #[test]
+  fn loads_the_sample_map() {
+    let level = load_level(Path::new(SAMPLE_MAP)).unwrap();
+    assert_eq!(level.hero.name, "hero");
+    assert_eq!(level.detective.width, 12);
+    assert_eq!(level.geometry.len(), 1);
+    assert_eq!(level.music.explore, "background_theme");
+    assert_eq!(level.music.tension, None);
+    assert_eq!(level.music.resolution, Some(String::from("happy_ending")));
+
+    // The tile becomes a static actor, drawn from the bottom of its cell
+    let tile = level.actors.iter().find(|actor| actor.name == "ground_8_4").unwrap();
+    assert_eq!(tile.image, "objects/cairn/cairn");
+    assert_eq!(tile.actor_type, level::ActorType::Static);
+    assert_eq!(tile.position, level::Point { x: 2720.0, y: 1440.0 });
+
+    let body = level.actors.iter().find(|actor| actor.name == "body").unwrap();
+    assert_eq!(body.actor_type, level::ActorType::Clue(true));
+    assert_eq!(body.image, "objects/body/body");
+
+    let images = assets::asset_names().unwrap();
+    let sounds = sound::SoundManifest::from_path_str(sound::MANIFEST_PATH).unwrap();
+    let problems = validate::check_level(&level, &images, &sounds);
+    assert!(problems.is_empty(), "{:?}", problems);
+  }
+
+  #[test]
+  fn rejects_an_object_named_like_a_tile() {
+    let path = Path::new(SAMPLE_MAP);
+    let mut map = tiled::parse_file(path).unwrap();
+    map.object_groups[0].objects[2].name = String::from("ground_8_4");
+    assert!(make_level(&map, path).is_err());
+  }
+}
@@ -0,0 +1,262 @@
+extern crate std;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use assets;
+use campaign;
+use default_actor;
+use error;
+use level;
+use simulation;
+use sound;
+
+/// Check levels without opening a window, printing everything wrong with
+/// them. With no paths, every level of the campaign is checked. Fails if any
+/// level has a problem.
+pub fn run(paths: &[String]) -> error::Result<()> {
+  use error::ResultExt; // chain_err
+  use error_chain::ChainedError; // display_chain
+
+  let paths = if paths.is_empty() {
+    let campaign = campaign::Campaign::from_path_str("assets/campaign.json")
+      .chain_err(|| "Failed to load campaign")?;
+    campaign.steps.into_iter().filter_map(|step| match step {
+      campaign::Step::Level(path) => Some(path),
+      _ => None,
+    }).collect()
+  } else {
+    paths.to_vec()
+  };
+
+  let images = assets::asset_names().chain_err(|| "Failed to list assets")?;
+  let sounds = sound::SoundManifest::from_path_str(sound::MANIFEST_PATH)
+    .chain_err(|| "Failed to load sound manifest")?;
+  let null_assets = assets::load_null_assets().chain_err(|| "Failed to load assets")?;
+
+  // The manifest is shared by every level, so its files are checked once
+  let missing_files = sounds.missing_files(std::path::Path::new(sound::SOUNDS_DIR));
+  for &(ref name, ref file) in missing_files.iter() {
+    println!("{}: sound {} plays missing file {}{}",
+             sound::MANIFEST_PATH, name, sound::SOUNDS_DIR, file);
+  }
+
+  let mut broken = 0;
+  for path in paths.iter() {
+    let problems = match level::Level::from_path_str(path) {
+      Ok(level) => {
+        let mut problems = check_level(&level, &images, &sounds);
+        // Anything else that would stop the level from starting shows up in
+        // its first update
+        if problems.is_empty() {
+          let started = simulation::Simulation::new(level, &null_assets)
+            .and_then(|mut simulation| simulation.step());
+          if let Err(e) = started {
+            problems.push(format!("could not be played: {}", e.display_chain()));
+          }
+        }
+        problems
+      },
+      Err(e) => vec![format!("could not be loaded: {}", e.display_chain())],
+    };
+
+    if problems.is_empty() {
+      println!("{}: OK", path);
+    } else {
+      broken += 1;
+      for problem in problems.iter() {
+        println!("{}: {}", path, problem);
+      }
+    }
+  }
+
+  if !missing_files.is_empty() {
+    Err(error::Error::from(format!("{} sounds in {} have no file",
+                                   missing_files.len(), sound::MANIFEST_PATH)))
+  } else if broken == 0 {
+    Ok(())
+  } else {
+    Err(error::Error::from(format!("{} of {} levels have problems", broken, paths.len())))
+  }
+}
+
+/// Everything wrong with a level that would otherwise only show up while
+/// playing it. images are the names of the assets that exist.
+pub fn check_level(
+  level: &level::Level,
+  images: &BTreeSet<String>,
+  sounds: &sound::SoundManifest,
+) -> Vec<String> {
+  let mut problems = Vec::new();
+
+  // Images
+  let mut image_refs = vec![
+    (&level.hero.name, &level.hero.idle),
+    (&level.hero.name, &level.hero.ascend),
+    (&level.detective.name, &level.detective.idle),
+    (&level.detective.name, &level.detective.walk),
+    (&level.detective.name, &level.detective.clue),
+  ];
+  if let Some(ref killer) = level.killer {
+    image_refs.extend(vec![
+      (&killer.name, &killer.idle),
+      (&killer.name, &killer.walk),
+      (&killer.name, &killer.work),
+    ]);
+  }
+  image_refs.extend(level.actors.iter().map(|actor| (&actor.name, &actor.image)));
+  for actor in level.actors.iter() {
+    image_refs.extend(actor.states.iter().filter_map(|state| state.image.as_ref())
+      .map(|image| (&actor.name, image)));
+  }
+  for (name, image) in image_refs {
+    if !images.contains(image) {
+      problems.push(format!("{} uses unknown image {}", name, image));
+    }
+  }
+
+  // Sounds
+  for sound in sounds.missing(level) {
+    problems.push(format!("unknown sound {}", sound));
+  }
+
+  // Shapes
+  problems.extend(shape_problems(level));
+
+  // Names
+  let mut counts = BTreeMap::new();
+  let names = level.actors.iter()
+    .map(|actor| &actor.name)
+    .chain(vec![&level.hero.name, &level.detective.name])
+    .chain(level.killer.iter().map(|killer| &killer.name));
+  for name in names {
+    *counts.entry(name.as_str()).or_insert(0) += 1;
+  }
+  for (name, count) in counts.iter() {
+    if *count > 1 {
+      problems.push(format!("{} actors are named {}", count, name));
+    }
+  }
+
+  // States
+  for actor in level.actors.iter() {
+    if let Err(e) = default_actor::check_states(actor) {
+      problems.push(format!("{}: {}", actor.name, e));
+    }
+  }
+
+  // Triggers
+  for trigger in level.triggers.iter() {
+    if !counts.contains_key(trigger.name.as_str()) {
+      problems.push(format!("trigger for unknown actor {}", trigger.name));
+    }
+    for action in trigger.on_hero_interact.iter().chain(trigger.on_detective_interact.iter()) {
+      let target = match *action {
+        level::Action::Show(ref name) |
+        level::Action::Hide(ref name) |
+        level::Action::Activate(ref name) |
+        level::Action::Deactivate(ref name) |
+        level::Action::ToggleActive(ref name) |
+        level::Action::LookAt { ref name, .. } => name,
+        // Sounds were checked above
+        level::Action::PlaySound(_) |
+        level::Action::Say { .. } |
+        level::Action::ShakeCamera { .. } => continue,
+      };
+      if !counts.contains_key(target.as_str()) {
+        problems.push(format!("trigger for {} refers to unknown actor {}", trigger.name, target));
+      }
+    }
+  }
+
+  // Positions
+  let (ref min, ref max) = level.world_bounds;
+  if min.x >= max.x || min.y >= max.y {
+    problems.push(String::from("world_bounds are empty"));
+  }
+  let mut positions = vec![
+    (&level.hero.name, &level.hero.position),
+    (&level.detective.name, &level.detective.position),
+  ];
+  positions.extend(level.killer.iter().map(|killer| (&killer.name, &killer.position)));
+  positions.extend(level.actors.iter().map(|actor| (&actor.name, &actor.position)));
+  for (name, position) in positions {
+    if position.x < min.x || position.x > max.x || position.y < min.y || position.y > max.y {
+      problems.push(format!(
+        "{} at ({}, {}) is outside world_bounds", name, position.x, position.y));
+    }
+  }
+
+  // The killer has to have somewhere to go
+  if let Some(ref killer) = level.killer {
+    if killer.stash < min.x || killer.stash > max.x {
+      problems.push(format!("{}'s stash at {} is outside world_bounds", killer.name, killer.stash));
+    }
+    if killer.speed <= 0.0 {
+      problems.push(format!("{} has no speed", killer.name));
+    }
+  }
+
+  // The detective has to have something to find
+  let macguffins: Vec<&str> = level.actors.iter()
+    .filter(|actor| actor.actor_type == level::ActorType::Clue(true))
+    .map(|actor| actor.name.as_str())
+    .collect();
+  match macguffins.len() {
+    0 => problems.push(String::from("no actor is the macguffin (Clue(true))")),
+    1 => {},
+    _ => problems.push(format!("more than one macguffin: {}", macguffins.join(", "))),
+  }
+
+  problems
+}
+
+// Polygons with fewer than 3 points, which can't make a collider
+fn shape_problems(level: &level::Level) -> Vec<String> {
+  let mut problems = Vec::new();
+  let mut shapes = vec![
+    (&level.hero.name, level.hero.shape.as_ref()),
+    (&level.detective.name, level.detective.shape.as_ref()),
+  ];
+  shapes.extend(level.killer.iter().map(|killer| (&killer.name, killer.shape.as_ref())));
+  shapes.extend(level.actors.iter().map(|actor| (&actor.name, actor.shape.as_ref())));
+  for (name, shape) in shapes {
+    if let Some(&level::Shape::Polygon(ref points)) = shape {
+      if points.len() < 3 {
+        problems.push(format!("{} has a polygon with only {} points", name, points.len()));
+      }
+    }
+  }
+  for (index, geometry) in level.geometry.iter().enumerate() {
+    if let level::Shape::Polygon(ref points) = geometry.shape {
+      if points.len() < 3 {
+        problems.push(format!("geometry {} has a polygon with only {} points", index, points.len()));
+      }
+    }
+  }
+  problems
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn polygons_with_fewer_than_three_points_are_problems() {
+    let mut level = level::Level::from_path_str("assets/levels/sample.json").unwrap();
+    let images = assets::asset_names().unwrap();
+    let sounds = sound::SoundManifest::from_path_str(sound::MANIFEST_PATH).unwrap();
+    assert!(check_level(&level, &images, &sounds).is_empty());
+
+    let line = vec![level::Point { x: 0.0, y: 0.0 }, level::Point { x: 10.0, y: 0.0 }];
+    level.actors[0].shape = Some(level::Shape::Polygon(line.clone()));
+    level.geometry.push(level::Geometry {
+      position: level::Point { x: 0.0, y: 0.0 },
+      shape: level::Shape::Polygon(Vec::new()),
+    });
+    let problems = check_level(&level, &images, &sounds);
+    assert_eq!(problems, vec![
+      format!("{} has a polygon with only 2 points", level.actors[0].name),
+      String::from("geometry 0 has a polygon with only 0 points"),
+    ]);
+  }
+}
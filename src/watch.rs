@@ -0,0 +1,91 @@
+extern crate std;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// How often watched files are checked, in seconds.
+const POLL_INTERVAL: f64 = 0.5;
+
+/// Notices when watched files change by polling their modification times, so
+/// it works the same on every platform.
+pub struct Watcher {
+  files: Vec<PathBuf>,
+  dirs: Vec<PathBuf>, // watched recursively
+  modified: BTreeMap<PathBuf, SystemTime>,
+  elapsed: f64, // seconds since the last poll
+}
+
+impl Watcher {
+  pub fn new() -> Watcher {
+    Watcher {
+      files: Vec::new(),
+      dirs: Vec::new(),
+      modified: BTreeMap::new(),
+      elapsed: 0.0,
+    }
+  }
+
+  /// Watch the file at path. Files that exist when they're first watched
+  /// aren't reported as changed.
+  pub fn watch_file(&mut self, path: &Path) {
+    if self.files.iter().any(|file| file == path) {
+      return;
+    }
+    self.files.push(path.to_owned());
+    self.scan();
+  }
+
+  /// Watch every file under dir, including files added later.
+  pub fn watch_dir(&mut self, dir: &Path) {
+    self.dirs.push(dir.to_owned());
+    self.scan();
+  }
+
+  /// Advance time by dt seconds, and every POLL_INTERVAL return the files
+  /// that changed or appeared since the last poll.
+  pub fn poll(&mut self, dt: f64) -> Vec<PathBuf> {
+    self.elapsed += dt;
+    if self.elapsed < POLL_INTERVAL {
+      return Vec::new();
+    }
+    self.elapsed = 0.0;
+    self.scan()
+  }
+
+  // Record the modification time of every watched file, returning the files
+  // whose time changed
+  fn scan(&mut self) -> Vec<PathBuf> {
+    let mut paths = self.files.clone();
+    for dir in self.dirs.iter() {
+      list_files(dir, &mut paths);
+    }
+
+    let mut changed = Vec::new();
+    for path in paths {
+      let modified = match std::fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified,
+        Err(_) => continue, // e.g. removed, or in the middle of being saved
+      };
+      if self.modified.insert(path.clone(), modified) != Some(modified) {
+        changed.push(path);
+      }
+    }
+    changed
+  }
+}
+
+fn list_files(dir: &Path, paths: &mut Vec<PathBuf>) {
+  let entries = match dir.read_dir() {
+    Ok(entries) => entries,
+    Err(_) => return,
+  };
+  for entry in entries.filter_map(|entry| entry.ok()) {
+    let path = entry.path();
+    if path.is_dir() {
+      list_files(&path, paths);
+    } else {
+      paths.push(path);
+    }
+  }
+}